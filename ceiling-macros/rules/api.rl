@@ -0,0 +1,2 @@
+main = pub 2 requests every 2 seconds for { ip + route };
+burst = 3 requests every 2 minutes for { ip };
@@ -1,4 +1,5 @@
-use syn::{parse::ParseStream, parse::Peek, Ident, LitInt, Result, Token};
+use proc_macro2::Span;
+use syn::{ext::IdentExt, parse::ParseStream, parse::Peek, Ident, LitInt, Result, Token};
 
 pub fn expected_ident(input: &mut ParseStream, ident: &str) -> Result<()> {
     let lookahead = input.lookahead1();
@@ -13,23 +14,24 @@ pub fn expected_ident(input: &mut ParseStream, ident: &str) -> Result<()> {
     }
 }
 
-pub fn expected_ident_or_nothing(input: &mut ParseStream, ident: &str) -> Result<bool> {
-    let lookahead = input.lookahead1();
-    if lookahead.peek(Ident) {
-        let i = input.parse::<Ident>()?;
-        if i != ident {
-            return Err(syn::Error::new(i.span(), format!("expected '{}'", ident)));
-        }
-        Ok(true)
-    } else {
-        Ok(false)
-    }
-}
-
+/// Parses an integer, optionally suffixed with `k` or `m` to multiply it by a thousand or a million.
 pub fn expected_int(input: &mut ParseStream) -> Result<u32> {
     let lookahead = input.lookahead1();
     if lookahead.peek(LitInt) {
-        Ok(input.parse::<LitInt>()?.base10_parse::<u32>()?)
+        let lit = input.parse::<LitInt>()?;
+        let value = lit.base10_parse::<u32>()?;
+        let multiplier = match lit.suffix() {
+            "" => 1,
+            "k" => 1_000,
+            "m" => 1_000_000,
+            _ => {
+                return Err(syn::Error::new(
+                    lit.span(),
+                    "expected a suffix of 'k' or 'm'",
+                ))
+            },
+        };
+        checked_mul(value, multiplier, lit.span(), "number")
     } else {
         Err(lookahead.error())
     }
@@ -40,24 +42,40 @@ pub fn expected_duration(input: &mut ParseStream) -> Result<u32> {
     let lookahead = input.lookahead1();
     let duration = if lookahead.peek(Ident) {
         let ident = input.parse::<Ident>()?;
-        match ident.to_string().as_str() {
-            "second" | "seconds" => duration,
-            "minute" | "minutes" => duration * 60,
-            "hour" | "hours" => duration * 60 * 60,
-            "day" | "days" => duration * 60 * 60 * 24,
+        let multiplier = match ident.to_string().as_str() {
+            "second" | "seconds" => 1,
+            "minute" | "minutes" => 60,
+            "hour" | "hours" => 60 * 60,
+            "day" | "days" => 60 * 60 * 24,
             _ => {
                 return Err(syn::Error::new(
                     ident.span(),
                     "expected 'seconds', 'minutes', 'hours', or 'days'",
                 ))
             },
-        }
+        };
+        checked_mul(duration, multiplier, ident.span(), "duration in seconds")?
     } else {
         return Err(lookahead.error());
     };
     Ok(duration)
 }
 
+/// Multiplies a parsed number by a unit, the product is computed in `u64` so it can't overflow and
+/// is an error at the span if it doesn't fit in a `u32`, instead of silently wrapping.
+pub fn checked_mul(value: u32, multiplier: u32, span: Span, what: &str) -> Result<u32> {
+    let product = value as u64 * multiplier as u64;
+    u32::try_from(product).map_err(|_| {
+        syn::Error::new(
+            span,
+            format!(
+                "{what} too large, the maximum is {}, found {product}",
+                u32::MAX
+            ),
+        )
+    })
+}
+
 pub fn expected_token<T: Peek>(input: &mut ParseStream, token: T) -> Result<()> {
     let lookahead = input.lookahead1();
     if lookahead.peek(token) {
@@ -71,10 +89,20 @@ pub fn expected_token_or_nothing<T: Peek>(input: &mut ParseStream, token: T) ->
     input.peek(token)
 }
 
+/// Parses an identifier that's used to name items in the generated code.
+/// Raw identifiers are rejected since the generated names are built by adding to them.
 pub fn expected_arbitrary_ident(input: &mut ParseStream) -> Result<String> {
     let lookahead = input.lookahead1();
     if lookahead.peek(Ident) {
-        Ok(input.parse::<Ident>()?.to_string())
+        let ident = input.parse::<Ident>()?;
+        let name = ident.to_string();
+        if name.starts_with("r#") {
+            return Err(syn::Error::new(
+                ident.span(),
+                "raw identifiers aren't supported",
+            ));
+        }
+        Ok(name)
     } else {
         Err(lookahead.error())
     }
@@ -82,13 +110,23 @@ pub fn expected_arbitrary_ident(input: &mut ParseStream) -> Result<String> {
 
 pub fn expected_path(input: &mut ParseStream) -> Result<String> {
     let mut path = String::new();
+    // an ident can only start the path or follow a `::`, so the path ends before any options after it
+    let mut segment = true;
     loop {
         let lookahead = input.lookahead1();
         if lookahead.peek(Token![::]) {
             path.push_str("::");
             input.parse::<Token![::]>()?;
-        } else if lookahead.peek(Ident) {
+            segment = true;
+        } else if segment && lookahead.peek(Ident) {
             path.push_str(&input.parse::<Ident>()?.to_string());
+            segment = false;
+        } else if segment
+            && (input.peek(Token![crate]) || input.peek(Token![self]) || input.peek(Token![super]))
+        {
+            // keywords aren't idents, but paths relative to the invoking crate start with them
+            path.push_str(&input.call(Ident::parse_any)?.to_string());
+            segment = false;
         } else {
             break;
         }
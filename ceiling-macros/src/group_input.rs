@@ -1,18 +1,28 @@
 use syn::{
-    braced,
+    braced, parenthesized,
     parse::{Parse, ParseStream},
-    LitStr, Result, Token,
+    LitStr, Path, Result, Token, Visibility,
 };
 
-use crate::generic_input::expected_arbitrary_ident;
+use crate::generic_input::{expected_arbitrary_ident, expected_token};
 
 pub struct GroupInput {
     pub name: String,
     pub groups: Vec<Vec<String>>,
+    /// The visibility of the constant if the groups are generated as a constant instead of a function.
+    pub constant: Option<Visibility>,
 }
 
 impl Parse for GroupInput {
     fn parse(mut input: ParseStream) -> Result<Self> {
+        let constant = if input.peek(Token![pub]) || input.peek(Token![const]) {
+            let visibility = input.parse::<Visibility>()?;
+            expected_token(&mut input, Token![const])?;
+            input.parse::<Token![const]>()?;
+            Some(visibility)
+        } else {
+            None
+        };
         let name = expected_arbitrary_ident(&mut input)?;
         let stream;
         braced!(stream in input);
@@ -36,6 +46,34 @@ impl Parse for GroupInput {
                 return Err(lookahead.error());
             }
         }
-        Ok(Self { name, groups })
+        Ok(Self {
+            name,
+            groups,
+            constant,
+        })
+    }
+}
+
+pub struct GroupFnInput {
+    pub visibility: Visibility,
+    pub name: String,
+    pub groups: Vec<Path>,
+}
+
+impl Parse for GroupFnInput {
+    fn parse(mut input: ParseStream) -> Result<Self> {
+        let visibility = input.parse::<Visibility>()?;
+        let name = expected_arbitrary_ident(&mut input)?;
+        let stream;
+        parenthesized!(stream in input);
+        let groups = stream
+            .parse_terminated::<_, Token![,]>(Path::parse)?
+            .into_iter()
+            .collect::<Vec<_>>();
+        Ok(Self {
+            visibility,
+            name,
+            groups,
+        })
     }
 }
@@ -4,12 +4,13 @@
 mod generic_input;
 mod group_input;
 mod rate_limiter_input;
+mod rule_impl;
 
-use group_input::GroupInput;
+use group_input::{GroupFnInput, GroupInput};
 use proc_macro2::TokenStream;
 use quote::quote;
-use rand::distributions::DistString;
-use rate_limiter_input::{RateLimiterInput, Rule};
+use rate_limiter_input::{HeaderNames, HeaderPart, OnEmpty, RateLimiterInput, Rounding, Rule};
+use rule_impl::{impl_credit, impl_preload, impl_remaining, impl_rule, window_limits, StoreErrors};
 use syn::{parse_macro_input, Ident, LitStr, Path, Result};
 
 /// This macro is the entrypoint for creating rate limiting rules with ceiling.
@@ -17,10 +18,11 @@ use syn::{parse_macro_input, Ident, LitStr, Path, Result};
 ///
 /// # Example
 /// ```
+/// # use ceiling::{AsyncStore, TokioStore as MyAsyncStore};
 /// ceiling::rate_limiter! {
-///     // takes in three inputs named `ip`, `route`, and `method`
+///     // takes in four inputs named `ip`, `forwarded_ip`, `route`, and `method`
 ///     // they must implement `std::fmt::Display` so they can be coerced into strings as needed
-///     ip, route, method in {
+///     ip, forwarded_ip, route, method in {
 ///         // the following creates a public (detailed information is meant to be returned to the client) rate limiting rule named main with a limit of 2 requests every 2 seconds (interval) for the key created by concatenating the ip, route, and method inputs together
 ///         // when the rate limit is hit, the timeout specified is 3 seconds from the time of the request that emptied the bucket
 ///         main = pub 2 requests every 2 seconds for { ip + route + method } timeout 3 seconds;
@@ -28,32 +30,429 @@ use syn::{parse_macro_input, Ident, LitStr, Path, Result};
 ///         // this one crates a private rate limiting rule with a limit of 3 request every 2 minutes (interval) for the key ip + route
 ///         // since timeout is not specified, the bucket will reset when the interval is up
 ///         burst = 3 requests every 2 minutes for { ip + route };
+///         // large numbers can be suffixed with `k` or `m` to multiply them by a thousand or a million
+///         // `N/unit` is shorthand for `N requests every 1 unit`, with the units `s`, `min`, `h`, and `d`, e.g. `100/min`
+///         daily = 10k requests every 1 day for { ip };
+///         // `coalesce(...)` in a key takes the first of its inputs that isn't empty, e.g. to prefer a proxy's client IP header
+///         proxied = 10 requests every 1 minute for { coalesce(forwarded_ip, ip) + route };
+///         // `for global` instead of a key limits every request together in a single bucket
+///         total = 10k requests every 1 second for global;
+///         // a rule can have several windows in brackets, it's limited if any of them is
+///         // each window takes an optional timeout and has its own bucket, the hit struct reports the state of every window in `api_windows`
+///         api = pub [10 requests every 1 second, 1k requests every 1 hour timeout 2 hours] for { ip };
+///         // naming a rule defined before it instead of the limit inherits its windows, a timeout given after the key overrides the inherited one
+///         // unless the rule inherited from has several windows
+///         api_method = api for { ip + method };
+///         // `template` before the name gives a rule that's only inherited from, it has no store and never limits a request, and its key can be left out
+///         template standard = 100 requests every 1 minute timeout 5 minutes;
+///         by_route = standard for { route };
 ///     // `as RateLimiter` tells the macro to name the generated struct RateLimiter
 ///     // `async` says the following custom store is asynchronous
 ///     // i.e. implements `ceiling::AsyncStore` instead of `ceiling::SyncStore`
 ///     // `in crate::MyAsyncStore` tells the macro to use the struct `crate::MyAsyncStore` for the bucket stores
 ///     // specifying a bucket store is not required, if none is provided it will use `ceiling::DefaultStore`
+///     // with the crate feature `tokio` enabled, `ceiling::TokioStore` is an in-process asynchronous store that yields while waiting on a bucket
 ///     } as RateLimiter async in crate::MyAsyncStore
 /// }
+/// # fn main() {}
 /// ```
 /// ```
+/// # use ceiling::SyncStore;
+/// // a rate limiter without inputs starts with `in`, every rule is then global and `hit` takes no arguments
+/// ceiling::rate_limiter! {
+///     in {
+///         total = 100 requests every 1 second for global;
+///     } as ServiceRateLimiter
+/// }
+/// let (limited, hit) = ServiceRateLimiter::new().hit();
+/// ```
+/// ```
+/// # use ceiling::SyncStore;
+/// // the rules can be read from a file with `include!`, the path is relative to the crate's root (the directory containing `Cargo.toml`)
+/// // the file contains the rule statements that would otherwise go inside the braces, and changes to it rebuild the rate limiter
+/// ceiling::rate_limiter! {
+///     ip, route in include!("rules/api.rl") as RateLimiter
+/// }
+/// ```
+/// ```
+/// # use ceiling::SyncStore;
+/// // the store can instead be left up to the caller by naming a type parameter after the limiter
+/// // if a store path is also given with `in` it is used as the default for the type parameter
+/// ceiling::rate_limiter! {
+///     ip in {
+///         main = 2 requests every 2 seconds for { ip };
+///     } as RateLimiter<S>
+/// }
+/// let rate_limiter = RateLimiter::<ceiling::DefaultStore>::new();
+/// ```
+/// ```
+/// # use ceiling::SyncStore;
+/// // the generated structs are `pub` by default, a visibility can be given before the name to restrict them
+/// // the hit struct has the same visibility as the rate limiter
+/// ceiling::rate_limiter! {
+///     ip in {
+///         main = 2 requests every 2 seconds for { ip };
+///     } as pub(crate) RateLimiter
+/// }
+/// ```
+/// ```
+/// # use ceiling::SyncStore;
+/// # ceiling::rate_limiter! {
+/// #     ip, route in {
+/// #         main = pub 2 requests every 2 seconds for { ip + route };
+/// #         burst = 3 requests every 2 minutes for { ip + route };
+/// #         leaky = 10 requests every 1 second for { ip } leaky;
+/// #     } as RateLimiter
+/// # }
+/// # struct Config { expose_rate_limits: bool }
+/// # struct Tiers;
+/// # impl Tiers { fn capacity(&self, _: &str) -> (u32, u32) { (10, 1) } }
+/// # struct Flags { burst_limit_enabled: bool }
+/// # fn main() -> Result<(), ceiling::ConfigError> {
+/// # let (config, tiers, flags) = (Config { expose_rate_limits: true }, Tiers, Flags { burst_limit_enabled: true });
+/// // whether a rule is public can be overridden when the rate limiter is created, e.g. from runtime configuration
+/// // `with_public` overrides every rule and `with_rule_public` a single rule, counting rules are always private
+/// let rate_limiter = RateLimiter::new()
+///     .with_public(config.expose_rate_limits)
+///     .with_rule_public("burst", false);
+/// // the limit, interval, and timeout of a window can be overridden the same way, the window being its index in the brackets or zero
+/// // this returns an error for unknown rules or limits that aren't valid for the rule, e.g. an interval of zero
+/// let rate_limiter = RateLimiter::new().with_rule_limits("burst", 0, ceiling::Rule::new(5, 120, 120))?;
+/// // a leaky rule with a single window can resolve the capacity and refill rate (requests per interval) of each bucket from its key instead,
+/// // e.g. for tiered quotas within one rule, the resolver is called when a bucket is first hit and its result is cached until the bucket could refill
+/// let rate_limiter = RateLimiter::new().with_capacity_resolver("leaky", move |key| tiers.capacity(key))?;
+/// // a rule can be turned off and on while the rate limiter is in use, e.g. from a feature flag, a disabled rule never limits a request
+/// // clones share whether rules are enabled, so this applies to every clone of the rate limiter
+/// rate_limiter.set_rule_enabled("burst", flags.burst_limit_enabled);
+/// # Ok(())
+/// # }
+/// ```
+/// ```
+/// # use ceiling::SyncStore;
+/// // the names of the headers returned by `to_headers` can be changed for individual attributes
+/// // any attribute that isn't listed keeps its default header name
+/// ceiling::rate_limiter! {
+///     ip in {
+///         main = pub 2 requests every 2 seconds for { ip };
+///     } as RateLimiter headers { remaining = "X-Quota-Left", reset = "X-Quota-Reset" }
+/// }
+/// ```
+/// ```
+/// # use ceiling::{DefaultStore as MyRemoteStore, SyncStore};
+/// // `try_hit` uses the fallible store methods and returns any store error, e.g. when a remote store is unreachable
+/// // `on_error allow` fails open, treating a rule as not limited when its store errors, while `on_error deny` fails closed
+/// // with a policy `try_hit` handles store errors itself and always returns `Ok`
+/// ceiling::rate_limiter! {
+///     ip in {
+///         main = 2 requests every 2 seconds for { ip };
+///     } as RateLimiter in crate::MyRemoteStore on_error allow
+/// }
+/// # fn main() {}
+/// ```
+/// ```
+/// # use ceiling::SyncStore;
+/// // a part of a key whose inputs are all empty is replaced by `<empty>`, so requests missing it don't silently share buckets with keys built differently
+/// // `on_empty "placeholder"` replaces it with another placeholder, `on_empty allow` leaves it empty,
+/// // and `on_empty deny` makes the hit methods limit the request by the rule without touching its buckets, reporting the rule as exhausted
+/// ceiling::rate_limiter! {
+///     ip, route in {
+///         main = 2 requests every 2 seconds for { ip + route };
+///     } as RateLimiter on_empty deny
+/// }
+/// ```
+/// ```
+/// # use ceiling::SyncStore;
+/// # fn main() -> Result<(), ceiling::StoreError> {
+/// // the seconds until a bucket resets are rounded down by default, so a client waiting exactly that long can retry
+/// // just before the bucket resets and still be limited, `rounding up` includes the rest of the current second
+/// // this applies to "reset_after" in the headers and serialized output and to `retry_after` in the error body
+/// ceiling::rate_limiter! {
+///     ip in {
+///         main = 2 requests every 2 seconds for { ip };
+///     } as RateLimiter rounding up
+/// }
+/// # let rate_limiter = RateLimiter::new();
+/// let (rate_limited, hit) = rate_limiter.try_hit("1.1.1.1")?;
+/// # Ok(())
+/// # }
+/// ```
+/// ```
+/// # use ceiling::SyncStore;
+/// // the parts of a key are joined with `+` by default, so inputs that can contain a `+` can make different keys collide
+/// // `separator` joins them with a string that can't appear in the inputs instead, it can't contain `#`
+/// ceiling::rate_limiter! {
+///     user, query in {
+///         main = 2 requests every 2 seconds for { user + query };
+///     } as RateLimiter separator "::"
+/// }
+/// ```
+/// ```
+/// # use ceiling::SyncStore;
+/// // clones share the stores through an `Arc` by default, `single_threaded` shares them through an `Rc` instead
+/// // for rate limiters that never leave a thread, e.g. in a single threaded runtime, the rate limiter then isn't `Send` or `Sync`
+/// // the stores still implement `ceiling::SyncStore` or `ceiling::AsyncStore`, and there's no `spawn_pruner` since it can't share the stores
+/// ceiling::rate_limiter! {
+///     ip in {
+///         main = 2 requests every 2 seconds for { ip };
+///     } as RateLimiter single_threaded
+/// }
+/// ```
+/// ```
+/// # use ceiling::{DefaultStore as MyStore, SyncStore};
+/// // `erased` keeps the stores behind `ceiling::ErasedStore`, a boxed `ceiling::SyncStore` whose lock is boxed too, instead of
+/// // making the rate limiter generic over its store, so there's a single copy of the generated code for every store it's used with
+/// // the store given with `in` is used by `new`, `ceiling::DefaultStore` if none is given, and `with_store` replaces it
+/// // erased rate limiters can't be generic or `async`
+/// ceiling::rate_limiter! {
+///     ip in {
+///         main = 2 requests every 2 seconds for { ip };
+///     } as RateLimiter erased
+/// }
+/// # fn main() {
+/// let rate_limiter = RateLimiter::new().with_store::<crate::MyStore>();
+/// # }
+/// ```
+/// ```
+/// # use ceiling::SyncStore;
+/// # struct Request { ip: String, route: String }
+/// # let request = Request { ip: "1.1.1.1".into(), route: "/example".into() };
+/// // inputs can be read from the fields of a context instead of passed one by one, the type of the context is given with `context`
+/// // this generates `hit_from`, taking a reference to the context, the other methods still take the inputs, named after the fields
+/// ceiling::rate_limiter! {
+///     req.ip, req.route in {
+///         main = 2 requests every 2 seconds for { ip + route };
+///     } as RateLimiter context Request
+/// }
+/// # let rate_limiter = RateLimiter::new();
+/// let (rate_limited, hit) = rate_limiter.hit_from(&request);
+/// ```
+/// ```
+/// # use ceiling::SyncStore;
+/// # struct Request(std::collections::HashMap<String, String>);
+/// # impl Request { fn headers(&self) -> &std::collections::HashMap<String, String> { &self.0 } }
+/// # let (ip, request) = ("1.1.1.1", Request(Default::default()));
+/// // `header("Name")` in a key reads the header from a `ceiling::HeaderLookup`, e.g. a `http::HeaderMap` with the crate feature `http` enabled,
+/// // which every method taking the inputs then takes after them, this doesn't implement the traits taking only the inputs, e.g. `ceiling::DynLimiter`
+/// // a request without the header is limited by the rule without touching its buckets, which is reported as exhausted, unless a fallback is given after the name
+/// ceiling::rate_limiter! {
+///     ip in {
+///         keyed = pub 1k requests every 1 hour for { header("X-Api-Key") };
+///         tenant = 100 requests every 1 minute for { ip + header("X-Tenant", "none") };
+///     } as RateLimiter
+/// }
+/// # let rate_limiter = RateLimiter::new();
+/// let (rate_limited, hit) = rate_limiter.hit(ip, request.headers());
+/// ```
+/// ```no_run
+/// # use ceiling::{AsyncStore, SyncStore};
+/// # ceiling::rate_limiter! {
+/// #     ip, route, method in {
+/// #         main = pub 2 requests every 2 seconds for { ip + route + method } timeout 3 seconds;
+/// #         burst = 3 requests every 2 minutes for { ip + route };
+/// #     } as RateLimiter async in ceiling::TokioStore
+/// # }
+/// # ceiling::rate_limiter! {
+/// #     ip, route, method in {
+/// #         main = 2 requests every 2 seconds for { ip + route + method };
+/// #     } as SyncRateLimiter
+/// # }
+/// # struct Response;
+/// # impl Response {
+/// #     fn header(&mut self, _: &str, _: String) {}
+/// #     fn json(&mut self, _: &ceiling::RateLimitError) {}
+/// # }
+/// # fn validate<T>(_: &T) -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// # let sync_rate_limiter = SyncRateLimiter::new();
+/// # let (request, mut response, forwarded_for, peer_ip) = ((), Response, "1.1.1.1", "1.1.1.1");
 /// let rate_limiter = RateLimiter::new();
 /// // "hits" the rate limiter, what would happen when someone, for example, makes a request
-/// // the return result is a `bool` (`rate_limiter`) of whether the request is being rate limiter (`true` means it is and should not continue)
+/// // the return result is a `bool` (`rate_limited`) of whether the request is being rate limited (`true` means it is and should not continue)
 /// // and a `RateLimiterHit` (the name of the struct is rate limiter name + "Hit") struct containing detailed metadata on the state of all the rate limiting rules
 /// // rules can be found by using the name of the rule, i.e. `hit.main` corresponds to the rule named `main`
-/// // the value of a rule's metadata is a tuple of type `(u32, u64, bool, String)` corresponding to the requests remaining, the reset time, whether the rule is public or not, and the key of the bucket
-/// let (rate_limiter, hit) = rate_limiter.hit("1.1.1.1", "/example", "GET").await;
+/// // the value of a rule's metadata is a tuple of type `(u32, u64, bool, String, u32, u32, u32)` corresponding to the requests remaining, the reset time, whether the rule is public or not, the key of the bucket,
+/// // and the limit, interval, and timeout of the rule (the most restrictive window's if it has several)
+/// let (rate_limited, hit) = rate_limiter.hit("1.1.1.1", "/example", "GET").await;
+/// // `hit_fast` stops at the first rule that trips, skipping the stores of the rules after it
+/// // it only returns whether the request is limited and the name of the rule that limited it, so there are no headers
+/// let (rate_limited, rule) = rate_limiter.hit_fast("1.1.1.1", "/example", "GET").await;
+/// // rules can also be looked up by name at runtime, returning `None` for unknown rules
+/// let main = hit.get("main");
+/// // `config` returns the inputs and rules of the rate limiter, with the crate feature `serde` enabled it can be serialized
+/// // e.g. to check the configuration into version control, the rules as given in the macro are also in the `RULES` constant
+/// let config = rate_limiter.config();
+/// // inputs are used in keys verbatim, `ceiling::normalize_route` lowercases a route and strips its query string and extra slashes
+/// // so equivalent paths share a bucket, `ceiling::normalize_route_strip_id` also strips a trailing numeric ID
+/// let (rate_limited, hit) = rate_limiter.hit("1.1.1.1", ceiling::normalize_route("/Example/"), "GET").await;
+/// // behind reverse proxies `ceiling::client_ip_from_forwarded` takes the client's address from `X-Forwarded-For`, given the number of trusted proxies
+/// let ip = ceiling::client_ip_from_forwarded(forwarded_for, 1).unwrap_or_else(|| peer_ip.to_string());
+/// // `keys` builds the key of each rule from the inputs, and `hit_with_keys` hits the rules with keys given in the order of the rules
+/// // e.g. to build the keys ahead of the hit, they're moved into the hit, and this isn't available for rate limiters with distinct rules or costs taken from inputs
+/// let keys = rate_limiter.keys("1.1.1.1", "/example", "GET");
+/// let (limited, hit) = rate_limiter.hit_with_keys(keys).await;
+/// // `hit_dyn` takes the inputs as a slice in the order of the parameters of `hit`, e.g. for middleware shared by rate limiters with different inputs
+/// // it returns a `ceiling::InputCountError` if the number of inputs is wrong
+/// let (limited, hit) = rate_limiter.hit_dyn(&[&"1.1.1.1", &"/example", &"GET"]).await?;
+/// // `credit` gives requests back to every rule's buckets, up to their limits, e.g. to refund a request when the operation it was for fails
+/// rate_limiter.credit("1.1.1.1", "/example", "GET", 1).await;
+/// // with a synchronous store, `hit_guarded` also returns a `ceiling::HitGuard` that credits each rule the requests taken when it's dropped,
+/// // unless `commit` is called, e.g. to give the request back when the handler bails out before doing any work
+/// let (limited, hit, guard) = sync_rate_limiter.hit_guarded("1.1.1.1", "/example", "GET");
+/// validate(&request)?;
+/// guard.commit();
+/// // rate limiters with a synchronous store implement the object-safe `ceiling::DynLimiter`, so different rate limiters can be stored together
+/// // its `hit_dyn` takes the inputs as strings and returns the state of each rule as a `ceiling::RuleState`
+/// // `with_on_first_hit` sets a callback taking the name of a rule and a key, invoked when a hit creates a bucket that wasn't in the rule's store
+/// // e.g. to count the clients active in each window, buckets that reset without being pruned don't count as new
+/// let rate_limiter = RateLimiter::new().with_on_first_hit(|rule, key| println!("new client of {rule}: {key}"));
+/// // `with_decision_sink` sets a function called synchronously with a `ceiling::Decision` for every rule on each hit,
+/// // carrying the rule, the key, the requests remaining, whether the rule limited the hit, and the time, e.g. for logging decisions to a file
+/// let rate_limiter = RateLimiter::new().with_decision_sink(|decision| println!("{decision:?}"));
+/// // `with_memory_budget` caps the buckets kept across every rule's store, before each hit the store holding the most buckets
+/// // evicts the excess, starting with the buckets that reset soonest, stores can override `bucket_count` and `evict` to make this cheaper
+/// let rate_limiter = RateLimiter::new().with_memory_budget(100_000);
+/// // `with_clock_skew` keeps buckets for a few seconds after their reset before they expire, for stores shared by instances whose clocks differ,
+/// // this extends the effective window of fixed window rules by up to the tolerance
+/// let rate_limiter = RateLimiter::new().with_clock_skew(2);
+/// // each rule also has a `<rule>_remaining` method taking only the inputs in its key
+/// // it reads the requests remaining without hitting the rule, e.g. for showing a quota to a user
+/// let remaining = rate_limiter.burst_remaining("1.1.1.1", "/example").await;
+/// // `clear_buckets` removes the buckets of every rule matching the inputs given, inputs that are `None` match any value
+/// // e.g. to reset a client across every rule when unbanning them, regardless of route and method
+/// rate_limiter.clear_buckets(Some("1.1.1.1"), None, None).await;
+/// // `clear_rule` removes every bucket of a single rule with its store's `clear`, e.g. after retuning its limits, returning whether the rule exists
+/// rate_limiter.clear_rule("burst").await;
+/// // `preload` sets full buckets for known keys of rules, e.g. to warm the stores before a scheduled traffic spike, returning the number set
+/// // a preloaded bucket counts as full and unused until it's hit, buckets already in the store are left alone
+/// rate_limiter.preload([("main", "127.0.0.1+/+GET"), ("burst", "127.0.0.1+/")]).await;
+/// // each hit prunes the stores of the rules it hits, `spawn_pruner` also prunes every store in the background, e.g. when some rules are rarely hit
+/// // it spawns a thread for sync stores or a `tokio` task for async stores (with the crate feature `tokio` enabled), pruning stops when the handle is dropped
+/// let pruner = sync_rate_limiter.spawn_pruner(std::time::Duration::from_secs(60));
 /// // with the crate feature `serde` enabled, the `hit` object implements `serde::Serialize` and can be easily serialized to any format
 /// // the serialized data will only contain the public rules, the various fields can be found below
+/// // `serialize_rules` returns a view of the hit serializing only the public rules named, e.g. the one relevant to the endpoint
 /// // as another option, the hit object has a `to_headers` method that will return a Vec<(&str, String)> corresponding to the header and value
 /// // information on the headers can be found below
+/// // the seconds until reset are counted from `hit.now`, the time of the hit, use `to_headers_at` to count them from another time
 /// let headers = hit.to_headers();
+/// // `to_headers_minimal` returns only the limit, remaining, and reset headers of each public rule, e.g. for every response of a high-traffic API
+/// let headers = hit.to_headers_minimal();
+/// // `to_policy_headers` describes each public rule for humans instead, e.g. `X-RateLimit-Policy: main 2 requests per 2 seconds per ip+route`
+/// let headers = hit.to_policy_headers();
 /// for (header, value) in headers {
 ///     response.header(header, value);
 /// }
+/// // when the request was rate limited, `to_error_body` returns a `ceiling::RateLimitError` with the seconds to wait before retrying
+/// // the rule name and limit are only included for public rules, with the crate feature `serde` enabled it can be serialized as the response body
+/// if let Some(body) = hit.to_error_body() {
+///     response.json(&body);
+/// }
+/// // for logging, `to_flat_map` returns every attribute of every rule, including private ones, keyed by `<rule>.<attribute>`
+/// println!("hit the rate limiter: {:?}", hit.to_flat_map());
+/// # Ok(())
+/// # }
+/// # fn main() {}
+/// ```
+/// The following methods are generated only when the crate invoking the macro has a feature of the same name as the ceiling feature
+/// enabled, since the generated code is compiled as part of that crate, so they aren't compiled here.
+/// ```ignore
+/// // with the crate feature `env` enabled, `from_env` reads the limits from environment variables named after the rule, e.g. with the prefix `API`:
+/// // `API_BURST_LIMIT`, `API_BURST_INTERVAL`, and `API_BURST_TIMEOUT` in seconds, or `API_BURST_0_LIMIT` etc. for rules with several windows
+/// // variables that aren't set fall back to the limits given in the macro, and values that aren't whole numbers are returned as an error
+/// let rate_limiter = RateLimiter::from_env("API")?;
+/// // with the crate feature `clock` enabled, `with_clock` sets a `ceiling::Clock` read for the current time instead of the system time,
+/// // e.g. a `ceiling::CoarseClock` caching the current second so a hit is an atomic load instead of a system call
+/// let rate_limiter = RateLimiter::new().with_clock(ceiling::CoarseClock::new());
+/// // with the crate feature `events` enabled, `subscribe` returns a `tokio` broadcast receiver of a `ceiling::BucketEvent` for each window hit,
+/// // carrying the rule, the key, the requests remaining, and whether the hit took from the bucket, started a new window, or tripped it
+/// // nothing is built or sent while there are no receivers, e.g. for a live dashboard
+/// let mut events = rate_limiter.subscribe();
+/// // with the crate feature `http` enabled, `to_response_parts` returns the `http::StatusCode` and `http::HeaderMap` for the response
+/// // the status is `429 Too Many Requests` if the request was limited, otherwise `200 OK` and the request should be handled as normal
+/// let (status, headers) = hit.to_response_parts(rate_limited);
+/// // with the crate feature `prometheus` enabled, each rule counts the requests it allowed and limited
+/// // `prometheus_metrics` formats the counters and the number of buckets per rule for a `/metrics` endpoint
+/// let metrics = rate_limiter.prometheus_metrics().await;
+/// // with the crate feature `otel` enabled, the hit methods set the outcome as attributes of the active OpenTelemetry span,
+/// // e.g. `http.rate_limit.limited` and `http.rate_limit.remaining`, the attributes are listed in `ceiling::otel::record_hit`
+/// // with the crate feature `tonic` enabled, rate limiters with a synchronous store and two inputs implement `ceiling::tonic::GrpcLimiter`
+/// // the first input is the peer address and the second the method path, so they can be used with `ceiling::tonic::RateLimitInterceptor`
+/// let interceptor = ceiling::tonic::RateLimitInterceptor::new(GrpcRateLimiter::new());
+/// // with the crate feature `governor-compat` enabled, rate limiters with a synchronous store and one input can be wrapped for code written against `governor`
+/// // `check_key` returns `Err(NotUntil)` when limited, with the earliest time to retry taken from the limited rule that resets last
+/// let limiter = ceiling::governor::KeyedRateLimiter::new(IpRateLimiter::new());
+/// if let Err(not_until) = limiter.check_key(&ip) {
+///     response.retry_after(not_until.wait_time_from(std::time::SystemTime::now()));
+/// }
 /// ```
 ///
+/// ## Rule Clauses
+/// Optional clauses can follow the key of a rule in any order.
+///
+/// | Clause    | Example             | Description                                                                                         |
+/// | --------- | ------------------- | --------------------------------------------------------------------------------------------------- |
+/// | `timeout` | `timeout 3 seconds` | time before the bucket resets after the limit is reached, defaults to the interval                  |
+/// |           | `timeout 2x`        | a timeout can also be a multiple of the interval, written as `2x` or `2 intervals`                  |
+/// | `jitter`  | `jitter 10%`        | moves resets earlier or later by a deterministic per-key amount of up to the percentage of the interval given, at most 100% |
+/// | `cost`    | `cost 5`            | the number of requests each hit takes from the bucket, if fewer remain the bucket is emptied instead |
+/// |           | `cost { page_size }` | the cost can also be parsed from an input on each hit, a value that isn't a whole number costs 1  |
+/// | `hide`    | `hide key`          | omits the key of a public rule from the headers and serialized output                               |
+/// | `leaky`   | `leaky`             | requests leak back into the bucket continuously instead of all at once, so it can't have a timeout or jitter |
+/// | `aligned` | `aligned`           | windows are aligned to multiples of the interval since the epoch, e.g. UTC days, ignoring timeout  |
+/// | `extend`  | `extend on abuse`   | every limited hit restarts the timeout, so a client that keeps retrying stays limited               |
+/// | `tags`    | `tags { tier = "free" }` | labels added to the rule's metrics from `prometheus_metrics` alongside the rule's name         |
+/// | `distinct` | `distinct { route }` | limits the number of distinct values seen per window instead of requests, see below           |
+/// | `reason`  | `reason "quota_exceeded"` | a machine-readable reason for clients when the rule limits a request, see below          |
+/// | `grace`   | `grace 3`           | the first hits on a key without a bucket don't take from it, see below                              |
+/// | `forgive` | `forgive after 1 minute` | shortens the timeout of a limited client that stops sending requests, see below               |
+///
+/// A rule can count requests without ever limiting them by replacing its limit with `count`, e.g. `visits = count every 1 hour for { ip };`.
+/// The hit struct then has a `<rule>_used` method returning the requests counted in the current interval.
+/// Counting rules are private and can't be leaky.
+///
+/// A distinct rule limits how many different values of its `distinct` inputs are seen per window for each key,
+/// e.g. `endpoints = 100 requests every 1 hour for { api_key } distinct { route };` limits each API key to 100 routes an hour.
+/// Only the first hit with each value takes from the bucket, and the values seen are kept as buckets in the rule's store until the window resets,
+/// keyed by the rule's key, a `#`, and the value. Each `#` and `\` in the key is escaped with a `\` by `ceiling::escape_key`, in the store and the hit,
+/// so a key containing `#` can't collide with the value of another key.
+/// Distinct rules can't be counting, leaky, have several windows, or have a cost.
+///
+/// The reason of the limited rule that resets last is returned by the hit struct's `reason` method, included in `to_error_body`,
+/// and sent in the `X-RateLimit-Reason` header, so clients can tell limits apart, e.g. a short burst from a daily quota.
+/// The reason is given even if the rule is private, and counting rules can't have one since they never limit.
+///
+/// A rule with a limit of zero blocks every request, e.g. `blocked = 0 requests every 1 minute for { route };` to temporarily block a route.
+/// Its headers report no requests remaining, a spacing of the whole interval, and a reset that doesn't move until the bucket resets.
+/// Rules with a limit of zero can't be leaky or have a grace.
+///
+/// A rule with a grace lets the given number of hits on a new key through without taking from the bucket,
+/// e.g. to let a client retry its first requests, and the requests remaining never appear to be more than the limit.
+/// The bucket starts with the cost of those hits on top of the limit, with a cost taken from an input the cost of the hit creating it.
+/// The grace is given again once the bucket is pruned, an expired bucket that hasn't been pruned yet resets without it.
+/// Counting, leaky, and distinct rules can't have a grace.
+///
+/// A forgiving rule rewards a limited client for backing off. When a limited client hits the rule again after being quiet
+/// for at least the time given, the rest of its timeout is shortened in proportion to how long it was quiet,
+/// e.g. with `forgive after 1 minute` it's halved after two quiet minutes and cut to a tenth after ten.
+/// The time of the last limited hit is kept in the rule's store in a bucket keyed by the rule's key followed by `#last`,
+/// the key being escaped like a distinct rule's.
+/// Counting, leaky, aligned, distinct, and rules extending on abuse can't forgive.
+///
+/// The reset of a leaky rule is the time the bucket will be full again.
+/// An aligned rule's window ends with its last second, and its reset is reported as the start of the next window, so `1k requests every 1 day for { ip } aligned`
+/// reports midnight UTC as the reset in the headers and the seconds until midnight as the reset after, whatever the rounding.
+///
+/// Clauses apply to every window of a rule with several windows, other than `timeout` which is given for each window instead.
+/// The headers and serialized output of such a rule describe its most restrictive window,
+/// the limited window that resets last or otherwise the window with the fewest requests remaining.
+///
+/// Every rule has its own store, so rules with the same key never share buckets even if their windows differ.
+///
+/// The key of a public rule is visible to clients, so a warning is emitted if it contains an input that looks sensitive
+/// (such as a token, session, or email) unless the rule hides its key.
+/// The warning only covers rules marked `pub` in the macro, a private rule made public with `with_public` or `with_rule_public`
+/// exposes its key without one, so such rules should hide their key if they may be made public at runtime.
+///
 /// ## Headers/Metadata Attributes
 /// | Header                  | Attribute     | Description                                                                                     |
 /// | ----------------------- | ------------- | ----------------------------------------------------------------------------------------------- |
@@ -63,11 +462,15 @@ use syn::{parse_macro_input, Ident, LitStr, Path, Result};
 /// | X-RateLimit-Remaining   | "remaining"   | hits remaining in interval                                                                      |
 /// | X-RateLimit-Reset       | "reset"       | timestamp in seconds when the bucket resets                                                     |
 /// | X-RateLimit-Reset-After | "reset_after" | seconds until bucket resets                                                                     |
+/// | X-RateLimit-Spacing     | "spacing"     | seconds to leave between hits to never be limited, the interval divided by the limit            |
 /// | X-RateLimit-Key         | "key"         | the bucket key, may be shared between routes and therefore useful for client-side rate limiting |
+/// |                         | "public"      | whether the rule is public, always true in the serialized output since it only contains public rules |
+/// | X-RateLimit-Reason      | "reason"      | the reason of the limited rule that resets last, only sent if the request was limited by a rule with a reason |
+/// | X-RateLimit-Policy      | "policy"      | a description of the rule for humans, only returned by `to_policy_headers`                      |
 #[proc_macro]
 pub fn rate_limiter(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     impl_rate_limiter(parse_macro_input!(input as RateLimiterInput))
-        .unwrap()
+        .unwrap_or_else(|e| e.to_compile_error())
         .into()
 }
 
@@ -76,17 +479,216 @@ fn impl_rate_limiter(
         inputs,
         rules,
         name,
+        visibility,
         store,
         async_store,
+        generic,
+        headers,
+        on_error,
+        on_empty,
+        rounding,
+        separator,
+        single_threaded,
+        erased,
+        context,
+        header_parts,
+        include,
     }: RateLimiterInput,
 ) -> Result<TokenStream> {
     let name = syn::parse_str::<syn::Ident>(&name)?;
-    let store = syn::parse_str::<Path>(&store.unwrap_or_else(|| "ceiling::DefaultStore".into()))?;
+    let store_bound = if async_store {
+        quote!(ceiling::AsyncStore)
+    } else {
+        quote!(ceiling::SyncStore)
+    };
+    // the concrete store, or the default of the type parameter, is asserted to implement the store trait matching `async`
+    // so a mismatch is reported at the store instead of as errors awaiting its methods in the generated code
+    let mut asserted_store = None;
+    let (store, struct_generics, impl_generics, type_generics) = match generic {
+        Some(generic) => {
+            let generic = syn::parse_str::<syn::Ident>(&generic)?;
+            let default = match store {
+                Some(store) => {
+                    let store = syn::parse_str::<Path>(&store)?;
+                    asserted_store = Some(quote!(#store));
+                    quote!(= #store)
+                },
+                None => quote!(),
+            };
+            (
+                quote!(#generic),
+                quote!(<#generic: #store_bound #default>),
+                quote!(<#generic: #store_bound>),
+                quote!(<#generic>),
+            )
+        },
+        None => {
+            let store =
+                syn::parse_str::<Path>(&store.unwrap_or_else(|| "ceiling::DefaultStore".into()))?;
+            asserted_store = Some(quote!(#store));
+            (quote!(#store), quote!(), quote!(), quote!())
+        },
+    };
+    // an erased rate limiter keeps its stores behind `ceiling::ErasedStore`, creating the given store by default
+    let (store, new_store) = if erased {
+        (
+            quote!(ceiling::ErasedStore),
+            quote!(ceiling::ErasedStore::from_store(#store::new())),
+        )
+    } else {
+        (store.clone(), quote!(#store::new()))
+    };
+    let store_assertion = asserted_store.map(|store| {
+        quote! {
+            const _: fn() = || {
+                fn assert_store<T: #store_bound>() {}
+                assert_store::<#store>();
+            };
+        }
+    });
 
+    // the limits of every window are kept in the rate limiter so they can be overridden at runtime,
+    // a rule's windows follow those of the rules before it
+    let offsets = rules
+        .iter()
+        .scan(0, |offset, r| {
+            let start = *offset;
+            *offset += r.windows.len();
+            Some(start)
+        })
+        .collect::<Vec<_>>();
+    let num_windows = rules.iter().map(|r| r.windows.len()).sum::<usize>();
+    let window_counts = rules.iter().map(|r| r.windows.len()).collect::<Vec<_>>();
+    let offset_ends = rules
+        .iter()
+        .zip(&offsets)
+        .map(|(r, offset)| offset + r.windows.len())
+        .collect::<Vec<_>>();
+    let default_limits = rules.iter().flat_map(|r| &r.windows).map(
+        |rate_limiter_input::Window {
+             limit,
+             interval,
+             timeout,
+         }| quote!(ceiling::Rule::new(#limit, #interval, #timeout)),
+    );
+    let limit_checks = rules.iter().zip(&offsets).map(|(r, offset)| {
+        let (name, len) = (&r.name, r.windows.len());
+        // every rule needs an interval, a window of zero seconds would reset on every hit
+        let reason = if r.count {
+            quote!((limits.limit != u32::MAX)
+                .then_some("counting rules have no limit")
+                .or((limits.interval == 0).then_some("counting rules need an interval above zero")))
+        } else if r.leaky {
+            quote!((limits.limit == 0 || limits.interval == 0)
+                .then_some("leaky rules need a limit and interval above zero"))
+        } else if r.aligned && r.grace > 0 {
+            quote!((limits.limit == 0 || limits.interval == 0)
+                .then_some("aligned rules with a grace need a limit and interval above zero"))
+        } else if r.aligned {
+            quote!((limits.interval == 0).then_some("aligned rules need an interval above zero"))
+        } else if r.grace > 0 {
+            quote!((limits.limit == 0 || limits.interval == 0)
+                .then_some("rules with a grace need a limit and interval above zero"))
+        } else {
+            quote!((limits.interval == 0).then_some("rules need an interval above zero"))
+        };
+        quote!(#name if window < #len => (#offset + window, #reason))
+    });
+    let resolver_checks = rules.iter().zip(&offsets).map(|(r, offset)| {
+        let name = &r.name;
+        let reason = if r.leaky && r.windows.len() == 1 {
+            quote!(None)
+        } else {
+            quote!(Some(
+                "only leaky rules with a single window can resolve their capacity"
+            ))
+        };
+        quote!(#name => (#offset, #reason))
+    });
+    // the environment variables of a rule with several windows are numbered by the window's index
+    let env_windows = rules.iter().flat_map(|r| {
+        let name = r.name.to_uppercase();
+        let rule = &r.name;
+        let several = r.windows.len() > 1;
+        (0..r.windows.len()).map(move |i| {
+            let var = if several {
+                format!("{name}_{i}")
+            } else {
+                name.clone()
+            };
+            quote!((#rule, #i, #var))
+        })
+    });
+    let keys = impl_keys(&inputs, &header_parts, &rules, &separator, &on_empty, true)?;
+    let rule_keys = impl_keys(&inputs, &header_parts, &rules, &separator, &on_empty, false)?;
+    let rule_remaining = rules
+        .iter()
+        .zip(&offsets)
+        .map(|(r, offset)| {
+            impl_rule_remaining(
+                &inputs,
+                &header_parts,
+                r,
+                *offset,
+                &separator,
+                &on_empty,
+                async_store,
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let credits = rules
+        .iter()
+        .zip(&offsets)
+        .map(|(r, offset)| impl_credit(r, *offset, async_store));
+    let clear_buckets = impl_clear_buckets(
+        &inputs,
+        &header_parts,
+        &rules,
+        &offsets,
+        &separator,
+        &on_empty,
+        async_store,
+    )?;
+    let key_warnings = rules.iter().filter_map(impl_key_warning);
+    // the traits taking inputs have no way to pass the headers, so they're only implemented without header parts
+    let (grpc_limiter, governor_limiter, dyn_limiter) = if header_parts.is_empty() {
+        (
+            impl_grpc_limiter(&name, &inputs, async_store, &impl_generics, &type_generics),
+            impl_governor_limiter(&name, &inputs, async_store, &impl_generics, &type_generics),
+            impl_dyn_limiter(
+                &name,
+                &inputs,
+                &rules,
+                async_store,
+                &impl_generics,
+                &type_generics,
+            ),
+        )
+    } else {
+        (quote!(), quote!(), quote!())
+    };
+    // the headers are taken after the inputs by every method building keys if any rule has a `header("Name")` part
+    let (header_param, header_arg) = if header_parts.is_empty() {
+        (quote!(), quote!())
+    } else {
+        (
+            quote!(headers: &(impl ceiling::HeaderLookup + ?Sized),),
+            quote!(headers,),
+        )
+    };
+    let input_strs = inputs.iter().map(String::as_str).collect::<Vec<_>>();
+    let rule_infos = rules
+        .iter()
+        .map(|r| impl_rule_info(r, &header_parts))
+        .collect::<Vec<_>>();
     let input_type_params = inputs
         .iter()
         .map(|i| syn::parse_str::<syn::Ident>(format!("{}_IN", i.to_uppercase()).as_str()).unwrap())
         .collect::<Vec<_>>();
+    let fields = inputs
+        .iter()
+        .map(|i| syn::parse_str::<syn::Ident>(i).unwrap())
+        .collect::<Vec<_>>();
     let inputs = inputs
         .iter()
         .map(|i| syn::parse_str::<syn::Ident>(format!("{i}_input").as_str()).unwrap())
@@ -98,69 +700,432 @@ fn impl_rate_limiter(
         .collect::<Vec<_>>();
 
     let hit = syn::parse_str::<syn::Ident>(format!("{}Hit", name).as_str())?;
+    let hit_rules = syn::parse_str::<syn::Ident>(format!("{}HitRules", name).as_str())?;
 
     let rule_names = rules
         .iter()
         .map(|r| syn::parse_str::<syn::Ident>(&r.name).unwrap())
         .collect::<Vec<_>>();
+    let rule_strs = rules
+        .iter()
+        .map(|r| LitStr::new(&r.name, proc_macro2::Span::call_site()))
+        .collect::<Vec<_>>();
     let rule_impls = rules
         .iter()
-        .map(|r| impl_rule(r, async_store))
+        .zip(&offsets)
+        .map(|(r, offset)| impl_rule(r, *offset, async_store, StoreErrors::Infallible))
+        .collect::<Vec<_>>();
+    let rule_sequential = rule_impls.iter().map(|r| &r.sequential).collect::<Vec<_>>();
+    let try_errors = match on_error {
+        Some(on_error) => StoreErrors::Handle(on_error),
+        None => StoreErrors::Propagate,
+    };
+    let rule_try_sequential = rules
+        .iter()
+        .zip(&offsets)
+        .map(|(r, offset)| impl_rule(r, *offset, async_store, try_errors).sequential);
+    let rule_loads = rule_impls.iter().map(|r| &r.load);
+    let rule_consumes = rule_impls.iter().map(|r| &r.consume);
+    let rule_trips = rule_impls.iter().map(|r| &r.trips);
+    let rule_finishes = rule_impls.iter().map(|r| &r.finish);
+
+    // rules with several windows also report the state of each window in a separate field of the hit struct
+    let rule_windows = rules
+        .iter()
+        .map(|r| {
+            (r.windows.len() > 1)
+                .then(|| syn::parse_str::<syn::Ident>(&format!("{}_windows", r.name)).unwrap())
+        })
+        .collect::<Vec<_>>();
+    let hit_fields = rule_names.iter().zip(&rule_windows).zip(&rules).map(
+        |((name, windows), r)| match windows {
+            Some(windows) => {
+                let len = r.windows.len();
+                quote!(pub #name: (u32, u64, bool, String, u32, u32, u32), pub #windows: [ceiling::Window; #len])
+            },
+            None => quote!(pub #name: (u32, u64, bool, String, u32, u32, u32)),
+        },
+    );
+    // the runtime override of whether a rule is public replaces the value given in the macro
+    // and the limit, interval, and timeout reported are the most restrictive window's if the rule has several
+    let hit_inits = rule_names
+        .iter()
+        .zip(&rule_windows)
+        .enumerate()
+        .map(|(i, (name, windows))| match windows {
+            Some(windows) => quote! {
+                #name: {
+                    // bound together so the rule's own name is read before it's shadowed
+                    let (state, rule) = (#name.0, ceiling::Window::most_restrictive(&#name.1).rule);
+                    (state.0, state.1, self.__ceiling.public_override[#i].unwrap_or(state.2), state.3, rule.limit, rule.interval, rule.timeout)
+                },
+                #windows: #name.1
+            },
+            // a leaky rule reports the capacity resolved for its bucket as its limit
+            None if rules[i].leaky => {
+                let window = offsets[i];
+                let (limit, interval, timeout) = window_limits(window);
+                let resolved = syn::parse_str::<Ident>(&format!("{name}_limit")).unwrap();
+                quote! {
+                    #name: {
+                        let #resolved = match &self.__ceiling.capacity_resolvers[#window] {
+                            Some(resolver) => resolver.cached(&#name.3, now).map_or(#limit, |(capacity, _)| capacity.min(u32::MAX as u64) as u32),
+                            None => #limit,
+                        };
+                        (#name.0, #name.1, self.__ceiling.public_override[#i].unwrap_or(#name.2), #name.3, #resolved, #interval, #timeout)
+                    }
+                }
+            },
+            None => {
+                let (limit, interval, timeout) = window_limits(offsets[i]);
+                quote!(#name: (#name.0, #name.1, self.__ceiling.public_override[#i].unwrap_or(#name.2), #name.3, #limit, #interval, #timeout))
+            },
+        })
+        .collect::<Vec<_>>();
+    let num_rule_names = rule_names.len();
+    let state_name = Ident::new(&format!("__{}State", name), name.span());
+    // a counting rule's remaining requests are only zero once it has counted `u32::MAX` requests, which doesn't limit a hit
+    let rule_limited = rule_names
+        .iter()
+        .zip(&rules)
+        .map(|(name, r)| {
+            if r.count {
+                quote!(false)
+            } else {
+                quote!(hit.#name.0 == 0)
+            }
+        })
+        .collect::<Vec<_>>();
+    // counting rules never limit a hit, so they aren't described on the span
+    let span_rules = rule_names
+        .iter()
+        .zip(&rules)
+        .filter(|(_, r)| !r.count)
+        .map(|(name, r)| {
+            let rule = &r.name;
+            quote!((#rule, hit.#name.0, hit.#name.4, hit.#name.1))
+        })
+        .collect::<Vec<_>>();
+    let preloads = rules
+        .iter()
+        .zip(&offsets)
+        .map(|(r, offset)| impl_preload(r, *offset, async_store))
+        .collect::<Vec<_>>();
+    let rule_indices = (0..num_rule_names).collect::<Vec<_>>();
+    let enabled_names = rules.iter().map(rule_impl::enabled).collect::<Vec<_>>();
+    let enabled = quote! {
+        #(let #enabled_names = self.__ceiling.enabled[#rule_indices].load(std::sync::atomic::Ordering::Relaxed);)*
+    };
+    // a rule with a `header("Name")` part without a fallback is skipped like a disabled rule if the request doesn't have the header,
+    // and limits the request instead of sharing a bucket between every request without it, as does a rule with an empty part with `on_empty deny`
+    let rule_missing = rules
+        .iter()
+        .map(|r| {
+            let mut missing = header_parts
+                .iter()
+                .filter(|h| {
+                    h.fallback.is_none()
+                        && r.key
+                            .iter()
+                            .chain(&r.distinct)
+                            .flatten()
+                            .any(|k| *k == h.input)
+                })
+                .map(|h| {
+                    let lookup = syn::parse_str::<Ident>(&h.input).unwrap();
+                    quote!(#lookup.is_none())
+                })
+                .collect::<Vec<_>>();
+            if matches!(on_empty, OnEmpty::Deny) && !r.global && !r.key.is_empty() {
+                let empty = syn::parse_str::<Ident>(&format!("{}_empty", r.name)).unwrap();
+                missing.push(quote!(#empty));
+            }
+            (!missing.is_empty()).then(|| quote!((#(#missing)||*)))
+        })
+        .collect::<Vec<_>>();
+    let header_checks = rules.iter().zip(&rule_missing).filter_map(|(r, missing)| {
+        let missing = missing.as_ref()?;
+        let enabled = rule_impl::enabled(r);
+        Some(quote!(let #enabled = #enabled && !#missing;))
+    });
+    let header_checks = quote!(#(#header_checks)*);
+    // counting rules never limit a hit, so they're only skipped
+    let header_limits = rule_missing
+        .iter()
+        .zip(&rules)
+        .map(|(missing, r)| match missing {
+            Some(missing) if !r.count => quote!(hit |= #missing;),
+            _ => quote!(),
+        })
+        .collect::<Vec<_>>();
+    // a rule that limited the request this way is reported as exhausted, so the headers, error body, decisions, and metrics agree with the hit
+    let header_exhausts = rule_missing
+        .iter()
+        .zip(&rules)
+        .zip(&rule_names)
+        .map(|((missing, r), name)| match missing {
+            Some(missing) if !r.count && r.windows.len() > 1 => quote! {
+                let #name = if #missing {
+                    (
+                        (0, #name.0.1, #name.0.2, #name.0.3),
+                        #name.1.map(|window| ceiling::Window { remaining: 0, ..window }),
+                    )
+                } else {
+                    #name
+                };
+            },
+            Some(missing) if !r.count => quote! {
+                let #name = if #missing { (0, #name.1, #name.2, #name.3) } else { #name };
+            },
+            _ => quote!(),
+        })
+        .collect::<Vec<_>>();
+    // the labels of each rule's metrics, the rule's name followed by its tags
+    let rule_labels = rules
+        .iter()
+        .map(|r| {
+            let escape = |value: &str| {
+                value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+            };
+            std::iter::once(("rule", r.name.as_str()))
+                .chain(r.tags.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+                .map(|(k, v)| format!("{k}=\"{}\"", escape(v)))
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect::<Vec<_>>();
+    // counting rules are never public, so they can't be overridden
+    let overridable = rules
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| !r.count)
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    let num_overridable = overridable.len();
+    let overridable_strs = overridable
+        .iter()
+        .map(|i| &rule_strs[*i])
+        .collect::<Vec<_>>();
+    // evaluates to the limit, interval, and timeout reported for a rule
+    let rule_configs = rule_names
+        .iter()
+        .map(|name| quote!((self.#name.4, self.#name.5, self.#name.6)))
+        .collect::<Vec<_>>();
+
+    // evaluates to the seconds until a rule's bucket resets, a bucket resets in the second after its reset time
+    // unless the rule is leaky, where the reset is the time the bucket is full again, or aligned, where it's the next window's start
+    let rule_reset_afters = rule_names
+        .iter()
+        .zip(&rules)
+        .map(|(name, r)| match rounding {
+            Rounding::Up if !r.leaky && !r.aligned => {
+                quote!(self.#name.1.saturating_add(1).saturating_sub(now))
+            },
+            _ => quote!((self.#name.1).saturating_sub(now)),
+        })
         .collect::<Vec<_>>();
 
-    let num_rules = rules.iter().filter(|r| r.public).count();
-    let num_headers = num_rules * 7;
+    let num_headers = rules.iter().filter(|r| !r.count).count() * 8 + 1;
 
-    let rules_serde = rule_names.iter().zip(&rules).map(|(name, r)| {
-        let Rule {
-            name: _,
-            limit,
-            interval,
-            timeout,
-            key: _,
-            public,
-        } = r;
-        if *public {
-            quote! {
-                let mut m: std::collections::HashMap<&str, Val> = std::collections::HashMap::with_capacity(7);
-                m.insert("limit", #limit.into());
-                m.insert("interval", #interval.into());
-                m.insert("timeout", #timeout.into());
+    let rules_serde = rule_names.iter().zip(&rules).zip(&rule_configs).zip(&rule_reset_afters).map(|(((name, r), config), reset_after)| {
+        let key = if r.hide_key {
+            quote!()
+        } else {
+            quote!(m.insert("key", (&self.#name.3).into());)
+        };
+        let rule = &r.name;
+        quote! {
+            if self.#name.2 && included(#rule) {
+                let (limit, interval, timeout) = #config;
+                let mut m: std::collections::HashMap<&str, Val> = std::collections::HashMap::with_capacity(10);
+                m.insert("limit", limit.into());
+                m.insert("interval", interval.into());
+                m.insert("timeout", timeout.into());
                 m.insert("remaining", self.#name.0.into());
                 m.insert("reset", self.#name.1.into());
-                m.insert("reset_after", (self.#name.1).saturating_sub(now).into());
-                m.insert("key", (&self.#name.3).into());
-                map.serialize_entry(stringify!(self.#name), &m)?;
+                m.insert("reset_after", #reset_after.into());
+                m.insert("spacing", ceiling::Rule::new(limit, interval, timeout).spacing().into());
+                m.insert("public", self.#name.2.into());
+                #key
+                map.serialize_entry(#rule, &m)?;
+            }
+        }
+    });
+    let rules_error = rule_names.iter().zip(&rules).zip(&rule_configs).zip(&rule_reset_afters).map(|(((name, r), config), reset_after)| {
+        if r.count {
+            return quote!();
+        }
+        let (rule, limit) = (
+            quote!(self.#name.2.then(|| stringify!(#name))),
+            quote!(self.#name.2.then(|| #config.0)),
+        );
+        let reason = match &r.reason {
+            Some(reason) => quote!(Some(#reason)),
+            None => quote!(None),
+        };
+        quote! {
+            let retry_after = #reset_after;
+            if self.#name.0 == 0 && error.as_ref().map_or(true, |e| e.retry_after < retry_after) {
+                error = Some(ceiling::RateLimitError {
+                    retry_after,
+                    limit: #limit,
+                    rule: #rule,
+                    reason: #reason,
+                });
             }
-        } else {
-            quote!()
         }
     });
-    let rules_headers = rule_names.iter().zip(&rules).map(|(name, r)| {
-        let Rule {
-            name: _,
-            limit,
-            interval,
-            timeout,
-            key: _,
-            public,
-        } = r;
-        if *public {
+    let HeaderNames {
+        limit: limit_header,
+        interval: interval_header,
+        timeout: timeout_header,
+        remaining: remaining_header,
+        reset: reset_header,
+        reset_after: reset_after_header,
+        spacing: spacing_header,
+        key: key_header,
+        reason: reason_header,
+        policy: policy_header,
+    } = &headers;
+    let rules_flat = rule_names
+        .iter()
+        .zip(&rule_configs)
+        .zip(&rule_reset_afters)
+        .map(|((name, config), reset_after)| {
+            let attribute = |attribute: &str| format!("{name}.{attribute}");
+            let (limit, interval, timeout, remaining, used, reset, reset_after_key, spacing, key, public) = (
+                attribute("limit"),
+                attribute("interval"),
+                attribute("timeout"),
+                attribute("remaining"),
+                attribute("used"),
+                attribute("reset"),
+                attribute("reset_after"),
+                attribute("spacing"),
+                attribute("key"),
+                attribute("public"),
+            );
             quote! {
-                vec.push(("X-RateLimit-Limit", format!("{} {}", stringify!(#name), #limit)));
-                vec.push(("X-RateLimit-Interval", format!("{} {}", stringify!(#name), #interval)));
-                vec.push(("X-RateLimit-Timeout", format!("{} {}", stringify!(#name), #timeout)));
-                vec.push(("X-RateLimit-Remaining", format!("{} {}", stringify!(#name), self.#name.0)));
-                vec.push(("X-RateLimit-Reset", format!("{} {}", stringify!(#name), self.#name.1)));
-                vec.push(("X-RateLimit-Reset-After", format!("{} {}", stringify!(#name), (self.#name.1).saturating_sub(now))));
-                vec.push(("X-RateLimit-Key", format!("{} {}", stringify!(#name), self.#name.3)));
+                let (limit, interval, timeout) = #config;
+                map.insert(#limit.into(), limit.to_string());
+                map.insert(#interval.into(), interval.to_string());
+                map.insert(#timeout.into(), timeout.to_string());
+                map.insert(#remaining.into(), self.#name.0.to_string());
+                map.insert(#used.into(), limit.saturating_sub(self.#name.0).to_string());
+                map.insert(#reset.into(), self.#name.1.to_string());
+                map.insert(#reset_after_key.into(), #reset_after.to_string());
+                map.insert(#spacing.into(), ceiling::Rule::new(limit, interval, timeout).spacing().to_string());
+                map.insert(#key.into(), self.#name.3.clone());
+                map.insert(#public.into(), self.#name.2.to_string());
             }
-        } else {
+        });
+    let num_flat = rules.len() * 10;
+    let rules_used = rule_names.iter().zip(&rules).filter(|(_, r)| r.count).map(|(name, _)| {
+        let method = syn::parse_str::<Ident>(&format!("{name}_used")).unwrap();
+        let doc = format!("The number of requests counted by the counting rule `{name}` in its current interval.");
+        quote! {
+            #[doc = #doc]
+            pub fn #method(&self) -> u32 {
+                u32::MAX - self.#name.0
+            }
+        }
+    });
+    let rules_headers = rule_names.iter().zip(&rules).zip(&rule_configs).zip(&rule_reset_afters).map(|(((name, r), config), reset_after)| {
+        let key = if r.hide_key {
             quote!()
+        } else {
+            quote!(vec.push((#key_header, format!("{} {}", stringify!(#name), self.#name.3)));)
+        };
+        quote! {
+            if self.#name.2 {
+                let (limit, interval, timeout) = #config;
+                vec.push((#limit_header, format!("{} {}", stringify!(#name), limit)));
+                vec.push((#interval_header, format!("{} {}", stringify!(#name), interval)));
+                vec.push((#timeout_header, format!("{} {}", stringify!(#name), timeout)));
+                vec.push((#remaining_header, format!("{} {}", stringify!(#name), self.#name.0)));
+                vec.push((#reset_header, format!("{} {}", stringify!(#name), self.#name.1)));
+                vec.push((#reset_after_header, format!("{} {}", stringify!(#name), #reset_after)));
+                vec.push((#spacing_header, format!("{} {}", stringify!(#name), ceiling::Rule::new(limit, interval, timeout).spacing())));
+                #key
+            }
+        }
+    });
+    let rules_minimal_headers = rule_names.iter().zip(&rule_configs).map(|(name, config)| {
+        quote! {
+            if self.#name.2 {
+                vec.push((#limit_header, format!("{} {}", stringify!(#name), #config.0)));
+                vec.push((#remaining_header, format!("{} {}", stringify!(#name), self.#name.0)));
+                vec.push((#reset_header, format!("{} {}", stringify!(#name), self.#name.1)));
+            }
+        }
+    });
+    // the inputs in a rule's key are known at compile time, the limit and interval are taken from the hit so overrides are described
+    let rules_policy_headers = rule_names.iter().zip(&rules).map(|(name, r)| {
+        let per = if r.global {
+            String::new()
+        } else {
+            let parts = r
+                .key
+                .iter()
+                .map(|part| match part.as_slice() {
+                    [input] => display_part(input, &header_parts).to_string(),
+                    inputs => format!("coalesce({})", inputs.join(", ")),
+                })
+                .collect::<Vec<_>>();
+            format!(" per {}", parts.join(separator.as_str()))
+        };
+        quote! {
+            if self.#name.2 {
+                let (limit, interval) = (self.#name.4, self.#name.5);
+                vec.push((
+                    #policy_header,
+                    format!(
+                        "{} {} request{} per {} second{}{}",
+                        stringify!(#name),
+                        limit,
+                        if limit == 1 { "" } else { "s" },
+                        interval,
+                        if interval == 1 { "" } else { "s" },
+                        #per,
+                    ),
+                ));
+            }
         }
     });
+    let num_policy_headers = rules.iter().filter(|r| !r.count).count();
+    let num_minimal_headers = num_policy_headers * 3;
 
+    let num_inputs = inputs.len();
     let async_hit = if async_store { quote!(async) } else { quote!() };
+    // the stores, enabled flags, and metrics are shared between clones, without atomic reference counting if single threaded
+    let shared = if single_threaded {
+        quote!(std::rc::Rc)
+    } else {
+        quote!(std::sync::Arc)
+    };
+    let with_store = if erased {
+        quote! {
+            /// Replaces the store of every rule with a new, empty store of type `S`, which clones made before this don't share.
+            pub fn with_store<S>(mut self) -> Self
+            where
+                S: ceiling::SyncStore + 'static,
+                S::Lock: 'static,
+            {
+                #(self.#rule_names = #shared::new(ceiling::ErasedStore::from_store(S::new()));)*
+                self
+            }
+        }
+    } else {
+        quote!()
+    };
+    let await_store = if async_store {
+        quote!(.await)
+    } else {
+        quote!()
+    };
     let use_store = if async_store {
         quote!(
             use ceiling::AsyncStore;
@@ -170,176 +1135,1685 @@ fn impl_rate_limiter(
             use ceiling::SyncStore;
         )
     };
-    Ok(quote! {
-        #[derive(Clone, Debug)]
-        pub struct #name {
-            #(#rule_names: std::sync::Arc<#store>),*
-        }
+    let key_names = rules
+        .iter()
+        .map(|r| syn::parse_str::<Ident>(&format!("{}_key", r.name)).unwrap())
+        .collect::<Vec<_>>();
+    // the values counted by distinct rules and costs taken from inputs can't be given with the keys,
+    // so those rate limiters always build their keys
+    let hit_with_keys = if rules
+        .iter()
+        .all(|r| r.distinct.is_empty() && r.cost_input.is_none())
+    {
+        quote! {
+            /// Hits the rate limiter like `hit`, but with the key of each rule given in the order of the rules instead of built from the inputs,
+            /// e.g. keys from `keys` built ahead of the hit, or keys built in ways the macro can't express.
+            /// The keys are moved into the hit struct, so owned keys aren't copied and only borrowed ones are allocated.
+            #[must_use = "check whether the request is rate limited"]
+            pub #async_hit fn hit_with_keys<K: Into<String>>(&self, keys: [K; #num_rule_names]) -> (bool, #hit) {
+                #use_store
+                self.enforce_memory_budget()#await_store;
 
-        impl #name {
-            pub fn new() -> Self {
-                Self {
-                    #(#rule_names: std::sync::Arc::new(#store::new())),*
-                }
+                let now = self.now();
+                let mut hit = false;
+                let [#(#key_names),*] = keys.map(Into::into);
+                #enabled
+                #(
+                    let #rule_names = #rule_sequential;
+                )*
+                let rate_limit_hit = #hit {
+                    #(#hit_inits,)*
+                    now,
+                };
+                self.record_decisions(&rate_limit_hit);
+                #[cfg(feature = "prometheus")]
+                self.record_metrics(&rate_limit_hit);
+                #[cfg(feature = "otel")]
+                self.record_span(hit, &rate_limit_hit);
+                (hit, rate_limit_hit)
             }
+        }
+    } else {
+        quote!()
+    };
+    // the body of `hit_atomic`, shared with `hit_guarded` which also needs to know which rules took a request
+    let atomic_consume = quote! {
+        #use_store
+        self.enforce_memory_budget()#await_store;
 
-            pub #async_hit fn hit<#(#input_type_params),*>(&self, #(#input_params),*) -> (bool, #hit)
+        let now = self.now();
+        let mut hit = false;
+        #keys
+        #enabled
+        #header_checks
+        #(#header_limits)*
+        #(#rule_loads)*
+        if hit #(|| #rule_trips)* {
+            hit = true;
+        } else {
+            #(#rule_consumes)*
+        }
+    };
+    let atomic_finish = quote! {
+        #(
+            let #rule_names = {
+                #rule_finishes
+            };
+        )*
+        #(#header_exhausts)*
+        let rate_limit_hit = #hit {
+            #(#hit_inits,)*
+            now,
+        };
+        self.record_decisions(&rate_limit_hit);
+        #[cfg(feature = "prometheus")]
+        self.record_metrics(&rate_limit_hit);
+        #[cfg(feature = "otel")]
+        self.record_span(hit, &rate_limit_hit);
+    };
+    // the refund happens when the guard is dropped, which can't await an async store
+    let hit_guarded = if async_store {
+        quote!()
+    } else {
+        let costs = rules.iter().map(|r| match &r.cost_input {
+            Some(input) => {
+                let input = syn::parse_str::<Ident>(&format!("{input}_input")).unwrap();
+                quote!(ceiling::parse_cost(&#input.to_string()))
+            },
+            None => {
+                let cost = r.cost;
+                quote!(#cost)
+            },
+        });
+        // a rule took a request if none tripped and it was enabled, and for a distinct rule if its value hadn't been seen,
+        // in which case the value's marker is removed along with the refund
+        let consumed = rules.iter().map(|r| {
+            let enabled = rule_impl::enabled(r);
+            if r.distinct.is_empty() {
+                quote!((!hit && #enabled))
+            } else {
+                let seen = syn::parse_str::<Ident>(&format!("{}_seen", r.name)).unwrap();
+                quote!((!hit && #enabled && !#seen))
+            }
+        });
+        let markers = rules.iter().map(|r| {
+            if r.distinct.is_empty() {
+                quote!(None)
+            } else {
+                let seen_key = syn::parse_str::<Ident>(&format!("{}_seen_key", r.name)).unwrap();
+                quote!(Some(#seen_key.clone()))
+            }
+        });
+        let refunds = rules
+            .iter()
+            .zip(&offsets)
+            .enumerate()
+            .map(|(i, (r, offset))| {
+                let store = syn::parse_str::<Ident>(&r.name).unwrap();
+                let credit = impl_credit(r, *offset, false);
+                quote! {
+                    if consumed[#i] {
+                        let amount = costs[#i];
+                        #credit
+                        if let Some(marker) = &markers[#i] {
+                            self.#store.remove(marker);
+                        }
+                    }
+                }
+            });
+        quote! {
+            /// Hits the rate limiter like `hit_atomic`, returning a `ceiling::HitGuard` that gives the requests taken back to the buckets of the rules
+            /// that took one like `credit` when it's dropped without being committed. A limited request took nothing, so nothing is given back,
+            /// and neither is a distinct rule's request if its value had already been seen.
+            /// The guard borrows the rate limiter, see `ceiling::HitGuard`. Only rate limiters with a synchronous store have this method,
+            /// since the refund can't await a store when the guard is dropped.
+            #[must_use = "check whether the request is rate limited"]
+            pub fn hit_guarded<#(#input_type_params),*>(&self, #(#input_params,)* #header_param) -> (bool, #hit, ceiling::HitGuard<impl FnOnce() + '_>)
             where
                 #(#input_type_params: std::fmt::Display),*
-                {
-                    #use_store
+            {
+                #atomic_consume
+                #atomic_finish
+                let consumed = [#(#consumed),*];
+                let markers = [#(#markers),*];
+                #rule_keys
+                let costs = [#(#costs),*];
+                (hit, rate_limit_hit, self.hit_guard(consumed, [#(#key_names),*], markers, costs))
+            }
 
-                    let now = std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs();
-                    let mut hit = false;
-                    #(#rule_impls)*
-                    (hit, #hit {
-                        #(#rule_names),*
-                    })
-                }
-        }
+            // the guard is built outside of `hit_guarded` so its type doesn't depend on the types of the inputs
+            fn hit_guard(
+                &self,
+                consumed: [bool; #num_rule_names],
+                keys: [String; #num_rule_names],
+                markers: [Option<String>; #num_rule_names],
+                costs: [u32; #num_rule_names],
+            ) -> ceiling::HitGuard<impl FnOnce() + '_> {
+                ceiling::HitGuard::new(move || {
+                    use ceiling::SyncStore;
 
-        #[derive(Clone, Debug)]
-        pub struct #hit {
-            pub #(#rule_names: (u32, u64, bool, String)),*
+                    let now = self.now();
+                    let [#(#key_names),*] = keys;
+                    #enabled
+                    #(#refunds)*
+                })
+            }
         }
+    };
+    let hit_from = match context {
+        Some((context, ty)) => {
+            let context = syn::parse_str::<Ident>(&context)?;
+            let ty = syn::parse_str::<Path>(&ty)?;
+            quote! {
+                /// Hits the rate limiter like `hit`, with each input read from the field of the same name of the context.
+                #[must_use = "check whether the request is rate limited"]
+                pub #async_hit fn hit_from(&self, #context: &#ty, #header_param) -> (bool, #hit) {
+                    self.hit(#(&#context.#fields,)* #header_arg)#await_store
+                }
+            }
+        },
+        None => quote!(),
+    };
+    // async stores are pruned by a `tokio` task awaiting their prune, sync stores by a thread,
+    // neither of which can share the stores of a single threaded rate limiter
+    let spawn_pruner = if single_threaded {
+        quote!()
+    } else if async_store {
+        quote! {
+            /// Spawns a `tokio` task pruning the expired buckets of every rule's store each interval, in addition to the pruning done on each hit.
+            /// The task shares the stores like a clone and is aborted when the returned `ceiling::Pruner` is dropped.
+            /// Must be called from within a `tokio` runtime.
+            #[cfg(feature = "tokio")]
+            pub fn spawn_pruner(&self, interval: std::time::Duration) -> ceiling::Pruner
+            where
+                Self: 'static,
+            {
+                use ceiling::AsyncStore;
 
-        impl #hit {
-            pub fn to_headers(&self) -> Vec<(&str, String)> {
-                let now = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-                let mut vec = Vec::with_capacity(#num_headers);
-                #(#rules_headers)*
-                vec
+                let limiter = self.clone();
+                ceiling::Pruner::task(interval, move |now| {
+                    let limiter = limiter.clone();
+                    async move {
+                        #(limiter.#rule_names.prune(now.saturating_sub(limiter.__ceiling.clock_skew)).await;)*
+                    }
+                })
             }
         }
-
-        #[cfg(feature = "serde")]
-        impl serde::Serialize for #hit {
-            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    } else {
+        quote! {
+            /// Spawns a thread pruning the expired buckets of every rule's store each interval, in addition to the pruning done on each hit.
+            /// The thread shares the stores like a clone and is stopped when the returned `ceiling::Pruner` is dropped.
+            pub fn spawn_pruner(&self, interval: std::time::Duration) -> ceiling::Pruner
             where
-                S: serde::Serializer,
+                Self: 'static,
             {
-                use serde::ser::SerializeMap;
+                use ceiling::SyncStore;
 
-                let now = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-                let mut map = serializer.serialize_map(Some(#num_rules))?;
-                #(#rules_serde)*
-                map.end()
+                let limiter = self.clone();
+                ceiling::Pruner::thread(interval, move |now| {
+                    #(limiter.#rule_names.prune(now.saturating_sub(limiter.__ceiling.clock_skew));)*
+                })
             }
         }
+    };
+    // including the file makes the compiler rebuild the rate limiter when the rules change
+    let include = include.map(|path| {
+        quote!(
+            const _: &str = include_str!(#path);
+        )
+    });
+    Ok(quote! {
+        #include
+        #store_assertion
+        #(#key_warnings)*
 
-        #[cfg(feature = "serde")]
-        enum Val {
-            Int(u64),
-            Str(String),
+        // the state of the rate limiter other than the rules' stores is kept in a single field,
+        // so rules can be named after any of it
+        #[derive(Clone, Debug)]
+        struct #state_name {
+            public_override: [Option<bool>; #num_rule_names],
+            limits: [ceiling::Rule; #num_windows],
+            capacity_resolvers: [Option<ceiling::CapacityResolver>; #num_windows],
+            enabled: #shared<[std::sync::atomic::AtomicBool; #num_rule_names]>,
+            on_first_hit: Option<ceiling::RuleCallback>,
+            decision_sink: Option<ceiling::DecisionSink>,
+            memory_budget: Option<usize>,
+            clock_skew: u64,
+            #[cfg(feature = "prometheus")]
+            metrics: #shared<[[std::sync::atomic::AtomicU64; 2]; #num_rule_names]>,
+            #[cfg(feature = "events")]
+            events: ceiling::EventChannel,
+            #[cfg(feature = "clock")]
+            clock: Option<std::sync::Arc<dyn ceiling::Clock>>,
         }
 
-        #[cfg(feature = "serde")]
-        impl From<u32> for Val {
-            fn from(v: u32) -> Val {
-                Val::Int(v as u64)
+        impl #state_name {
+            fn new() -> Self {
+                Self {
+                    public_override: [None; #num_rule_names],
+                    limits: [#(#default_limits),*],
+                    capacity_resolvers: std::array::from_fn(|_| None),
+                    enabled: #shared::new(std::array::from_fn(|_| std::sync::atomic::AtomicBool::new(true))),
+                    on_first_hit: None,
+                    decision_sink: None,
+                    memory_budget: None,
+                    clock_skew: 0,
+                    #[cfg(feature = "prometheus")]
+                    metrics: #shared::new(std::array::from_fn(|_| Default::default())),
+                    #[cfg(feature = "events")]
+                    events: ceiling::EventChannel::new(),
+                    #[cfg(feature = "clock")]
+                    clock: None,
+                }
             }
         }
 
-        #[cfg(feature = "serde")]
-        impl From<u64> for Val {
-            fn from(v: u64) -> Val {
-                Val::Int(v)
-            }
+        #[derive(Debug)]
+        #visibility struct #name #struct_generics {
+            #(#rule_names: #shared<#store>,)*
+            __ceiling: #state_name,
         }
 
-        #[cfg(feature = "serde")]
-        impl From<&String> for Val {
-            fn from(v: &String) -> Val {
-                Val::Str(v.to_string())
+        /// Clones share the stores of the rate limiter, so a hit on a clone counts towards the original.
+        /// Use `fork` for a rate limiter with its own stores.
+        impl #impl_generics Clone for #name #type_generics {
+            fn clone(&self) -> Self {
+                Self {
+                    #(#rule_names: self.#rule_names.clone(),)*
+                    __ceiling: self.__ceiling.clone(),
+                }
+            }
+        }
+
+        // a store's lock may not implement `Drop` itself, but is still dropped explicitly to release it early
+        #[allow(clippy::drop_non_drop)]
+        impl #impl_generics #name #type_generics {
+            pub fn new() -> Self {
+                Self {
+                    #(#rule_names: #shared::new(#new_store),)*
+                    __ceiling: #state_name::new(),
+                }
+            }
+
+            /// Creates a rate limiter with the same rules but new, empty stores, unlike `clone` which shares the stores.
+            /// Any overrides of whether rules are public and of their limits are kept, as are the rules currently disabled,
+            /// the capacity resolvers, the `on_first_hit` callback, the decision sink, the memory budget, the clock skew tolerance, and the clock, the metrics start from zero and the events go to a new channel.
+            pub fn fork(&self) -> Self {
+                let state = &self.__ceiling;
+                Self {
+                    __ceiling: #state_name {
+                        public_override: state.public_override,
+                        limits: state.limits,
+                        capacity_resolvers: state.capacity_resolvers.clone(),
+                        on_first_hit: state.on_first_hit.clone(),
+                        decision_sink: state.decision_sink.clone(),
+                        memory_budget: state.memory_budget,
+                        clock_skew: state.clock_skew,
+                        enabled: #shared::new(std::array::from_fn(|i| {
+                            std::sync::atomic::AtomicBool::new(state.enabled[i].load(std::sync::atomic::Ordering::Relaxed))
+                        })),
+                        #[cfg(feature = "prometheus")]
+                        metrics: #shared::new(std::array::from_fn(|_| Default::default())),
+                        #[cfg(feature = "events")]
+                        events: ceiling::EventChannel::new(),
+                        #[cfg(feature = "clock")]
+                        clock: state.clock.clone(),
+                    },
+                    ..Self::new()
+                }
+            }
+
+            /// The configuration of every rule as given in the macro.
+            pub const RULES: &'static [ceiling::RuleInfo] = &[#(#rule_infos),*];
+
+            /// The configuration of the rate limiter, with any overrides of whether rules are public and of their limits applied.
+            pub fn config(&self) -> ceiling::LimiterConfig {
+                let mut rules = Self::RULES.to_vec();
+                let offsets: [usize; #num_rule_names] = [#(#offsets),*];
+                for ((rule, public), offset) in rules.iter_mut().zip(self.__ceiling.public_override).zip(offsets) {
+                    rule.public = public.unwrap_or(rule.public);
+                    rule.windows = self.__ceiling.limits[offset..offset + rule.windows.len()].to_vec().into();
+                }
+                ceiling::LimiterConfig {
+                    inputs: &[#(#input_strs),*],
+                    rules,
+                }
+            }
+
+            #spawn_pruner
+
+            /// Overrides the limit, interval, and timeout of a window of a rule, the window being its index in the brackets or zero for rules with a single window.
+            /// Returns an error if the rule or window doesn't exist or the limits aren't valid for the rule, e.g. a leaky rule with an interval of zero.
+            /// Buckets already in the stores keep their reset, so the new interval and timeout apply from the next time they reset.
+            pub fn with_rule_limits(mut self, rule: &str, window: usize, limits: ceiling::Rule) -> Result<Self, ceiling::ConfigError> {
+                let (index, reason): (usize, Option<&'static str>) = match rule {
+                    #(#limit_checks,)*
+                    _ => return Err(ceiling::ConfigError::UnknownRule { rule: rule.to_string(), window }),
+                };
+                if let Some(reason) = reason {
+                    return Err(ceiling::ConfigError::InvalidLimits { rule: rule.to_string(), window, reason });
+                }
+                self.__ceiling.limits[index] = limits;
+                Ok(self)
+            }
+
+            /// Sets a resolver returning the capacity of a leaky rule's bucket and the requests leaked back into it every interval from the bucket's key,
+            /// e.g. to give the tenants of a higher tier a bigger bucket within a single rule. Both are at least one, and the rule's limit is used for both without a resolver.
+            /// The resolver is called when a bucket is first hit and the values are cached by the resolver, apart from the rule's store,
+            /// until a bucket emptied at that time would be full again. The hit struct and headers report the resolved capacity as the rule's limit.
+            /// Returns an error if the rule doesn't exist or isn't a leaky rule with a single window.
+            pub fn with_capacity_resolver<F: Fn(&str) -> (u32, u32) + Send + Sync + 'static>(mut self, rule: &str, resolver: F) -> Result<Self, ceiling::ConfigError> {
+                let (index, reason): (usize, Option<&'static str>) = match rule {
+                    #(#resolver_checks,)*
+                    _ => return Err(ceiling::ConfigError::UnknownRule { rule: rule.to_string(), window: 0 }),
+                };
+                if let Some(reason) = reason {
+                    return Err(ceiling::ConfigError::InvalidLimits { rule: rule.to_string(), window: 0, reason });
+                }
+                self.__ceiling.capacity_resolvers[index] = Some(ceiling::CapacityResolver::new(resolver));
+                Ok(self)
+            }
+
+            /// Creates a rate limiter with the limits of its rules read from environment variables, falling back to the limits given in the macro.
+            /// The variables are named `{prefix}_{RULE}_LIMIT`, `{prefix}_{RULE}_INTERVAL`, and `{prefix}_{RULE}_TIMEOUT` with the rule's name uppercased,
+            /// or `{prefix}_{RULE}_{WINDOW}_LIMIT` etc. for rules with several windows, the interval and timeout being in seconds.
+            /// Returns an error if a variable isn't a whole number or the limits aren't valid for the rule.
+            #[cfg(feature = "env")]
+            pub fn from_env(prefix: &str) -> Result<Self, ceiling::ConfigError> {
+                let var = |var: String| match std::env::var(&var) {
+                    Ok(value) => match value.trim().parse::<u32>() {
+                        Ok(value) => Ok(Some(value)),
+                        Err(_) => Err(ceiling::ConfigError::InvalidEnv { var, value }),
+                    },
+                    Err(std::env::VarError::NotPresent) => Ok(None),
+                    Err(std::env::VarError::NotUnicode(value)) => Err(ceiling::ConfigError::InvalidEnv {
+                        var,
+                        value: value.to_string_lossy().into_owned(),
+                    }),
+                };
+                let windows: [(&str, usize, &str); #num_windows] = [#(#env_windows),*];
+                let mut limiter = Self::new();
+                for (i, (rule, window, name)) in windows.into_iter().enumerate() {
+                    let defaults = limiter.__ceiling.limits[i];
+                    let limits = ceiling::Rule::new(
+                        var(format!("{prefix}_{name}_LIMIT"))?.unwrap_or(defaults.limit),
+                        var(format!("{prefix}_{name}_INTERVAL"))?.unwrap_or(defaults.interval),
+                        var(format!("{prefix}_{name}_TIMEOUT"))?.unwrap_or(defaults.timeout),
+                    );
+                    if limits != defaults {
+                        limiter = limiter.with_rule_limits(rule, window, limits)?;
+                    }
+                }
+                Ok(limiter)
+            }
+
+            /// Sets a callback invoked with the name of a rule and the key of the bucket when a hit creates a bucket that wasn't in the rule's store,
+            /// e.g. to count the clients active in each window. It isn't invoked when an expired bucket resets, only once the bucket has been pruned,
+            /// and it's invoked once per rule for rules with several windows. The callback is invoked after the bucket is released.
+            pub fn with_on_first_hit<F: Fn(&str, &str) + Send + Sync + 'static>(mut self, on_first_hit: F) -> Self {
+                self.__ceiling.on_first_hit = Some(ceiling::RuleCallback::new(on_first_hit));
+                self
+            }
+
+            /// Sets a sink called with the decision of every rule on each hit, e.g. for recording what changed limits would have done
+            /// against production traffic. The sink is called synchronously once the hit is decided, before the hit is returned.
+            /// `hit_fast` doesn't decide every rule, so its hits aren't passed to the sink.
+            pub fn with_decision_sink<F: Fn(&ceiling::Decision<'_>) + Send + Sync + 'static>(mut self, sink: F) -> Self {
+                self.__ceiling.decision_sink = Some(ceiling::DecisionSink::new(sink));
+                self
+            }
+
+            /// Passes the decision of each rule on a hit to the decision sink, if there is one.
+            fn record_decisions(&self, hit: &#hit) {
+                if let Some(sink) = &self.__ceiling.decision_sink {
+                    #(
+                        sink.call(&ceiling::Decision {
+                            rule: #rule_strs,
+                            key: &hit.#rule_names.3,
+                            remaining: hit.#rule_names.0,
+                            limited: #rule_limited,
+                            now: hit.now,
+                        });
+                    )*
+                }
+            }
+
+            #with_store
+
+            /// Sets the most buckets kept across the stores of every rule combined. Before each hit, while the stores hold more buckets
+            /// than the budget, the rule's store holding the most buckets evicts the excess, starting with the buckets that reset soonest
+            /// since they hold the least state. A hit can add one bucket per window of each rule on top of the budget.
+            /// The buckets are counted with the stores' `bucket_count` on every hit, which is O(n) for stores that don't override it.
+            pub fn with_memory_budget(mut self, max_entries: usize) -> Self {
+                self.__ceiling.memory_budget = Some(max_entries);
+                self
+            }
+
+            /// Sets the seconds a bucket is kept after its reset before it's treated as expired, e.g. for stores shared by instances
+            /// whose clocks differ slightly, so a bucket written by an instance with a clock behind isn't reset early by one ahead.
+            /// A bucket expires once its reset plus the tolerance is before now, which extends the effective window of fixed window rules
+            /// by up to the tolerance. A bucket kept past its reset reports its reset plus the tolerance in the hit struct and headers,
+            /// the time it expires, so clients aren't told to retry while it's still limited. Buckets are also pruned that much later.
+            /// Leaky rules leak requests back over time rather than resetting, so they're unaffected. The tolerance is zero by default.
+            pub fn with_clock_skew(mut self, seconds: u64) -> Self {
+                self.__ceiling.clock_skew = seconds;
+                self
+            }
+
+            /// Sets the clock read for the current time instead of the system time, e.g. a `ceiling::CoarseClock` to take the system call off every hit.
+            /// The clock is shared between clones and kept by `fork`.
+            #[cfg(feature = "clock")]
+            pub fn with_clock(mut self, clock: impl ceiling::Clock + 'static) -> Self {
+                self.__ceiling.clock = Some(std::sync::Arc::new(clock));
+                self
+            }
+
+            // the current time in seconds since the epoch, from the clock if one is set
+            fn now(&self) -> u64 {
+                #[cfg(feature = "clock")]
+                if let Some(clock) = &self.__ceiling.clock {
+                    return clock.now_secs();
+                }
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+            }
+
+            /// Evicts buckets from the largest stores until the stores are within the memory budget, if there is one.
+            /// Stops early if the largest store can't evict any buckets.
+            #async_hit fn enforce_memory_budget(&self) {
+                #use_store
+
+                let Some(budget) = self.__ceiling.memory_budget else {
+                    return;
+                };
+                loop {
+                    let counts = [#(self.#rule_names.bucket_count()#await_store),*];
+                    let total = counts.iter().sum::<usize>();
+                    if total <= budget {
+                        break;
+                    }
+                    let largest = (0..counts.len()).max_by_key(|&i| counts[i]).unwrap_or_default();
+                    let evicted = #(if largest == #rule_indices {
+                        self.#rule_names.evict(total - budget)#await_store
+                    } else)* {
+                        0
+                    };
+                    if evicted == 0 {
+                        break;
+                    }
+                }
+            }
+
+            /// Returns a receiver of a `ceiling::BucketEvent` for each window of an enabled rule checked by a hit from now on,
+            /// shared between clones. Receivers that fall too far behind miss the oldest events.
+            #[cfg(feature = "events")]
+            pub fn subscribe(&self) -> ceiling::EventReceiver {
+                self.__ceiling.events.subscribe()
+            }
+
+            /// Overrides whether every rule is public, replacing the `pub` given in the macro for the headers, serialized output, and error body.
+            /// Counting rules are never public and aren't affected.
+            /// Rules made public this way aren't checked for sensitive inputs in their keys like `pub` rules are,
+            /// so their keys are sent to clients unless the rule hides its key.
+            pub fn with_public(mut self, public: bool) -> Self {
+                let overridable: [usize; #num_overridable] = [#(#overridable),*];
+                for i in overridable {
+                    self.__ceiling.public_override[i] = Some(public);
+                }
+                self
+            }
+
+            /// Overrides whether a single rule is public like `with_public`, unknown rules and counting rules are ignored.
+            pub fn with_rule_public(mut self, rule: &str, public: bool) -> Self {
+                match rule {
+                    #(#overridable_strs => self.__ceiling.public_override[#overridable] = Some(public),)*
+                    _ => {},
+                }
+                self
+            }
+
+            /// Enables or disables a rule at runtime, unknown rules are ignored. A disabled rule never limits a request and doesn't touch its store,
+            /// its state in the hit struct is that of a bucket without any hits. Clones share whether rules are enabled, so this applies to every clone.
+            pub fn set_rule_enabled(&self, rule: &str, enabled: bool) {
+                let index = match rule {
+                    #(#rule_strs => #rule_indices,)*
+                    _ => return,
+                };
+                self.__ceiling.enabled[index].store(enabled, std::sync::atomic::Ordering::Relaxed);
+            }
+
+            /// Whether a rule is enabled, `None` if the rule doesn't exist.
+            pub fn rule_enabled(&self, rule: &str) -> Option<bool> {
+                match rule {
+                    #(#rule_strs => Some(self.__ceiling.enabled[#rule_indices].load(std::sync::atomic::Ordering::Relaxed)),)*
+                    _ => None,
+                }
+            }
+
+            #[must_use = "check whether the request is rate limited"]
+            pub #async_hit fn hit<#(#input_type_params),*>(&self, #(#input_params,)* #header_param) -> (bool, #hit)
+            where
+                #(#input_type_params: std::fmt::Display),*
+                {
+                    #use_store
+                    self.enforce_memory_budget()#await_store;
+
+                    let now = self.now();
+                    let mut hit = false;
+                    #keys
+                    #enabled
+                    #header_checks
+                    #(#header_limits)*
+                    #(
+                        let #rule_names = #rule_sequential;
+                    )*
+                    #(#header_exhausts)*
+                    let rate_limit_hit = #hit {
+                        #(#hit_inits,)*
+                        now,
+                    };
+                    self.record_decisions(&rate_limit_hit);
+                    #[cfg(feature = "prometheus")]
+                    self.record_metrics(&rate_limit_hit);
+                    #[cfg(feature = "otel")]
+                    self.record_span(hit, &rate_limit_hit);
+                    (hit, rate_limit_hit)
+                }
+
+            /// Hits the rate limiter like `hit`, but stops at the first rule that trips and skips the stores of the rules after it.
+            /// Returns whether the request is limited and the name of the rule that limited it.
+            /// No hit struct is returned since the state of the remaining rules isn't known, so there are no headers for the response.
+            #[must_use = "check whether the request is rate limited"]
+            pub #async_hit fn hit_fast<#(#input_type_params),*>(&self, #(#input_params,)* #header_param) -> (bool, Option<&'static str>)
+            where
+                #(#input_type_params: std::fmt::Display),*
+                {
+                    #use_store
+                    self.enforce_memory_budget()#await_store;
+
+                    let now = self.now();
+                    let mut hit = false;
+                    #keys
+                    #enabled
+                    #header_checks
+                    #(
+                        let _ = #rule_sequential;
+                        #header_limits
+                        #[cfg(feature = "prometheus")]
+                        self.__ceiling.metrics[#rule_indices][hit as usize].fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        if hit {
+                            return (true, Some(#rule_strs));
+                        }
+                    )*
+                    (false, None)
+                }
+
+            /// Hits the rate limiter like `hit`, but uses the fallible store methods.
+            /// If the rate limiter has an `on_error` policy a store error allows or limits the rule instead of being returned.
+            #[must_use = "check whether the request is rate limited"]
+            pub #async_hit fn try_hit<#(#input_type_params),*>(&self, #(#input_params,)* #header_param) -> Result<(bool, #hit), ceiling::StoreError>
+            where
+                #(#input_type_params: std::fmt::Display),*
+                {
+                    #use_store
+                    self.enforce_memory_budget()#await_store;
+
+                    let now = self.now();
+                    let mut hit = false;
+                    #keys
+                    #enabled
+                    #header_checks
+                    #(#header_limits)*
+                    #(
+                        let #rule_names = #rule_try_sequential;
+                    )*
+                    #(#header_exhausts)*
+                    let rate_limit_hit = #hit {
+                        #(#hit_inits,)*
+                        now,
+                    };
+                    self.record_decisions(&rate_limit_hit);
+                    #[cfg(feature = "prometheus")]
+                    self.record_metrics(&rate_limit_hit);
+                    #[cfg(feature = "otel")]
+                    self.record_span(hit, &rate_limit_hit);
+                    Ok((hit, rate_limit_hit))
+                }
+
+            /// Hits the rate limiter like `hit`, but only takes from the rules' buckets if none of them would trip.
+            /// Every bucket is locked before any are changed, so a request rejected by one rule doesn't use up the others.
+            #[must_use = "check whether the request is rate limited"]
+            pub #async_hit fn hit_atomic<#(#input_type_params),*>(&self, #(#input_params,)* #header_param) -> (bool, #hit)
+            where
+                #(#input_type_params: std::fmt::Display),*
+                {
+                    #atomic_consume
+                    #atomic_finish
+                    (hit, rate_limit_hit)
+                }
+
+            #hit_with_keys
+
+            #hit_from
+
+            #hit_guarded
+
+            /// Hits the rate limiter like `hit`, but with the inputs given in the order of the parameters of `hit` as a slice,
+            /// e.g. for middleware shared by rate limiters with different inputs. The names of the inputs are listed in `config().inputs`.
+            #[must_use = "check whether the request is rate limited"]
+            pub #async_hit fn hit_dyn(&self, inputs: &[&dyn std::fmt::Display], #header_param) -> Result<(bool, #hit), ceiling::InputCountError> {
+                let [#(#inputs),*] = inputs else {
+                    return Err(ceiling::InputCountError {
+                        expected: #num_inputs,
+                        found: inputs.len(),
+                    });
+                };
+                Ok(self.hit(#(#inputs,)* #header_arg)#await_store)
             }
+
+            /// Builds the key of each rule from the inputs, in the order of the rules, as used by `hit` and accepted by `hit_with_keys`.
+            pub fn keys<#(#input_type_params),*>(&self, #(#input_params,)* #header_param) -> [String; #num_rule_names]
+            where
+                #(#input_type_params: std::fmt::Display),*
+            {
+                #rule_keys
+                [#(#key_names),*]
+            }
+
+            #(#rule_remaining)*
+
+            /// Gives `amount` requests back to the buckets of every enabled rule for the inputs, up to each rule's limit,
+            /// e.g. to refund a request that was counted before the operation it was for failed.
+            /// Buckets that have reset since the hit are already full and are left alone.
+            pub #async_hit fn credit<#(#input_type_params),*>(&self, #(#input_params,)* #header_param amount: u32)
+            where
+                #(#input_type_params: std::fmt::Display),*
+            {
+                #use_store
+
+                let now = self.now();
+                #rule_keys
+                #enabled
+                #(#credits)*
+            }
+
+            #clear_buckets
+
+            /// Removes every bucket of the rule with the store's `clear`, e.g. to give every client a clean slate on a rule whose limits changed,
+            /// leaving the other rules alone. Returns whether the rule exists.
+            pub #async_hit fn clear_rule(&self, rule: &str) -> bool {
+                #use_store
+
+                // the capacities resolved for the rule's buckets are cached by the resolvers, not the store
+                match rule {
+                    #(#rule_strs => {
+                        self.#rule_names.clear()#await_store;
+                        self.__ceiling.capacity_resolvers[#offsets..#offset_ends].iter().flatten().for_each(ceiling::CapacityResolver::clear);
+                    },)*
+                    _ => return false,
+                }
+                true
+            }
+
+            /// Sets full buckets for the keys of rules given as the name of the rule and the key of its bucket, e.g. to warm the stores
+            /// with the clients known to take part in a scheduled traffic spike. The keys are those in the hit struct or from `keys`.
+            /// A preloaded bucket is full and unused until it's hit, its window starting when it's preloaded,
+            /// and a hit on it doesn't invoke the `on_first_hit` callback since the bucket is already in the store.
+            /// Returns the number of buckets set, buckets already in the store and rules that don't exist are skipped.
+            /// Preloading stops at the first entry that doesn't fit in the memory budget, if there is one, so the buckets of active clients
+            /// aren't evicted to make room for buckets that may never be hit.
+            pub #async_hit fn preload<'a>(&self, entries: impl IntoIterator<Item = (&'a str, &'a str)>) -> usize {
+                #use_store
+
+                let now = self.now();
+                let room = match self.__ceiling.memory_budget {
+                    Some(budget) => budget.saturating_sub([#(self.#rule_names.bucket_count()#await_store),*].iter().sum::<usize>()),
+                    None => usize::MAX,
+                };
+                let mut preloaded = 0;
+                for (rule, key) in entries {
+                    match rule {
+                        #(#rule_strs => {
+                            if preloaded + #window_counts > room {
+                                break;
+                            }
+                            #preloads
+                        },)*
+                        _ => {},
+                    }
+                }
+                preloaded
+            }
+
+            /// Prunes the expired buckets of every rule's store, returning the name of the rule and the key of each bucket removed
+            /// with the store's `prune_returning`, e.g. to invalidate caches keyed by them. The buckets of rules with several windows,
+            /// the values seen by distinct rules, and the last limited hits of forgiving rules have their suffix after a `#` in the key,
+            /// the keys of distinct and forgiving rules are escaped and `ceiling::unescape_key` returns them without the suffix.
+            pub #async_hit fn prune_expired(&self) -> Vec<(&'static str, String)> {
+                #use_store
+
+                let now = self.now();
+                let mut pruned = vec![];
+                #(
+                    pruned.extend(
+                        self.#rule_names
+                            .prune_returning(now.saturating_sub(self.__ceiling.clock_skew))#await_store
+                            .into_iter()
+                            .map(|key| (#rule_strs, key)),
+                    );
+                )*
+                pruned
+            }
+
+            /// Lists the keys of a rule's buckets starting with the prefix, returning no keys if the rule doesn't exist.
+            /// This is O(n) in the size of the rule's store and should be used sparingly.
+            pub #async_hit fn keys_with_prefix(&self, rule: &str, prefix: &str) -> Vec<String> {
+                #use_store
+
+                match rule {
+                    #(#rule_strs => self.#rule_names.keys_with_prefix(prefix)#await_store,)*
+                    _ => vec![],
+                }
+            }
+        }
+
+        #[cfg(feature = "otel")]
+        impl #impl_generics #name #type_generics {
+            /// Sets the outcome of the hit as attributes of the active OpenTelemetry span.
+            fn record_span(&self, limited: bool, hit: &#hit) {
+                ceiling::otel::record_hit(limited, &[#(#span_rules),*], hit.now);
+            }
+        }
+
+        #[cfg(feature = "prometheus")]
+        impl #impl_generics #name #type_generics {
+            /// Counts the outcome of each rule, the counters are shared between clones.
+            fn record_metrics(&self, hit: &#hit) {
+                #(
+                    self.__ceiling.metrics[#rule_indices][(hit.#rule_names.0 == 0) as usize]
+                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                )*
+            }
+
+            /// Formats the number of requests allowed and limited by each rule since the rate limiter was created,
+            /// and the number of buckets in each rule's store, in the Prometheus text exposition format.
+            /// The buckets are listed with `keys_with_prefix`, so this is O(n) in the size of the stores and stores that can't list their keys report zero.
+            pub #async_hit fn prometheus_metrics(&self) -> String {
+                #use_store
+                use std::fmt::Write;
+
+                let mut out = String::from(
+                    "# HELP ceiling_requests_total The number of requests checked against each rule by outcome.\n\
+                     # TYPE ceiling_requests_total counter\n",
+                );
+                #(
+                    for (outcome, count) in ["allowed", "limited"].into_iter().zip(self.__ceiling.metrics[#rule_indices].iter()) {
+                        let _ = writeln!(
+                            out,
+                            "ceiling_requests_total{{{},outcome=\"{}\"}} {}",
+                            #rule_labels,
+                            outcome,
+                            count.load(std::sync::atomic::Ordering::Relaxed),
+                        );
+                    }
+                )*
+                out.push_str(
+                    "# HELP ceiling_buckets The number of buckets in each rule's store.\n\
+                     # TYPE ceiling_buckets gauge\n",
+                );
+                #(
+                    let _ = writeln!(
+                        out,
+                        "ceiling_buckets{{{}}} {}",
+                        #rule_labels,
+                        self.#rule_names.keys_with_prefix("")#await_store.len(),
+                    );
+                )*
+                out
+            }
+        }
+
+        #grpc_limiter
+
+        #governor_limiter
+
+        #dyn_limiter
+
+        #[derive(Clone, Debug, PartialEq, Eq)]
+        #visibility struct #hit {
+            #(#hit_fields,)*
+            /// The time of the hit in seconds since the epoch, the seconds until each rule resets are counted from it
+            /// so the headers, error body, and serialized output agree with the decision.
+            pub now: u64,
         }
 
+        impl #hit {
+            pub fn to_headers(&self) -> Vec<(&'static str, String)> {
+                self.to_headers_at(self.now)
+            }
+
+            /// Returns the headers like `to_headers`, with the seconds until each rule resets counted from the time given in seconds since the epoch
+            /// instead of the time of the hit, e.g. the current time when the headers are sent long after the hit.
+            pub fn to_headers_at(&self, now: u64) -> Vec<(&'static str, String)> {
+                let mut vec = Vec::with_capacity(#num_headers);
+                #(#rules_headers)*
+                if let Some(reason) = self.reason() {
+                    vec.push((#reason_header, reason.to_string()));
+                }
+                vec
+            }
+
+            /// Returns only the limit, remaining, and reset headers of each public rule, for sending on every response
+            /// when the rest of the headers from `to_headers` aren't worth their size.
+            pub fn to_headers_minimal(&self) -> Vec<(&'static str, String)> {
+                let mut vec = Vec::with_capacity(#num_minimal_headers);
+                #(#rules_minimal_headers)*
+                vec
+            }
+
+            /// Returns a header describing each public rule for humans, e.g. `X-RateLimit-Policy: main 2 requests per 2 seconds per ip+route`,
+            /// for debugging with tools like curl. The inputs in the key are given as in the macro, the limit and interval are those the hit was checked against.
+            pub fn to_policy_headers(&self) -> Vec<(&'static str, String)> {
+                let mut vec = Vec::with_capacity(#num_policy_headers);
+                #(#rules_policy_headers)*
+                vec
+            }
+
+            /// The reason given with the `reason` clause of the limited rule that resets last, the same rule described by `to_error_body`.
+            /// Returns `None` if the request wasn't limited or the rule has no reason.
+            pub fn reason(&self) -> Option<&'static str> {
+                self.to_error_body().and_then(|error| error.reason)
+            }
+
+            pub fn to_error_body(&self) -> Option<ceiling::RateLimitError> {
+                let now = self.now;
+                let mut error: Option<ceiling::RateLimitError> = None;
+                #(#rules_error)*
+                error
+            }
+
+            /// Gets the state of a rule by name, returning `None` if the rule doesn't exist.
+            /// This is the same as the rule's field, for code that only knows the name of the rule at runtime.
+            pub fn get(&self, rule: &str) -> Option<&(u32, u64, bool, String, u32, u32, u32)> {
+                match rule {
+                    #(#rule_strs => Some(&self.#rule_names),)*
+                    _ => None,
+                }
+            }
+
+            #(#rules_used)*
+
+            /// Flattens every attribute of every rule, including private rules and hidden keys, into a map keyed by `<rule>.<attribute>`.
+            /// This is meant for logging the whole decision as a single field and shouldn't be sent to clients.
+            pub fn to_flat_map(&self) -> std::collections::HashMap<String, String> {
+                let now = self.now;
+                let mut map = std::collections::HashMap::with_capacity(#num_flat);
+                #(#rules_flat)*
+                map
+            }
+        }
+
+        impl ceiling::HitSummary for #hit {
+            fn to_headers(&self) -> Vec<(&'static str, String)> {
+                #hit::to_headers(self)
+            }
+
+            fn to_error_body(&self) -> Option<ceiling::RateLimitError> {
+                #hit::to_error_body(self)
+            }
+        }
+
+        #[cfg(feature = "http")]
+        impl #hit {
+            /// Converts the headers from `to_headers` into an `http::HeaderMap`, skipping any that aren't valid header values.
+            pub fn to_header_map(&self) -> http::HeaderMap {
+                let mut map = http::HeaderMap::new();
+                for (header, value) in self.to_headers() {
+                    if let (Ok(header), Ok(value)) = (
+                        http::header::HeaderName::from_bytes(header.as_bytes()),
+                        http::HeaderValue::from_str(&value),
+                    ) {
+                        map.append(header, value);
+                    }
+                }
+                map
+            }
+
+            /// Returns the status code and headers for a response, the status is `429 Too Many Requests` if the request was limited
+            /// and `200 OK` otherwise, meaning the request should be handled as normal with the headers added to the response.
+            pub fn to_response_parts(&self, limited: bool) -> (http::StatusCode, http::HeaderMap) {
+                let status = if limited {
+                    http::StatusCode::TOO_MANY_REQUESTS
+                } else {
+                    http::StatusCode::OK
+                };
+                (status, self.to_header_map())
+            }
+        }
+
+        /// A view of a hit serializing only the public rules named, returned from `serialize_rules`.
         #[cfg(feature = "serde")]
-        impl serde::Serialize for Val {
-            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        #[derive(Clone, Copy, Debug)]
+        #visibility struct #hit_rules<'a> {
+            hit: &'a #hit,
+            rules: &'a [&'a str],
+        }
+
+        #[cfg(feature = "serde")]
+        impl #hit {
+            /// Returns a view of the hit serializing only the public rules named, e.g. only the rule relevant to the endpoint a client hit.
+            /// Names of rules that don't exist are ignored.
+            pub fn serialize_rules<'a>(&'a self, rules: &'a [&'a str]) -> #hit_rules<'a> {
+                #hit_rules { hit: self, rules }
+            }
+
+            fn serialize_filtered<S>(&self, serializer: S, rules: Option<&[&str]>) -> Result<S::Ok, S::Error>
             where
                 S: serde::Serializer,
             {
-                match self {
-                    Self::Int(v) => serializer.serialize_u64(*v),
-                    Self::Str(v) => serializer.serialize_str(v),
+                use serde::ser::SerializeMap;
+
+                enum Val {
+                    Int(u64),
+                    Float(f64),
+                    Str(String),
+                    Bool(bool),
+                }
+
+                impl From<u32> for Val {
+                    fn from(v: u32) -> Val {
+                        Val::Int(v as u64)
+                    }
+                }
+
+                impl From<u64> for Val {
+                    fn from(v: u64) -> Val {
+                        Val::Int(v)
+                    }
+                }
+
+                impl From<f64> for Val {
+                    fn from(v: f64) -> Val {
+                        Val::Float(v)
+                    }
                 }
+
+                impl From<bool> for Val {
+                    fn from(v: bool) -> Val {
+                        Val::Bool(v)
+                    }
+                }
+
+                impl From<&String> for Val {
+                    fn from(v: &String) -> Val {
+                        Val::Str(v.to_string())
+                    }
+                }
+
+                impl serde::Serialize for Val {
+                    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+                    where
+                        S: serde::Serializer,
+                    {
+                        match self {
+                            Self::Int(v) => serializer.serialize_u64(*v),
+                            Self::Float(v) => serializer.serialize_f64(*v),
+                            Self::Str(v) => serializer.serialize_str(v),
+                            Self::Bool(v) => serializer.serialize_bool(*v),
+                        }
+                    }
+                }
+
+                let included = |rule: &str| rules.map_or(true, |rules| rules.contains(&rule));
+                let now = self.now;
+                let len = [#((#rule_strs, self.#rule_names.2)),*].into_iter().filter(|(rule, public)| *public && included(rule)).count();
+                let mut map = serializer.serialize_map(Some(len))?;
+                #(#rules_serde)*
+                map.end()
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for #hit {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                self.serialize_filtered(serializer, None)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for #hit_rules<'_> {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                self.hit.serialize_filtered(serializer, Some(self.rules))
             }
         }
+    })
+}
+
+/// Input names that likely contain values which shouldn't be sent back to clients.
+const SENSITIVE_INPUTS: &[&str] = &[
+    "token", "session", "email", "password", "secret", "auth", "cookie",
+];
 
+/// Generates a deprecation warning if a public rule exposes a key containing a potentially sensitive input.
+/// Procedural macros can't emit warnings on stable, so this uses a deprecated item to make the compiler do it.
+fn impl_key_warning(rule: &Rule) -> Option<TokenStream> {
+    if rule.key.is_empty() && !rule.global {
+        let note = format!(
+            "the rule '{}' has an empty key, so every request shares one bucket, use `for global` \
+             to make the global limit explicit",
+            rule.name
+        );
+        return Some(quote! {
+            const _: () = {
+                #[deprecated(note = #note)]
+                const EMPTY_KEY: () = ();
+                EMPTY_KEY
+            };
+        });
+    }
+    if !rule.public || rule.hide_key {
+        return None;
+    }
+    let input = rule.inputs().find(|k| {
+        let k = k.to_lowercase();
+        SENSITIVE_INPUTS.iter().any(|s| k.contains(s))
+    })?;
+    let note = format!(
+        "the key of the public rule '{}' contains the input '{}' and is sent to clients in the \
+         X-RateLimit-Key header, add `hide key` to the rule to omit it",
+        rule.name, input
+    );
+    Some(quote! {
+        const _: () = {
+            #[deprecated(note = #note)]
+            const SENSITIVE_KEY: () = ();
+            SENSITIVE_KEY
+        };
     })
 }
 
-fn impl_rule(rule: &Rule, async_store: bool) -> TokenStream {
+/// The part of a key as written in the macro, the inputs standing for `header("Name")` parts are shown as the part.
+fn display_part<'a>(input: &'a str, header_parts: &'a [HeaderPart]) -> &'a str {
+    header_parts
+        .iter()
+        .find(|h| h.input == input)
+        .map_or(input, |h| h.part.as_str())
+}
+
+/// Generates the `ceiling::RuleInfo` listed in the `RULES` constant for a rule.
+fn impl_rule_info(rule: &Rule, header_parts: &[HeaderPart]) -> TokenStream {
     let Rule {
         name,
-        limit,
-        interval,
-        timeout,
+        windows,
         key,
+        global,
         public,
+        jitter,
+        cost,
+        cost_input,
+        hide_key,
+        leaky,
+        aligned,
+        extend,
+        tags,
+        distinct,
+        count,
+        reason,
+        grace,
+        forgive,
+        headers: _,
+        template: _,
     } = rule;
-    let name = syn::parse_str::<syn::Ident>(name).unwrap();
-    let key = key
+    let windows = windows.iter().map(
+        |rate_limiter_input::Window {
+             limit,
+             interval,
+             timeout,
+         }| quote!(ceiling::Rule::new(#limit, #interval, #timeout)),
+    );
+    let display = |part: &Vec<String>| {
+        let part = part.iter().map(|k| display_part(k, header_parts));
+        quote!(&[#(#part),*])
+    };
+    let key = key.iter().map(display);
+    let distinct = distinct.iter().map(display);
+    let tags = tags.iter().map(|(k, v)| quote!((#k, #v)));
+    let reason = match reason {
+        Some(reason) => quote!(Some(#reason)),
+        None => quote!(None),
+    };
+    let cost_input = match cost_input {
+        Some(input) => quote!(Some(#input)),
+        None => quote!(None),
+    };
+    quote! {
+        ceiling::RuleInfo {
+            name: #name,
+            windows: std::borrow::Cow::Borrowed(&[#(#windows),*]),
+            key: &[#(#key),*],
+            global: #global,
+            public: #public,
+            cost: #cost,
+            cost_input: #cost_input,
+            jitter: #jitter,
+            leaky: #leaky,
+            aligned: #aligned,
+            extend: #extend,
+            count: #count,
+            hide_key: #hide_key,
+            tags: &[#(#tags),*],
+            distinct: &[#(#distinct),*],
+            reason: #reason,
+            grace: #grace,
+            forgive: #forgive,
+        }
+    }
+}
+
+/// Generates the implementation of `ceiling::governor::KeyedLimiter` for rate limiters with a synchronous store and one input,
+/// or `ceiling::governor::DirectLimiter` for those without inputs.
+fn impl_governor_limiter(
+    name: &Ident,
+    inputs: &[String],
+    async_store: bool,
+    impl_generics: &TokenStream,
+    type_generics: &TokenStream,
+) -> TokenStream {
+    let (limiter, method, params, args) = match inputs.len() {
+        0 => (quote!(DirectLimiter), quote!(check), quote!(), quote!()),
+        1 => (
+            quote!(KeyedLimiter),
+            quote!(check_key),
+            quote!(key: &str),
+            quote!(key),
+        ),
+        _ => return quote!(),
+    };
+    if async_store {
+        return quote!();
+    }
+    quote! {
+        #[cfg(feature = "governor-compat")]
+        impl #impl_generics ceiling::governor::#limiter for #name #type_generics {
+            fn #method(&self, #params) -> Result<(), ceiling::governor::NotUntil> {
+                let (limited, hit) = self.hit(#args);
+                if !limited {
+                    return Ok(());
+                }
+                Err(ceiling::governor::NotUntil::new(hit.now, hit.to_error_body()))
+            }
+        }
+    }
+}
+
+/// Generates the implementation of `ceiling::DynLimiter` for rate limiters with a synchronous store.
+fn impl_dyn_limiter(
+    name: &Ident,
+    inputs: &[String],
+    rules: &[Rule],
+    async_store: bool,
+    impl_generics: &TokenStream,
+    type_generics: &TokenStream,
+) -> TokenStream {
+    if async_store {
+        return quote!();
+    }
+    let num_inputs = inputs.len();
+    let inputs = inputs
         .iter()
-        .map(|k| syn::parse_str::<syn::Ident>(format!("{k}_input").as_str()).unwrap())
+        .map(|i| syn::parse_str::<Ident>(&format!("{i}_input")).unwrap())
         .collect::<Vec<_>>();
-    let key = if key.is_empty() {
-        quote!("".to_string())
+    let states = rules.iter().map(|r| {
+        let (rule, field) = (&r.name, syn::parse_str::<Ident>(&r.name).unwrap());
+        quote! {
+            ceiling::RuleState {
+                name: #rule,
+                remaining: hit.#field.0,
+                reset: hit.#field.1,
+                public: hit.#field.2,
+                key: hit.#field.3,
+                limit: hit.#field.4,
+                interval: hit.#field.5,
+                timeout: hit.#field.6,
+            }
+        }
+    });
+    quote! {
+        impl #impl_generics ceiling::DynLimiter for #name #type_generics {
+            fn hit_dyn(&self, inputs: &[&str]) -> Result<(bool, Vec<ceiling::RuleState>), ceiling::InputCountError> {
+                let [#(#inputs),*] = inputs else {
+                    return Err(ceiling::InputCountError {
+                        expected: #num_inputs,
+                        found: inputs.len(),
+                    });
+                };
+                let (limited, hit) = self.hit(#(#inputs),*);
+                Ok((limited, vec![#(#states),*]))
+            }
+        }
+    }
+}
+
+/// Generates the implementation of `ceiling::tonic::GrpcLimiter` for rate limiters with a synchronous store and two inputs,
+/// the first being the peer address and the second the method path.
+fn impl_grpc_limiter(
+    name: &Ident,
+    inputs: &[String],
+    async_store: bool,
+    impl_generics: &TokenStream,
+    type_generics: &TokenStream,
+) -> TokenStream {
+    if async_store || inputs.len() != 2 {
+        return quote!();
+    }
+    quote! {
+        #[cfg(feature = "tonic")]
+        impl #impl_generics ceiling::tonic::GrpcLimiter for #name #type_generics {
+            fn hit_grpc(
+                &self,
+                peer: &str,
+                method: &str,
+            ) -> (bool, Vec<(&'static str, String)>, Option<ceiling::RateLimitError>) {
+                let (limited, hit) = self.hit(peer, method);
+                (limited, hit.to_headers(), hit.to_error_body())
+            }
+        }
+    }
+}
+
+/// Generates a method returning the requests remaining for a single rule, taking only the inputs in the rule's key.
+fn impl_rule_remaining(
+    inputs: &[String],
+    header_parts: &[HeaderPart],
+    rule: &Rule,
+    offset: usize,
+    separator: &str,
+    on_empty: &OnEmpty,
+    async_store: bool,
+) -> Result<TokenStream> {
+    let method = syn::parse_str::<Ident>(&format!("{}_remaining", rule.name))?;
+    let key = impl_keys(
+        inputs,
+        header_parts,
+        std::slice::from_ref(rule),
+        separator,
+        on_empty,
+        false,
+    )?;
+    let header_param = if header_parts
+        .iter()
+        .any(|h| rule.inputs().any(|k| *k == h.input))
+    {
+        quote!(headers: &(impl ceiling::HeaderLookup + ?Sized),)
     } else {
-        let lit = key.iter().map(|_| "{}").collect::<Vec<_>>().join("+");
-        quote!(format!(#lit, #(#key),*))
+        quote!()
     };
-    let get = if async_store {
-        quote!(self.#name.get(&key).await)
+    let inputs = inputs.iter().filter(|i| rule.inputs().any(|k| k == *i));
+    let input_type_params = inputs
+        .clone()
+        .map(|i| syn::parse_str::<Ident>(&format!("{}_IN", i.to_uppercase())).unwrap())
+        .collect::<Vec<_>>();
+    let input_params = inputs
+        .zip(&input_type_params)
+        .map(|(i, t)| {
+            let i = syn::parse_str::<Ident>(&format!("{i}_input")).unwrap();
+            quote!(#i: #t)
+        })
+        .collect::<Vec<_>>();
+    let remaining = impl_remaining(rule, offset, async_store);
+    let (async_fn, use_store) = if async_store {
+        (
+            quote!(async),
+            quote!(
+                use ceiling::AsyncStore;
+            ),
+        )
     } else {
-        quote!(self.#name.get(&key))
+        (
+            quote!(),
+            quote!(
+                use ceiling::SyncStore;
+            ),
+        )
     };
-    let set = if async_store {
-        quote!(self.#name.set(&key, #name, reset_updated).await)
+    let doc = format!(
+        "Reads the requests remaining for the rule `{}` without hitting it or locking its buckets, \
+         taking only the inputs in the rule's key.",
+        rule.name
+    );
+    Ok(quote! {
+        #[doc = #doc]
+        pub #async_fn fn #method<#(#input_type_params),*>(&self, #(#input_params,)* #header_param) -> u32
+        where
+            #(#input_type_params: std::fmt::Display),*
+        {
+            #use_store
+
+            let now = self.now();
+            #key
+            #remaining
+        }
+    })
+}
+
+/// Generates a method removing the buckets of every rule whose key matches the inputs given, inputs that
+/// are `None` match any value. A rule whose key doesn't contain any of the inputs given is left alone.
+fn impl_clear_buckets(
+    inputs: &[String],
+    header_parts: &[HeaderPart],
+    rules: &[Rule],
+    offsets: &[usize],
+    separator: &str,
+    on_empty: &OnEmpty,
+    async_store: bool,
+) -> Result<TokenStream> {
+    let params = inputs
+        .iter()
+        .map(|i| syn::parse_str::<Ident>(&format!("{i}_input")))
+        .collect::<Result<Vec<_>>>()?;
+    let (async_fn, await_store, use_store) = if async_store {
+        (
+            quote!(async),
+            quote!(.await),
+            quote!(
+                use ceiling::AsyncStore;
+            ),
+        )
     } else {
-        quote!(self.#name.set(&key, #name, reset_updated))
+        (
+            quote!(),
+            quote!(),
+            quote!(
+                use ceiling::SyncStore;
+            ),
+        )
     };
-    let prune = if async_store {
-        quote!(self.#name.prune(now).await)
-    } else {
-        quote!(self.#name.prune(now))
+    let clears = rules.iter().zip(offsets).map(|(r, offset)| {
+        let store = syn::parse_str::<Ident>(&r.name).unwrap();
+        // the values of `header("Name")` parts can't be given, so they match any value
+        let parts = r.key.iter().map(|part| {
+            let part = part.iter().map(|k| {
+                if header_parts.iter().any(|h| h.input == *k) {
+                    return quote!(None);
+                }
+                let input = syn::parse_str::<Ident>(&format!("{k}_input")).unwrap();
+                // empty parts are stored under the placeholder
+                match on_empty {
+                    OnEmpty::Placeholder(placeholder) => {
+                        quote!(#input.map(|value| if value.is_empty() { #placeholder } else { value }))
+                    },
+                    _ => quote!(#input),
+                }
+            });
+            quote!(&[#(#part),*])
+        });
+        // the buckets of a rule with several windows are suffixed with the window's index, and after their escaped key,
+        // the buckets marking the values seen by a distinct rule with the value and those holding the last limited hit of a forgiving rule with `last`
+        let escaped = !r.distinct.is_empty() || r.forgive > 0;
+        let strip_window = if escaped {
+            quote! {
+                let key = ceiling::unescape_key(key);
+                let key = key.as_ref();
+            }
+        } else if r.windows.len() > 1 {
+            quote!(let key = key.rsplit_once('#').map_or(key, |(key, _)| key);)
+        } else {
+            quote!()
+        };
+        let escape_prefix = if escaped {
+            quote!(let prefix = ceiling::escape_key(prefix);)
+        } else {
+            quote!()
+        };
+        // the capacity resolved for a leaky rule's bucket is cached by the resolver and resolved again after the bucket is removed
+        let forget = if r.leaky && r.windows.len() == 1 {
+            quote! {
+                if let Some(resolver) = &self.__ceiling.capacity_resolvers[#offset] {
+                    resolver.forget(&key);
+                }
+            }
+        } else {
+            quote!()
+        };
+        quote! {
+            let parts: &[&[Option<&str>]] = &[#(#parts),*];
+            if parts.iter().any(|p| p.iter().any(Option::is_some)) {
+                // the leading parts that are known narrow down the keys listed by the store
+                let prefix = parts
+                    .iter()
+                    .map_while(|p| match p {
+                        [Some(value)] => Some(*value),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join(#separator);
+                #escape_prefix
+                for key in self.#store.keys_with_prefix(&prefix)#await_store {
+                    let matches = {
+                        let key = key.as_str();
+                        #strip_window
+                        let values = key.split(#separator).collect::<Vec<_>>();
+                        values.len() == parts.len()
+                            && values.iter().zip(parts).all(|(value, part)| {
+                                part.iter().all(Option::is_none) || part.contains(&Some(*value))
+                            })
+                    };
+                    if matches {
+                        self.#store.remove(&key)#await_store;
+                        #forget
+                        removed += 1;
+                    }
+                }
+            }
+        }
+    });
+    Ok(quote! {
+        /// Removes the buckets of every rule whose key matches the inputs given, e.g. to reset a client across every rule.
+        /// Inputs that are `None` match any value, so `Some(ip)` with the other inputs `None` clears every bucket for the IP
+        /// regardless of the other inputs in the rules' keys. Rules whose keys contain none of the inputs given are left alone.
+        /// An empty input matches the parts replaced by the `on_empty` placeholder.
+        /// The buckets are listed with `keys_with_prefix`, so this is O(n) in the size of the stores and values containing the key separator may not match.
+        /// Returns the number of buckets removed.
+        #[allow(unused_variables)]
+        pub #async_fn fn clear_buckets(&self, #(#params: Option<&str>),*) -> usize {
+            #use_store
+
+            let mut removed = 0;
+            #(
+                {
+                    #clears
+                }
+            )*
+            removed
+        }
+    })
+}
+
+/// Generates the keys of every rule, each input is formatted once into a thread-local buffer that's
+/// reused between hits and the keys are joined from slices of it.
+/// With `values` the values counted by distinct rules and the costs of rules with a cost input are also generated,
+/// as `<rule>_distinct` and `<rule>_cost`.
+fn impl_keys(
+    inputs: &[String],
+    header_parts: &[HeaderPart],
+    rules: &[Rule],
+    separator: &str,
+    on_empty: &OnEmpty,
+    values: bool,
+) -> Result<TokenStream> {
+    // the inputs standing for `header("Name")` parts are read from the headers before the keys are built
+    let inputs = inputs
+        .iter()
+        .chain(header_parts.iter().map(|h| &h.input))
+        .cloned()
+        .collect::<Vec<_>>();
+    for rule in rules {
+        if let Some(part) = rule
+            .inputs()
+            .chain(rule.distinct.iter().flatten())
+            .chain(&rule.cost_input)
+            .find(|k| !inputs.contains(k))
+        {
+            return Err(syn::Error::new(
+                proc_macro2::Span::call_site(),
+                format!(
+                    "unknown input '{}' in the key of rule '{}'",
+                    part, rule.name
+                ),
+            ));
+        }
+    }
+    // the values counted by distinct rules are written to the buffer like the parts of a key
+    let distinct_rules = rules
+        .iter()
+        .filter(|r| values && !r.distinct.is_empty())
+        .collect::<Vec<_>>();
+    let cost_rules = rules
+        .iter()
+        .filter(|r| values && r.cost_input.is_some())
+        .collect::<Vec<_>>();
+    let used = inputs
+        .iter()
+        .filter(|i| {
+            rules.iter().any(|r| r.inputs().any(|k| k == *i))
+                || distinct_rules
+                    .iter()
+                    .any(|r| r.distinct.iter().flatten().any(|k| k == *i))
+                || cost_rules.iter().any(|r| r.cost_input.as_ref() == Some(*i))
+        })
+        .collect::<Vec<_>>();
+    let mut start = quote!(0);
+    let parts = used.iter().map(|i| {
+        let input = syn::parse_str::<syn::Ident>(format!("{i}_input").as_str()).unwrap();
+        let part = syn::parse_str::<syn::Ident>(format!("{i}_part").as_str()).unwrap();
+        let end = syn::parse_str::<syn::Ident>(format!("{i}_end").as_str()).unwrap();
+        let slice = quote!(let #part = &buffer[#start..#end];);
+        start = quote!(#end);
+        (
+            quote! {
+                let _ = write!(buffer, "{}", #input);
+                let #end = buffer.len();
+            },
+            slice,
+        )
+    });
+    let (writes, slices): (Vec<_>, Vec<_>) = parts.unzip();
+    // a missing header without a fallback is an empty part, the hit methods check for it to limit the request
+    let lookups = header_parts
+        .iter()
+        .filter(|h| used.contains(&&h.input))
+        .map(|h| {
+            let lookup = syn::parse_str::<syn::Ident>(&h.input).unwrap();
+            let input = syn::parse_str::<syn::Ident>(&format!("{}_input", h.input)).unwrap();
+            let (name, fallback) = (&h.name, h.fallback.as_deref().unwrap_or_default());
+            quote! {
+                let #lookup = ceiling::HeaderLookup::header(headers, #name);
+                let #input = #lookup.unwrap_or(#fallback);
+            }
+        });
+    let join = |key: &[Vec<String>]| {
+        let parts = key.iter().map(|part| {
+            let inputs = part
+                .iter()
+                .map(|k| syn::parse_str::<syn::Ident>(format!("{k}_part").as_str()).unwrap())
+                .collect::<Vec<_>>();
+            // `coalesce(...)` takes the first input that isn't empty, falling back to the last input
+            let (last, rest) = inputs.split_last().unwrap();
+            let value = quote!(#(if !#rest.is_empty() { #rest } else)* { #last });
+            match on_empty {
+                OnEmpty::Placeholder(placeholder) => quote! {{
+                    let part = #value;
+                    if part.is_empty() { #placeholder } else { part }
+                }},
+                _ => value,
+            }
+        });
+        // a key with a single part is copied straight from the buffer instead of joining a slice of one
+        if key.len() == 1 {
+            quote!(String::from(#(#parts)*))
+        } else {
+            quote!([#(#parts),*].join(#separator))
+        }
     };
-    quote! {
-        let #name = {
-            let key = #key;
-            let lock = #get;
-            let mut #name = (*lock).unwrap_or((#limit, now + (#interval as u64)));
-            let mut reset_updated = false;
-            if #name.1 < now {
-                #name = (#limit, now + (#interval as u64));
-                reset_updated = true;
-            }
-            if #name.0 > 1 {
-                #name.0 -= 1;
-                #set;
-            } else if #name.0 == 1 {
-                #name = (0, now + (#timeout as u64));
-                reset_updated = true;
-                #set;
-                hit = true;
+    // with `on_empty deny` the hit methods also get whether each rule's key has an empty part
+    let empty_rules = rules
+        .iter()
+        .filter(|r| values && matches!(on_empty, OnEmpty::Deny) && !r.global && !r.key.is_empty())
+        .collect::<Vec<_>>();
+    let key_names =
+        rules
+            .iter()
+            .map(|r| syn::parse_str::<syn::Ident>(format!("{}_key", r.name).as_str()).unwrap())
+            .chain(distinct_rules.iter().map(|r| {
+                syn::parse_str::<syn::Ident>(format!("{}_distinct", r.name).as_str()).unwrap()
+            }))
+            .chain(cost_rules.iter().map(|r| {
+                syn::parse_str::<syn::Ident>(format!("{}_cost", r.name).as_str()).unwrap()
+            }))
+            .chain(empty_rules.iter().map(|r| {
+                syn::parse_str::<syn::Ident>(format!("{}_empty", r.name).as_str()).unwrap()
+            }))
+            .collect::<Vec<_>>();
+    let keys = rules
+        .iter()
+        .map(|r| {
+            if r.global {
+                let name = &r.name;
+                quote!(String::from(#name))
+            } else if r.key.is_empty() {
+                quote!(String::new())
+            } else if !r.distinct.is_empty() || r.forgive > 0 {
+                // the buckets marking the values seen or the last limited hit are suffixed to the key, which is escaped so they can't collide
+                let key = join(&r.key);
+                quote!(ceiling::escape_key(#key))
             } else {
-                hit = true;
+                join(&r.key)
+            }
+        })
+        .chain(distinct_rules.iter().map(|r| join(&r.distinct)))
+        .chain(cost_rules.iter().map(|r| {
+            let part = syn::parse_str::<syn::Ident>(
+                format!("{}_part", r.cost_input.as_ref().unwrap()).as_str(),
+            )
+            .unwrap();
+            quote!(ceiling::parse_cost(#part))
+        }))
+        .chain(empty_rules.iter().map(|r| {
+            // a `coalesce(...)` part is only empty if all of its inputs are
+            let parts = r.key.iter().map(|part| {
+                let inputs = part
+                    .iter()
+                    .map(|k| syn::parse_str::<syn::Ident>(format!("{k}_part").as_str()).unwrap());
+                quote!((#(#inputs.is_empty())&&*))
+            });
+            quote!(#(#parts)||*)
+        }))
+        .collect::<Vec<_>>();
+    Ok(quote! {
+        #(#lookups)*
+        let (#(#key_names,)*) = {
+            thread_local! {
+                static KEY_BUFFER: std::cell::RefCell<String> = std::cell::RefCell::new(String::new());
             }
-            drop(lock);
-            #prune;
-            (#name.0, #name.1, #public, key)
+            KEY_BUFFER.with(|buffer| {
+                use std::fmt::Write;
+
+                let mut buffer = buffer.borrow_mut();
+                buffer.clear();
+                #(#writes)*
+                #(#slices)*
+                (#(#keys,)*)
+            })
         };
-    }
+    })
 }
 
 /// `group!` is a utility macro for grouping multiple values into a single key
@@ -356,25 +2830,39 @@ fn impl_rule(rule: &Rule, async_store: bool) -> TokenStream {
 ///         "/one", "/two";
 ///     }
 /// }
+/// assert_eq!(bucket("/help"), bucket("/help2"));
+/// ```
+/// ```
+/// // groups can be split across modules by generating them as constants instead, optionally with a visibility
+/// // the constants are combined into a single function with `group_fn!`
+/// ceiling::group! {
+///     pub const HELP_ROUTES {
+///         "/help", "/help2", "/help3";
+///     }
+/// }
 /// ```
 #[proc_macro]
 pub fn group(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     impl_group(parse_macro_input!(input as GroupInput))
-        .unwrap()
+        .unwrap_or_else(|e| e.to_compile_error())
         .into()
 }
 
-fn impl_group(GroupInput { name, groups }: GroupInput) -> Result<TokenStream> {
+fn impl_group(
+    GroupInput {
+        name,
+        groups,
+        constant,
+    }: GroupInput,
+) -> Result<TokenStream> {
+    if let Some(visibility) = constant {
+        let name = syn::parse_str::<Ident>(&name)?;
+        return Ok(quote! {
+            #visibility const #name: &[&[&str]] = &[#(&[#(#groups),*]),*];
+        });
+    }
     let groups = groups.into_iter().enumerate().map(|(i, g)| {
-        let s = syn::parse_str::<LitStr>(
-            format!(
-                "\"__{}-{}\"",
-                i,
-                rand::distributions::Alphanumeric.sample_string(&mut rand::thread_rng(), 20),
-            )
-            .as_str(),
-        )
-        .unwrap();
+        let s = LitStr::new(&group_key(i, &g), proc_macro2::Span::call_site());
         quote! {
             #(
                 #g => #s,
@@ -392,3 +2880,61 @@ fn impl_group(GroupInput { name, groups }: GroupInput) -> Result<TokenStream> {
     };
     Ok(gen)
 }
+
+/// The shared bucket key of a group, its index and a 64 bit FNV-1a hash of its values, so the key is the same across builds
+/// and instances sharing a store. Each value is hashed with a zero byte after it, so e.g. `"ab", "c"` and `"a", "bc"` differ.
+fn group_key(index: usize, values: &[String]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in values.iter().flat_map(|v| v.bytes().chain([0])) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("__{index}-{hash:016x}")
+}
+
+/// `group_fn!` combines groups generated as constants by `group!`, which may be defined in different modules, into a single function.
+/// The function behaves like one generated by `group!` but returns the first value of a matching group as the shared bucket key,
+/// if a value is in several groups the first one listed wins.
+/// The groups are searched in order so this is O(n) in the total number of values.
+///
+/// # Example
+/// ```
+/// # ceiling::group! { const HELP_ROUTES { "/help", "/help2"; } }
+/// # mod admin { ceiling::group! { pub const ADMIN_ROUTES { "/admin", "/admin/users"; } } }
+/// // `bucket("/help2")` returns "/help" and `bucket("/admin/users")` returns "/admin"
+/// ceiling::group_fn! {
+///     pub bucket(HELP_ROUTES, crate::admin::ADMIN_ROUTES)
+/// }
+/// # fn main() {
+/// # assert_eq!(bucket("/help2"), "/help");
+/// # assert_eq!(bucket("/admin/users"), "/admin");
+/// # }
+/// ```
+#[proc_macro]
+pub fn group_fn(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    impl_group_fn(parse_macro_input!(input as GroupFnInput))
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+fn impl_group_fn(
+    GroupFnInput {
+        visibility,
+        name,
+        groups,
+    }: GroupFnInput,
+) -> Result<TokenStream> {
+    let name = syn::parse_str::<Ident>(&name)?;
+    Ok(quote! {
+        #visibility fn #name(value: &str) -> &str {
+            for groups in [#(#groups),*] {
+                for group in groups {
+                    if group.contains(&value) {
+                        return group[0];
+                    }
+                }
+            }
+            value
+        }
+    })
+}
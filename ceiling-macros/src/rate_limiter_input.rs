@@ -1,32 +1,110 @@
 use syn::{
-    braced,
-    parse::{Parse, ParseBuffer, ParseStream},
-    Ident, Result, Token,
+    braced, bracketed, parenthesized,
+    parse::{Parse, ParseBuffer, ParseStream, Parser},
+    Ident, LitInt, LitStr, Result, Token, Visibility,
 };
 
 use crate::generic_input::{
-    expected_arbitrary_ident, expected_duration, expected_ident, expected_ident_or_nothing,
-    expected_int, expected_path, expected_token, expected_token_or_nothing,
+    checked_mul, expected_arbitrary_ident, expected_duration, expected_ident, expected_int,
+    expected_path, expected_token, expected_token_or_nothing,
 };
 
 pub struct RateLimiterInput {
     pub inputs: Vec<String>,
     pub rules: Vec<Rule>,
     pub name: String,
+    /// The visibility of the generated structs, `pub` if none is given.
+    pub visibility: Visibility,
     pub store: Option<String>,
     pub async_store: bool,
+    pub generic: Option<String>,
+    pub headers: HeaderNames,
+    pub on_error: Option<OnError>,
+    pub on_empty: OnEmpty,
+    pub rounding: Rounding,
+    /// The separator between the parts of a key, `+` if none is given.
+    pub separator: String,
+    /// Whether the stores are shared between clones with `Rc` instead of `Arc`, given with `single_threaded`.
+    pub single_threaded: bool,
+    /// Whether the stores are kept behind `ceiling::ErasedStore` instead of as the store's type, given with `erased`.
+    pub erased: bool,
+    /// The name and type of the context the inputs are read from, given as `ctx.field` inputs and `context Type`.
+    pub context: Option<(String, String)>,
+    /// The `header("Name")` parts of the rules' keys, the parts in the keys are replaced by the inputs standing for them.
+    pub header_parts: Vec<HeaderPart>,
+    /// The absolute path of the file the rules were included from, if any.
+    pub include: Option<String>,
+}
+
+/// What the generated `try_hit` method does when the store errors.
+#[derive(Clone, Copy)]
+pub enum OnError {
+    /// Fail open, the rule is treated as not limited.
+    Allow,
+    /// Fail closed, the rule is treated as limited.
+    Deny,
+}
+
+/// What happens to a part of a key whose inputs are all empty, which would otherwise share buckets with every other request missing it.
+#[derive(Clone)]
+pub enum OnEmpty {
+    /// The part is left empty.
+    Allow,
+    /// The rule limits the request in the hit methods without touching its buckets, and is reported as exhausted.
+    Deny,
+    /// The part is replaced by the placeholder.
+    Placeholder(String),
+}
+
+impl Default for OnEmpty {
+    fn default() -> Self {
+        Self::Placeholder(String::from("<empty>"))
+    }
+}
+
+/// How the seconds until a bucket resets are rounded in the generated hit methods.
+#[derive(Clone, Copy, Default)]
+pub enum Rounding {
+    /// The whole seconds until the reset, a client waiting this long may retry in the second the bucket resets and still be limited.
+    #[default]
+    Down,
+    /// The whole seconds until the bucket has reset, a client waiting this long from any point in the current second won't be limited.
+    Up,
 }
 
 impl Parse for RateLimiterInput {
     fn parse(mut input: ParseStream) -> Result<Self> {
-        let inputs = Self::parse_inputs(&mut input)?;
-        let mut body;
-        braced!(body in input);
-        let rules = Self::parse_body(&mut body)?;
+        let (inputs, base) = Self::parse_inputs(&mut input)?;
+        let span = input.span();
+        let (mut rules, include) = if input.peek(Ident) && input.peek2(Token![!]) {
+            let (rules, path) = Self::parse_include(&mut input)?;
+            (rules, Some(path))
+        } else {
+            let body;
+            braced!(body in input);
+            (Self::parse_body(&body)?, None)
+        };
+        // templates aren't rules themselves
+        if rules.is_empty() {
+            return Err(syn::Error::new(span, "expected at least one rule"));
+        }
 
         expected_token(&mut input, Token![as])?;
         input.parse::<Token![as]>()?;
+        let visibility = match input.parse::<Visibility>()? {
+            Visibility::Inherited => syn::parse_quote!(pub),
+            visibility => visibility,
+        };
         let name = expected_arbitrary_ident(&mut input)?;
+        let generic = if expected_token_or_nothing(&mut input, Token![<]) {
+            input.parse::<Token![<]>()?;
+            let generic = expected_arbitrary_ident(&mut input)?;
+            expected_token(&mut input, Token![>])?;
+            input.parse::<Token![>]>()?;
+            Some(generic)
+        } else {
+            None
+        };
         let async_store = expected_token_or_nothing(&mut input, Token![async]);
         if async_store {
             input.parse::<Token![async]>()?;
@@ -37,19 +115,268 @@ impl Parse for RateLimiterInput {
         } else {
             None
         };
+        let mut headers = HeaderNames::default();
+        let mut on_error = None;
+        let mut on_empty = OnEmpty::default();
+        let mut rounding = Rounding::default();
+        let mut separator = String::from("+");
+        let mut single_threaded = false;
+        let mut context = None;
+        let mut erased = false;
+        while !input.is_empty() {
+            let lookahead = input.lookahead1();
+            if !lookahead.peek(Ident) {
+                return Err(lookahead.error());
+            }
+            let option = input.parse::<Ident>()?;
+            match option.to_string().as_str() {
+                "headers" => {
+                    let stream;
+                    braced!(stream in input);
+                    headers.parse_overrides(&stream)?;
+                },
+                "on_error" => {
+                    let lookahead = input.lookahead1();
+                    if !lookahead.peek(Ident) {
+                        return Err(lookahead.error());
+                    }
+                    let policy = input.parse::<Ident>()?;
+                    on_error = Some(match policy.to_string().as_str() {
+                        "allow" => OnError::Allow,
+                        "deny" => OnError::Deny,
+                        _ => {
+                            return Err(syn::Error::new(
+                                policy.span(),
+                                "expected 'allow' or 'deny'",
+                            ))
+                        },
+                    });
+                },
+                "on_empty" => {
+                    let lookahead = input.lookahead1();
+                    on_empty = if lookahead.peek(LitStr) {
+                        let lit = input.parse::<LitStr>()?;
+                        if lit.value().is_empty() || lit.value().contains('#') {
+                            return Err(syn::Error::new(
+                                lit.span(),
+                                "the placeholder must be non-empty and can't contain '#'",
+                            ));
+                        }
+                        OnEmpty::Placeholder(lit.value())
+                    } else if lookahead.peek(Ident) {
+                        let policy = input.parse::<Ident>()?;
+                        match policy.to_string().as_str() {
+                            "allow" => OnEmpty::Allow,
+                            "deny" => OnEmpty::Deny,
+                            _ => {
+                                return Err(syn::Error::new(
+                                    policy.span(),
+                                    "expected 'allow', 'deny', or a placeholder",
+                                ))
+                            },
+                        }
+                    } else {
+                        return Err(lookahead.error());
+                    };
+                },
+                "rounding" => {
+                    let lookahead = input.lookahead1();
+                    if !lookahead.peek(Ident) {
+                        return Err(lookahead.error());
+                    }
+                    let mode = input.parse::<Ident>()?;
+                    rounding = match mode.to_string().as_str() {
+                        "up" => Rounding::Up,
+                        "down" => Rounding::Down,
+                        _ => return Err(syn::Error::new(mode.span(), "expected 'up' or 'down'")),
+                    };
+                },
+                "separator" => {
+                    let lookahead = input.lookahead1();
+                    if !lookahead.peek(LitStr) {
+                        return Err(lookahead.error());
+                    }
+                    let lit = input.parse::<LitStr>()?;
+                    separator = lit.value();
+                    // `#` separates a key from the index of a window or a value seen by a distinct rule
+                    if separator.is_empty() || separator.contains('#') {
+                        return Err(syn::Error::new(
+                            lit.span(),
+                            "the separator must be non-empty and can't contain '#'",
+                        ));
+                    }
+                },
+                "single_threaded" => single_threaded = true,
+                "erased" => {
+                    if generic.is_some() {
+                        return Err(syn::Error::new(
+                            option.span(),
+                            "an erased rate limiter can't be generic over its store",
+                        ));
+                    }
+                    if async_store {
+                        return Err(syn::Error::new(
+                            option.span(),
+                            "only synchronous stores can be erased",
+                        ));
+                    }
+                    erased = true;
+                },
+                "context" => {
+                    let Some(base) = &base else {
+                        return Err(syn::Error::new(
+                            option.span(),
+                            "a context needs inputs read from it, e.g. `req.ip in { ... }`",
+                        ));
+                    };
+                    context = Some((base.to_string(), expected_path(&mut input)?));
+                },
+                _ => return Err(syn::Error::new(
+                    option.span(),
+                    "expected 'context', 'erased', 'headers', 'on_empty', 'on_error', 'rounding', 'separator', or 'single_threaded'",
+                )),
+            }
+        }
+        if let (Some(base), None) = (&base, &context) {
+            return Err(syn::Error::new(
+                base.span(),
+                format!("inputs read from '{base}' need its type, given with `context Type` after the name"),
+            ));
+        }
+        // the same header part in several rules stands for a single input, read from the headers once per hit
+        let mut header_parts = Vec::<HeaderPart>::new();
+        for rule in &mut rules {
+            for header in std::mem::take(&mut rule.headers) {
+                if !header_parts.iter().any(|h| h.part == header.part) {
+                    header_parts.push(HeaderPart {
+                        input: format!("__header_{}", header_parts.len()),
+                        ..header
+                    });
+                }
+            }
+            for part in rule.key.iter_mut().chain(&mut rule.distinct).flatten() {
+                if let Some(header) = header_parts.iter().find(|h| h.part == *part) {
+                    part.clone_from(&header.input);
+                }
+            }
+        }
         Ok(RateLimiterInput {
             inputs,
             rules,
             name,
+            visibility,
             store,
             async_store,
+            generic,
+            headers,
+            on_error,
+            on_empty,
+            rounding,
+            separator,
+            single_threaded,
+            erased,
+            context,
+            header_parts,
+            include,
         })
     }
 }
 
+/// A `header("Name")` part of a key, read from the headers given to the hit methods instead of an input.
+#[derive(Debug)]
+pub struct HeaderPart {
+    /// The part as written in the key, shown in the rule's configuration.
+    pub part: String,
+    /// The name of the input standing for the part in the keys, empty until the rate limiter is parsed.
+    pub input: String,
+    pub name: String,
+    /// The value used if the request doesn't have the header, without one the rules using the part limit the request.
+    pub fallback: Option<String>,
+}
+
+/// The names of the headers carrying each metadata attribute in the generated `to_headers` method.
+pub struct HeaderNames {
+    pub limit: String,
+    pub interval: String,
+    pub timeout: String,
+    pub remaining: String,
+    pub reset: String,
+    pub reset_after: String,
+    pub spacing: String,
+    pub key: String,
+    pub reason: String,
+    /// The header carrying the description of each rule in the `to_policy_headers` method.
+    pub policy: String,
+}
+
+impl Default for HeaderNames {
+    fn default() -> Self {
+        Self {
+            limit: "X-RateLimit-Limit".into(),
+            interval: "X-RateLimit-Interval".into(),
+            timeout: "X-RateLimit-Timeout".into(),
+            remaining: "X-RateLimit-Remaining".into(),
+            reset: "X-RateLimit-Reset".into(),
+            reset_after: "X-RateLimit-Reset-After".into(),
+            spacing: "X-RateLimit-Spacing".into(),
+            key: "X-RateLimit-Key".into(),
+            reason: "X-RateLimit-Reason".into(),
+            policy: "X-RateLimit-Policy".into(),
+        }
+    }
+}
+
+impl HeaderNames {
+    /// Parses a comma separated list of `attribute = "Header-Name"` overrides.
+    fn parse_overrides(&mut self, mut input: ParseStream) -> Result<()> {
+        while !input.is_empty() {
+            let lookahead = input.lookahead1();
+            if !lookahead.peek(Ident) {
+                return Err(lookahead.error());
+            }
+            let attribute = input.parse::<Ident>()?;
+            let header = match attribute.to_string().as_str() {
+                "limit" => &mut self.limit,
+                "interval" => &mut self.interval,
+                "timeout" => &mut self.timeout,
+                "remaining" => &mut self.remaining,
+                "reset" => &mut self.reset,
+                "reset_after" => &mut self.reset_after,
+                "spacing" => &mut self.spacing,
+                "key" => &mut self.key,
+                "reason" => &mut self.reason,
+                "policy" => &mut self.policy,
+                _ => {
+                    return Err(syn::Error::new(
+                        attribute.span(),
+                        "expected 'limit', 'interval', 'timeout', 'remaining', 'reset', \
+                         'reset_after', 'spacing', 'key', 'reason', or 'policy'",
+                    ))
+                },
+            };
+            expected_token(&mut input, Token![=])?;
+            input.parse::<Token![=]>()?;
+            let lookahead = input.lookahead1();
+            if !lookahead.peek(LitStr) {
+                return Err(lookahead.error());
+            }
+            *header = input.parse::<LitStr>()?.value();
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+        Ok(())
+    }
+}
+
 impl RateLimiterInput {
-    fn parse_inputs(input: &mut ParseStream) -> Result<Vec<String>> {
+    /// Parses the inputs before `in`, either plain names or fields read from a single context, e.g. `req.ip, req.path`.
+    /// Returns the names of the inputs, which are the fields for a context, and the context if any.
+    fn parse_inputs(input: &mut ParseStream) -> Result<(Vec<String>, Option<Ident>)> {
         let mut inputs = Vec::new();
+        // `None` until the first input, then whether the inputs are read from a context
+        let mut base: Option<Option<Ident>> = None;
         loop {
             let lookahead = input.lookahead1();
             if lookahead.peek(Token![in]) {
@@ -58,36 +385,135 @@ impl RateLimiterInput {
             } else if lookahead.peek(Token![,]) {
                 input.parse::<Token![,]>()?;
             } else if lookahead.peek(Ident) {
-                let ident = input.parse::<Ident>()?;
-                inputs.push(ident.to_string());
+                let span = input.span();
+                let name = expected_arbitrary_ident(input)?;
+                let accessor = if input.peek(Token![.]) {
+                    input.parse::<Token![.]>()?;
+                    inputs.push(expected_arbitrary_ident(input)?);
+                    Some(Ident::new(&name, span))
+                } else {
+                    inputs.push(name);
+                    None
+                };
+                match &base {
+                    None => base = Some(accessor),
+                    Some(base)
+                        if base.as_ref().map(Ident::to_string)
+                            != accessor.as_ref().map(Ident::to_string) =>
+                    {
+                        return Err(syn::Error::new(
+                            span,
+                            "the inputs must either all be plain names or all be fields of the same context",
+                        ));
+                    },
+                    Some(_) => {},
+                }
             } else {
-                lookahead.error();
+                return Err(lookahead.error());
             }
         }
-        Ok(inputs)
+        Ok((inputs, base.flatten()))
     }
 
-    fn parse_body(input: &mut ParseBuffer) -> Result<Vec<Rule>> {
-        Ok(input
-            .parse_terminated::<_, Token![;]>(Rule::parse)?
-            .into_iter()
-            .collect::<Vec<_>>())
+    /// Parses `include!("path")`, reading the rules from a file relative to the crate's root.
+    /// Returns the rules and the absolute path of the file.
+    fn parse_include(input: &mut ParseStream) -> Result<(Vec<Rule>, String)> {
+        expected_ident(input, "include")?;
+        input.parse::<Token![!]>()?;
+        let path;
+        parenthesized!(path in input);
+        let path = path.parse::<LitStr>()?;
+        let root = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+        let full_path = std::path::Path::new(&root).join(path.value());
+        let contents = std::fs::read_to_string(&full_path).map_err(|e| {
+            syn::Error::new(
+                path.span(),
+                format!("couldn't read rules from '{}': {}", full_path.display(), e),
+            )
+        })?;
+        // tokens parsed from a string don't have spans in the file, so errors point at the path instead
+        let rules = Self::parse_body.parse_str(&contents).map_err(|e| {
+            syn::Error::new(
+                path.span(),
+                format!("couldn't parse the rules in '{}': {}", path.value(), e),
+            )
+        })?;
+        Ok((rules, full_path.display().to_string()))
+    }
+
+    /// Parses rules separated by semicolons, each rule can inherit from the rules before it.
+    /// Templates are only kept while parsing for the rules inheriting from them.
+    fn parse_body(input: ParseStream) -> Result<Vec<Rule>> {
+        let mut rules = Vec::new();
+        while !input.is_empty() {
+            let rule = Rule::parse(input, &rules)?;
+            rules.push(rule);
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![;]>()?;
+        }
+        rules.retain(|r| !r.template);
+        Ok(rules)
     }
 }
 
 #[derive(Debug)]
 pub struct Rule {
     pub name: String,
-    pub limit: u32,
-    pub interval: u32,
-    pub timeout: u32,
-    pub key: Vec<String>,
+    pub windows: Vec<Window>,
+    /// The parts of the key joined with the separator, each part is the first non-empty input of a list,
+    /// which only has more than one input for `coalesce(...)` parts.
+    pub key: Vec<Vec<String>>,
+    /// Whether the rule is a global limit given with `for global`, its only bucket is keyed by the rule's name.
+    pub global: bool,
     pub public: bool,
+    pub jitter: u32,
+    pub cost: u32,
+    /// The input the cost of each hit is parsed from, given with `cost { input }` instead of a fixed cost.
+    pub cost_input: Option<String>,
+    pub hide_key: bool,
+    pub leaky: bool,
+    pub aligned: bool,
+    /// Whether each limited hit restarts the timeout, instead of the bucket resetting at a fixed time.
+    pub extend: bool,
+    /// Static labels added to the rule's metrics.
+    pub tags: Vec<(String, String)>,
+    /// The parts of the value counted by a distinct rule, joined like the key, empty if the rule isn't distinct.
+    pub distinct: Vec<Vec<String>>,
+    /// Whether the rule only counts requests, it never limits them and its remaining requests count down from `u32::MAX`.
+    pub count: bool,
+    /// The machine-readable reason given to clients when the rule limits a request.
+    pub reason: Option<String>,
+    /// The number of hits on a new bucket that aren't taken from it.
+    pub grace: u32,
+    /// The seconds a limited client has to stay quiet before its timeout starts being shortened, zero if the rule doesn't forgive.
+    pub forgive: u32,
+    /// The `header("Name")` parts of the key and distinct values, taken by the rate limiter once its rules are parsed.
+    pub headers: Vec<HeaderPart>,
+    /// Whether the rule is only inherited from, given with `template` before its name, it has no store and never limits a request.
+    pub template: bool,
 }
 
-impl Parse for Rule {
-    fn parse(mut input: ParseStream) -> Result<Self> {
+impl Rule {
+    /// Parses a rule, which can name one of the `previous` rules in place of its limit to inherit its windows.
+    fn parse(mut input: ParseStream, previous: &[Rule]) -> Result<Self> {
+        // `template` before the name gives a rule that's only inherited from, so its key can be left out
+        let template =
+            input.peek(Ident) && input.peek2(Ident) && input.fork().parse::<Ident>()? == "template";
+        if template {
+            input.parse::<Ident>()?;
+        }
+        let span = input.span();
         let name = expected_arbitrary_ident(&mut input)?;
+        // the hit methods have locals with these names, `now` is also a field of the hit struct,
+        // and `__ceiling` is the field of the rate limiter holding everything but the rules' stores
+        if name == "now" || name == "hit" || name == "__ceiling" {
+            return Err(syn::Error::new(
+                span,
+                format!("'{name}' is reserved and can't be the name of a rule"),
+            ));
+        }
         expected_token(&mut input, Token![=])?;
         input.parse::<Token![=]>()?;
 
@@ -98,44 +524,1049 @@ impl Parse for Rule {
             false
         };
 
-        let limit = expected_int(&mut input)?;
-        expected_ident(&mut input, "requests")?;
-        expected_ident(&mut input, "every")?;
-        let interval = expected_duration(&mut input)?;
-        expected_token(&mut input, Token![for])?;
-        input.parse::<Token![for]>()?;
-        let key;
-        braced!(key in input);
-        let key = Self::parse_key(key)?;
-        let timeout = expected_ident_or_nothing(&mut input, "timeout")?;
-        let timeout = if timeout {
-            expected_duration(&mut input)?
+        let windows = if input.peek(syn::token::Bracket) {
+            let stream;
+            bracketed!(stream in input);
+            let windows = stream
+                .parse_terminated::<_, Token![,]>(Window::parse)?
+                .into_iter()
+                .collect::<Vec<_>>();
+            if windows.is_empty() {
+                return Err(syn::Error::new(
+                    stream.span(),
+                    "expected at least one window",
+                ));
+            }
+            Some(windows)
         } else {
-            interval
+            None
         };
+        // a leaky rule can't have a timeout, so one given to any of its own windows is an error once it's known to be leaky
+        let window_timeouts = windows
+            .as_ref()
+            .is_some_and(|w| w.iter().any(|w| w.timeout != w.interval));
+        // a counting rule never limits requests, so it counts down from the largest limit
+        let count =
+            windows.is_none() && input.peek(Ident) && input.fork().parse::<Ident>()? == "count";
+        // a base rule named in place of the limit gives its windows, which the rule's own timeout overrides
+        let base = if windows.is_none() && !count && input.peek(Ident) {
+            let ident = input.parse::<Ident>()?;
+            let Some(base) = previous.iter().find(|r| ident == r.name) else {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    format!("unknown rule '{ident}', a rule can only inherit from a rule defined before it"),
+                ));
+            };
+            if base.count {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    "counting rules can't be inherited from",
+                ));
+            }
+            Some(base)
+        } else {
+            None
+        };
+        let windows = match base {
+            Some(base) if base.windows.len() > 1 => Some(base.windows.clone()),
+            _ => windows,
+        };
+        let (limit, interval) = match (&windows, base) {
+            (Some(_), _) => (0, 0),
+            (None, Some(base)) => (base.windows[0].limit, base.windows[0].interval),
+            (None, None) if count => {
+                let span = input.parse::<Ident>()?.span();
+                if public {
+                    return Err(syn::Error::new(span, "counting rules can't be public"));
+                }
+                expected_ident(&mut input, "every")?;
+                (u32::MAX, Window::parse_interval(&mut input)?)
+            },
+            (None, None) => Window::parse_limit(&mut input)?,
+        };
+        let keyless = template && !input.peek(Token![for]);
+        if !keyless {
+            expected_token(&mut input, Token![for])?;
+            input.parse::<Token![for]>()?;
+        }
+        let global = !keyless && input.peek(Ident) && input.fork().parse::<Ident>()? == "global";
+        let mut headers = Vec::new();
+        let key = if keyless {
+            vec![]
+        } else if global {
+            input.parse::<Ident>()?;
+            vec![]
+        } else {
+            let lookahead = input.lookahead1();
+            if !lookahead.peek(syn::token::Brace) {
+                return Err(lookahead.error());
+            }
+            let key;
+            braced!(key in input);
+            Self::parse_key(key, &mut headers)?
+        };
+        let mut timeout = None;
+        let mut jitter = 0;
+        let mut cost = 1;
+        let mut cost_input = None;
+        let mut hide_key = false;
+        let mut leaky = false;
+        let mut aligned = false;
+        let mut extend = false;
+        let mut tags = vec![];
+        let mut reason = None;
+        let mut grace = None;
+        let mut forgive = None;
+        let mut distinct = None;
+        while !input.is_empty() && !input.peek(Token![;]) {
+            let lookahead = input.lookahead1();
+            if !lookahead.peek(Ident) {
+                return Err(lookahead.error());
+            }
+            let clause = input.parse::<Ident>()?;
+            match clause.to_string().as_str() {
+                "timeout" => {
+                    if leaky {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "leaky rules can't have a timeout",
+                        ));
+                    }
+                    if let Some(base) = base.filter(|b| b.windows.len() > 1) {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            format!("'{}' has several windows, so a rule inheriting from it can't override their timeouts", base.name),
+                        ));
+                    }
+                    if windows.is_some() {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "the timeout of each window goes inside the brackets",
+                        ));
+                    }
+                    timeout = Some(Window::parse_timeout(&mut input, interval)?)
+                },
+                "jitter" => {
+                    if leaky {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "leaky rules can't have jitter",
+                        ));
+                    }
+                    let span = input.span();
+                    jitter = expected_int(&mut input)?;
+                    if jitter > 100 {
+                        return Err(syn::Error::new(
+                            span,
+                            "jitter can't be more than 100% of the interval",
+                        ));
+                    }
+                    expected_token(&mut input, Token![%])?;
+                    input.parse::<Token![%]>()?;
+                },
+                "cost" if input.peek(syn::token::Brace) => {
+                    let stream;
+                    braced!(stream in input);
+                    cost_input = Some(expected_arbitrary_ident(&mut &stream)?);
+                    if !stream.is_empty() {
+                        return Err(stream.error("expected a single input to take the cost from"));
+                    }
+                },
+                "cost" => {
+                    let span = input.span();
+                    cost = expected_int(&mut input)?;
+                    if cost == 0 {
+                        return Err(syn::Error::new(
+                            span,
+                            "the cost of a hit must be above zero, else the rule never limits",
+                        ));
+                    }
+                },
+                "grace" => grace = Some((clause.span(), expected_int(&mut input)?)),
+                "hide" => {
+                    expected_ident(&mut input, "key")?;
+                    hide_key = true;
+                },
+                "leaky" => {
+                    if windows
+                        .as_ref()
+                        .map_or(limit == 0, |w| w.iter().any(|w| w.limit == 0))
+                    {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "leaky rules need a limit above zero",
+                        ));
+                    }
+                    if aligned {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "leaky rules can't be aligned",
+                        ));
+                    }
+                    if count {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "counting rules can't be leaky",
+                        ));
+                    }
+                    if extend {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "leaky rules can't extend on abuse",
+                        ));
+                    }
+                    // requests leak back instead of the bucket resetting, so there's nothing to time out or jitter
+                    if timeout.is_some() || window_timeouts {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "leaky rules can't have a timeout",
+                        ));
+                    }
+                    if jitter > 0 {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "leaky rules can't have jitter",
+                        ));
+                    }
+                    leaky = true;
+                },
+                "aligned" => {
+                    if leaky {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "leaky rules can't be aligned",
+                        ));
+                    }
+                    aligned = true;
+                },
+                "extend" => {
+                    expected_ident(&mut input, "on")?;
+                    expected_ident(&mut input, "abuse")?;
+                    if leaky {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "leaky rules can't extend on abuse",
+                        ));
+                    }
+                    if count {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "counting rules never limit, so they can't extend on abuse",
+                        ));
+                    }
+                    extend = true;
+                },
+                "tags" => {
+                    let stream;
+                    braced!(stream in input);
+                    tags = Self::parse_tags(&stream)?;
+                },
+                "forgive" => {
+                    expected_ident(&mut input, "after")?;
+                    let after = expected_duration(&mut input)?;
+                    if after == 0 {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "the time to stay quiet before being forgiven must be above zero",
+                        ));
+                    }
+                    forgive = Some((clause.span(), after));
+                },
+                "reason" => {
+                    if count {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "counting rules never limit, so they can't have a reason",
+                        ));
+                    }
+                    let lookahead = input.lookahead1();
+                    if !lookahead.peek(LitStr) {
+                        return Err(lookahead.error());
+                    }
+                    let lit = input.parse::<LitStr>()?;
+                    if lit.value().is_empty() {
+                        return Err(syn::Error::new(lit.span(), "the reason can't be empty"));
+                    }
+                    reason = Some(lit.value());
+                },
+                "distinct" => {
+                    let stream;
+                    braced!(stream in input);
+                    let parts = Self::parse_key(stream, &mut headers)?;
+                    if parts.is_empty() {
+                        return Err(syn::Error::new(
+                            clause.span(),
+                            "expected at least one input to count distinct values of",
+                        ));
+                    }
+                    distinct = Some((clause.span(), parts));
+                },
+                _ => return Err(syn::Error::new(
+                    clause.span(),
+                    "expected 'timeout', 'jitter', 'cost', 'hide', 'leaky', 'aligned', 'extend', 'tags', 'distinct', 'reason', 'grace', or 'forgive'",
+                )),
+            }
+        }
+        // a distinct rule takes one request from its bucket for each new value, so only plain fixed windows are supported
+        if let Some((span, _)) = &distinct {
+            let conflict = if count {
+                Some("counting rules")
+            } else if leaky {
+                Some("leaky rules")
+            } else if windows.is_some() {
+                Some("rules with several windows")
+            } else if cost != 1 || cost_input.is_some() {
+                Some("rules with a cost")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(syn::Error::new(
+                    *span,
+                    format!("{conflict} can't be distinct"),
+                ));
+            }
+        }
+        // a leaky bucket leaks back to its limit, so requests over the limit would never be taken,
+        // and a rule with a limit of zero is meant to block every request
+        if let Some((span, _)) = &grace {
+            let conflict = if count {
+                Some("counting rules")
+            } else if leaky {
+                Some("leaky rules")
+            } else if distinct.is_some() {
+                Some("distinct rules")
+            } else if windows
+                .as_ref()
+                .map_or(limit == 0, |w| w.iter().any(|w| w.limit == 0))
+            {
+                Some("rules with a limit of zero")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(syn::Error::new(
+                    *span,
+                    format!("{conflict} can't have a grace"),
+                ));
+            }
+        }
+        let grace = grace.map_or(0, |(_, grace)| grace);
+        // forgiving shortens a timeout set when the limit is reached, which leaky and aligned rules ignore,
+        // and a rule extending on abuse restarts it on every limited hit instead
+        if let Some((span, _)) = &forgive {
+            let conflict = if count {
+                Some("counting rules")
+            } else if leaky {
+                Some("leaky rules")
+            } else if aligned {
+                Some("aligned rules")
+            } else if extend {
+                Some("rules extending on abuse")
+            } else if distinct.is_some() {
+                Some("distinct rules")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(syn::Error::new(*span, format!("{conflict} can't forgive")));
+            }
+        }
+        let forgive = forgive.map_or(0, |(_, forgive)| forgive);
+        let distinct = distinct.map(|(_, parts)| parts).unwrap_or_default();
+        let default_timeout = base.map_or(interval, |b| b.windows[0].timeout);
+        let windows = windows.unwrap_or_else(|| {
+            vec![Window {
+                limit,
+                interval,
+                timeout: timeout.unwrap_or(default_timeout),
+            }]
+        });
         Ok(Rule {
             name,
-            limit,
-            interval,
-            timeout,
+            windows,
             key,
+            global,
             public,
+            jitter,
+            cost,
+            cost_input,
+            hide_key,
+            leaky,
+            aligned,
+            extend,
+            tags,
+            distinct,
+            count,
+            reason,
+            grace,
+            forgive,
+            headers,
+            template,
         })
     }
 }
 
 impl Rule {
-    fn parse_key(input: ParseBuffer) -> Result<Vec<String>> {
-        Ok(input
-            .parse_terminated::<_, Token![+]>(|buf| {
-                let lookahead = buf.lookahead1();
-                if lookahead.peek(Ident) {
-                    Ok(buf.parse::<Ident>()?.to_string())
-                } else {
-                    Err(lookahead.error())
+    /// Parses the `k = "v", ...` inside a `tags { ... }` clause.
+    fn parse_tags(mut input: ParseStream) -> Result<Vec<(String, String)>> {
+        let mut tags: Vec<(String, String)> = vec![];
+        while !input.is_empty() {
+            let lookahead = input.lookahead1();
+            if !lookahead.peek(Ident) {
+                return Err(lookahead.error());
+            }
+            let name = input.parse::<Ident>()?;
+            let tag = name.to_string();
+            if tag == "rule" || tag == "outcome" {
+                return Err(syn::Error::new(
+                    name.span(),
+                    format!("the tag '{tag}' is already used as a label of the rule's metrics"),
+                ));
+            }
+            if tags.iter().any(|(t, _)| *t == tag) {
+                return Err(syn::Error::new(
+                    name.span(),
+                    format!("duplicate tag '{tag}'"),
+                ));
+            }
+            expected_token(&mut input, Token![=])?;
+            input.parse::<Token![=]>()?;
+            let lookahead = input.lookahead1();
+            if !lookahead.peek(LitStr) {
+                return Err(lookahead.error());
+            }
+            tags.push((tag, input.parse::<LitStr>()?.value()));
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+        Ok(tags)
+    }
+
+    fn parse_key(input: ParseBuffer, headers: &mut Vec<HeaderPart>) -> Result<Vec<Vec<String>>> {
+        let mut parts = Vec::new();
+        while !input.is_empty() {
+            parts.push(Self::parse_part(&input, headers)?);
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![+]>()?;
+        }
+        Ok(parts)
+    }
+
+    /// Parses a part of a key, an input, `coalesce(...)` of several inputs, or `header("Name")` with an optional fallback,
+    /// which is kept as written until the rate limiter replaces it with an input.
+    fn parse_part(buf: ParseStream, headers: &mut Vec<HeaderPart>) -> Result<Vec<String>> {
+        let lookahead = buf.lookahead1();
+        if !lookahead.peek(Ident) {
+            return Err(lookahead.error());
+        }
+        let ident = buf.parse::<Ident>()?;
+        if !buf.peek(syn::token::Paren) {
+            return Ok(vec![ident.to_string()]);
+        }
+        let stream;
+        parenthesized!(stream in buf);
+        if ident == "header" {
+            let lookahead = stream.lookahead1();
+            if !lookahead.peek(LitStr) {
+                return Err(lookahead.error());
+            }
+            let name = stream.parse::<LitStr>()?;
+            if name.value().is_empty() {
+                return Err(syn::Error::new(
+                    name.span(),
+                    "the header name can't be empty",
+                ));
+            }
+            let fallback = if stream.is_empty() {
+                None
+            } else {
+                stream.parse::<Token![,]>()?;
+                let lookahead = stream.lookahead1();
+                if !lookahead.peek(LitStr) {
+                    return Err(lookahead.error());
                 }
-            })?
+                Some(stream.parse::<LitStr>()?.value())
+            };
+            if !stream.is_empty() {
+                return Err(stream.error("expected the header name and an optional fallback"));
+            }
+            let part = match &fallback {
+                Some(fallback) => format!("header({:?}, {:?})", name.value(), fallback),
+                None => format!("header({:?})", name.value()),
+            };
+            headers.push(HeaderPart {
+                part: part.clone(),
+                input: String::new(),
+                name: name.value(),
+                fallback,
+            });
+            return Ok(vec![part]);
+        }
+        if ident != "coalesce" {
+            return Err(syn::Error::new(
+                ident.span(),
+                "expected 'coalesce' or 'header'",
+            ));
+        }
+        let inputs = stream
+            .parse_terminated::<_, Token![,]>(Ident::parse)?
             .into_iter()
-            .collect::<Vec<_>>())
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+        if inputs.is_empty() {
+            return Err(syn::Error::new(ident.span(), "expected at least one input"));
+        }
+        Ok(inputs)
+    }
+
+    /// The inputs used in the key of the rule.
+    pub fn inputs(&self) -> impl Iterator<Item = &String> {
+        self.key.iter().flatten()
+    }
+}
+
+/// A limit and interval of a rule, rules with several windows are limited if any of them is.
+#[derive(Clone, Debug)]
+pub struct Window {
+    pub limit: u32,
+    pub interval: u32,
+    pub timeout: u32,
+}
+
+impl Parse for Window {
+    fn parse(mut input: ParseStream) -> Result<Self> {
+        let (limit, interval) = Self::parse_limit(&mut input)?;
+        let timeout = if input.peek(Ident) {
+            expected_ident(&mut input, "timeout")?;
+            Self::parse_timeout(&mut input, interval)?
+        } else {
+            interval
+        };
+        Ok(Window {
+            limit,
+            interval,
+            timeout,
+        })
+    }
+}
+
+impl Window {
+    /// Parses `N requests every D` or the shorthand `N/unit`, returning the limit and interval.
+    fn parse_limit(input: &mut ParseStream) -> Result<(u32, u32)> {
+        let limit = expected_int(input)?;
+        if input.peek(Token![/]) {
+            input.parse::<Token![/]>()?;
+            let lookahead = input.lookahead1();
+            if !lookahead.peek(Ident) {
+                return Err(lookahead.error());
+            }
+            let unit = input.parse::<Ident>()?;
+            let interval = match unit.to_string().as_str() {
+                "s" => 1,
+                "min" => 60,
+                "h" => 60 * 60,
+                "d" => 60 * 60 * 24,
+                _ => {
+                    return Err(syn::Error::new(
+                        unit.span(),
+                        "expected 's', 'min', 'h', or 'd'",
+                    ))
+                },
+            };
+            return Ok((limit, interval));
+        }
+        expected_ident(input, "requests")?;
+        expected_ident(input, "every")?;
+        Ok((limit, Self::parse_interval(input)?))
+    }
+
+    /// Parses the duration of an interval, which can't be zero.
+    fn parse_interval(input: &mut ParseStream) -> Result<u32> {
+        let span = input.span();
+        let interval = expected_duration(input)?;
+        if interval == 0 {
+            return Err(syn::Error::new(
+                span,
+                "interval must be positive, else the bucket resets immediately and never limits",
+            ));
+        }
+        Ok(interval)
+    }
+
+    /// Parses a timeout as a duration or a multiple of the interval, either `2x` or `2 intervals`.
+    fn parse_timeout(input: &mut ParseStream, interval: u32) -> Result<u32> {
+        let fork = input.fork();
+        let lit = fork.parse::<LitInt>()?;
+        let intervals = fork.peek(Ident)
+            && matches!(
+                fork.parse::<Ident>()?.to_string().as_str(),
+                "interval" | "intervals"
+            );
+        if lit.suffix() != "x" && !intervals {
+            return expected_duration(input);
+        }
+        input.parse::<LitInt>()?;
+        if intervals {
+            input.parse::<Ident>()?;
+        }
+        checked_mul(
+            lit.base10_parse::<u32>()?,
+            interval,
+            lit.span(),
+            "timeout in seconds",
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(input: &str) -> String {
+        match syn::parse_str::<RateLimiterInput>(input) {
+            Ok(_) => panic!("expected an error parsing '{input}'"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn invalid_inputs() {
+        // these used to loop forever looking for `in`
+        error("ip");
+        error("ip ; in { main = 1 requests every 1 seconds for { ip }; } as RateLimiter");
+        assert_eq!(
+            error("r#type in { main = 1 requests every 1 seconds for { r#type }; } as RateLimiter"),
+            "raw identifiers aren't supported"
+        );
+    }
+
+    #[test]
+    fn context_inputs() {
+        let input = syn::parse_str::<RateLimiterInput>(
+            "req.ip, req.path in { main = 1 requests every 1 seconds for { ip + path }; } as RateLimiter context http::Request",
+        )
+        .unwrap();
+        assert_eq!(input.inputs, ["ip", "path"]);
+        assert_eq!(input.context, Some(("req".into(), "http::Request".into())));
+        assert_eq!(
+            error("req.ip, path in { main = 1 requests every 1 seconds for { ip }; } as RateLimiter context Request"),
+            "the inputs must either all be plain names or all be fields of the same context"
+        );
+        assert_eq!(
+            error("req.ip, other.path in { main = 1 requests every 1 seconds for { ip }; } as RateLimiter context Request"),
+            "the inputs must either all be plain names or all be fields of the same context"
+        );
+        assert_eq!(
+            error("req.ip in { main = 1 requests every 1 seconds for { ip }; } as RateLimiter"),
+            "inputs read from 'req' need its type, given with `context Type` after the name"
+        );
+        assert_eq!(
+            error("ip in { main = 1 requests every 1 seconds for { ip }; } as RateLimiter context Request"),
+            "a context needs inputs read from it, e.g. `req.ip in { ... }`"
+        );
+    }
+
+    #[test]
+    fn store_paths() {
+        let store = |path: &str| {
+            syn::parse_str::<RateLimiterInput>(&format!(
+                "ip in {{ main = 1 requests every 1 seconds for {{ ip }}; }} as RateLimiter in {path} on_error allow"
+            ))
+            .unwrap()
+            .store
+        };
+        assert_eq!(store("crate::MyStore"), Some("crate::MyStore".into()));
+        assert_eq!(
+            store("super::stores::MyStore"),
+            Some("super::stores::MyStore".into())
+        );
+        assert_eq!(
+            store("::ceiling::DefaultStore"),
+            Some("::ceiling::DefaultStore".into())
+        );
+    }
+
+    #[test]
+    fn header_parts() {
+        let input = syn::parse_str::<RateLimiterInput>(
+            r#"ip in {
+                a = 1 requests every 1 seconds for { ip + header("X-Api-Key") };
+                b = 1 requests every 1 seconds for { header("X-Api-Key") + header("X-Tenant", "none") };
+            } as RateLimiter"#,
+        )
+        .unwrap();
+        assert_eq!(input.rules[0].key, [["ip"], ["__header_0"]]);
+        assert_eq!(input.rules[1].key, [["__header_0"], ["__header_1"]]);
+        assert_eq!(input.header_parts[1].part, r#"header("X-Tenant", "none")"#);
+        assert_eq!(input.header_parts[1].name, "X-Tenant");
+        assert_eq!(input.header_parts[1].fallback.as_deref(), Some("none"));
+        assert_eq!(
+            error(
+                r#"ip in { main = 1 requests every 1 seconds for { header("") }; } as RateLimiter"#
+            ),
+            "the header name can't be empty"
+        );
+        assert_eq!(
+            error("ip in { main = 1 requests every 1 seconds for { other(ip) }; } as RateLimiter"),
+            "expected 'coalesce' or 'header'"
+        );
+    }
+
+    #[test]
+    fn on_empty() {
+        let parse = |option: &str| {
+            syn::parse_str::<RateLimiterInput>(&format!(
+                "ip in {{ main = 1 requests every 1 seconds for {{ ip }}; }} as RateLimiter{option}"
+            ))
+            .unwrap()
+            .on_empty
+        };
+        assert!(matches!(parse(""), OnEmpty::Placeholder(p) if p == "<empty>"));
+        assert!(matches!(parse(" on_empty allow"), OnEmpty::Allow));
+        assert!(matches!(parse(" on_empty deny"), OnEmpty::Deny));
+        assert!(matches!(parse(" on_empty \"-\""), OnEmpty::Placeholder(p) if p == "-"));
+        assert_eq!(
+            error("ip in { main = 1 requests every 1 seconds for { ip }; } as RateLimiter on_empty skip"),
+            "expected 'allow', 'deny', or a placeholder"
+        );
+        assert_eq!(
+            error("ip in { main = 1 requests every 1 seconds for { ip }; } as RateLimiter on_empty \"\""),
+            "the placeholder must be non-empty and can't contain '#'"
+        );
+    }
+
+    #[test]
+    fn inherit() {
+        let input = syn::parse_str::<RateLimiterInput>(
+            "ip, user in {
+                base = 100 requests every 1 minute for global timeout 2 minutes;
+                by_ip = base for { ip };
+                by_user = pub base for { user } timeout 5 minutes jitter 10%;
+                multi = [1 requests every 1 second, 5 requests every 1 minute] for { ip };
+                multi_user = multi for { user };
+                template shared = 10 requests every 1 second timeout 1 minute;
+                by_ip_shared = shared for { ip };
+            } as RateLimiter",
+        )
+        .unwrap();
+        let windows = |i: usize| {
+            input.rules[i]
+                .windows
+                .iter()
+                .map(|w| (w.limit, w.interval, w.timeout))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(windows(1), [(100, 60, 120)]);
+        assert_eq!(input.rules[1].key, [["ip"]]);
+        assert_eq!(windows(2), [(100, 60, 300)]);
+        assert!(input.rules[2].public);
+        assert_eq!(input.rules[2].jitter, 10);
+        assert_eq!(windows(4), [(1, 1, 1), (5, 60, 60)]);
+        // a template isn't a rule itself
+        assert_eq!(input.rules.len(), 6);
+        assert_eq!(input.rules[5].name, "by_ip_shared");
+        assert_eq!(windows(5), [(10, 1, 60)]);
+        assert_eq!(
+            error("ip in { multi = [1 requests every 1 second, 2 requests every 1 minute] for { ip }; a = multi for { ip } timeout 1 minute; } as RateLimiter"),
+            "'multi' has several windows, so a rule inheriting from it can't override their timeouts"
+        );
+        assert_eq!(
+            error("ip in { template base = 1 requests every 1 second; } as RateLimiter"),
+            "expected at least one rule"
+        );
+        assert_eq!(
+            error("ip in { a = b for { ip }; b = 1 requests every 1 seconds for { ip }; } as RateLimiter"),
+            "unknown rule 'b', a rule can only inherit from a rule defined before it"
+        );
+        assert_eq!(
+            error(
+                "ip in { a = count every 1 seconds for { ip }; b = a for { ip }; } as RateLimiter"
+            ),
+            "counting rules can't be inherited from"
+        );
+    }
+
+    #[test]
+    fn erased() {
+        let input = syn::parse_str::<RateLimiterInput>(
+            "ip in { main = 1 requests every 1 seconds for { ip }; } as RateLimiter in store::RedisStore erased",
+        )
+        .unwrap();
+        assert!(input.erased);
+        assert_eq!(input.store.as_deref(), Some("store::RedisStore"));
+        assert_eq!(
+            error(
+                "ip in { main = 1 requests every 1 seconds for { ip }; } as RateLimiter<S> erased"
+            ),
+            "an erased rate limiter can't be generic over its store"
+        );
+        assert_eq!(
+            error("ip in { main = 1 requests every 1 seconds for { ip }; } as RateLimiter async erased"),
+            "only synchronous stores can be erased"
+        );
+    }
+
+    fn windows(rule: &str) -> Result<Vec<(u32, u32, u32)>> {
+        let input = syn::parse_str::<RateLimiterInput>(&format!(
+            "ip in {{ main = {rule}; }} as RateLimiter"
+        ))?;
+        Ok(input.rules[0]
+            .windows
+            .iter()
+            .map(|w| (w.limit, w.interval, w.timeout))
+            .collect())
+    }
+
+    #[test]
+    fn no_inputs() {
+        let input = syn::parse_str::<RateLimiterInput>(
+            "in { total = 1 requests every 1 seconds for global; } as RateLimiter",
+        )
+        .unwrap();
+        assert!(input.inputs.is_empty());
+        assert!(input.rules[0].global);
+    }
+
+    #[test]
+    fn rate_shorthand() {
+        assert_eq!(windows("10/s for { ip }").unwrap(), [(10, 1, 1)]);
+        assert_eq!(
+            windows("1k/min for { ip } timeout 2x").unwrap(),
+            [(1000, 60, 120)]
+        );
+        assert_eq!(
+            windows("[5/h, 100 requests every 1 day] for { ip }").unwrap(),
+            [(5, 3600, 3600), (100, 86400, 86400)]
+        );
+        assert_eq!(windows("1/d for { ip }").unwrap(), [(1, 86400, 86400)]);
+        assert_eq!(
+            windows("10/m for { ip }").unwrap_err().to_string(),
+            "expected 's', 'min', 'h', or 'd'"
+        );
+    }
+
+    #[test]
+    fn reserved_names() {
+        assert_eq!(
+            error("ip in { now = 1 requests every 1 seconds for { ip }; } as RateLimiter"),
+            "'now' is reserved and can't be the name of a rule"
+        );
+        assert_eq!(
+            error("ip in { __ceiling = 1 requests every 1 seconds for { ip }; } as RateLimiter"),
+            "'__ceiling' is reserved and can't be the name of a rule"
+        );
+    }
+
+    #[test]
+    fn reason() {
+        let input = syn::parse_str::<RateLimiterInput>(
+            r#"ip in { main = 1 requests every 1 seconds for { ip } reason "burst"; } as RateLimiter"#,
+        )
+        .unwrap();
+        assert_eq!(input.rules[0].reason.as_deref(), Some("burst"));
+        assert_eq!(
+            error(
+                r#"ip in { main = count every 1 seconds for { ip } reason "burst"; } as RateLimiter"#
+            ),
+            "counting rules never limit, so they can't have a reason"
+        );
+        assert_eq!(
+            error(
+                r#"ip in { main = 1 requests every 1 seconds for { ip } reason ""; } as RateLimiter"#
+            ),
+            "the reason can't be empty"
+        );
+    }
+
+    #[test]
+    fn cost_input() {
+        let input = syn::parse_str::<RateLimiterInput>(
+            "ip, size in { main = 10 requests every 1 seconds for { ip } cost { size }; } as RateLimiter",
+        )
+        .unwrap();
+        assert_eq!(input.rules[0].cost_input.as_deref(), Some("size"));
+        assert_eq!(
+            error("ip, size in { main = 10 requests every 1 seconds for { ip } cost { ip size }; } as RateLimiter"),
+            "expected a single input to take the cost from"
+        );
+        assert_eq!(
+            error("ip, size in { main = 10 requests every 1 seconds for { ip } cost { size } distinct { size }; } as RateLimiter"),
+            "rules with a cost can't be distinct"
+        );
+    }
+
+    #[test]
+    fn forgive() {
+        let input = syn::parse_str::<RateLimiterInput>(
+            "ip in { main = 1 requests every 1 seconds for { ip } timeout 1 hour forgive after 1 minute; } as RateLimiter",
+        )
+        .unwrap();
+        assert_eq!(input.rules[0].forgive, 60);
+        assert_eq!(
+            error("ip in { main = 1 requests every 1 seconds for { ip } forgive after 0 seconds; } as RateLimiter"),
+            "the time to stay quiet before being forgiven must be above zero"
+        );
+        assert_eq!(
+            error("ip in { main = 1 requests every 1 seconds for { ip } extend on abuse forgive after 1 seconds; } as RateLimiter"),
+            "rules extending on abuse can't forgive"
+        );
+    }
+
+    #[test]
+    fn zero_interval() {
+        let message =
+            "interval must be positive, else the bucket resets immediately and never limits";
+        assert_eq!(
+            windows("1 requests every 0 seconds for { ip }")
+                .unwrap_err()
+                .to_string(),
+            message
+        );
+        assert_eq!(
+            windows("count every 0 seconds for { ip }")
+                .unwrap_err()
+                .to_string(),
+            message
+        );
+        assert_eq!(
+            windows("[1 requests every 1 seconds, 2 requests every 0 minutes] for { ip }")
+                .unwrap_err()
+                .to_string(),
+            message
+        );
+    }
+
+    #[test]
+    fn zero_cost() {
+        assert_eq!(
+            error("ip in { main = 1 requests every 1 seconds for { ip } cost 0; } as RateLimiter"),
+            "the cost of a hit must be above zero, else the rule never limits"
+        );
+    }
+
+    #[test]
+    fn jitter_above_interval() {
+        assert_eq!(
+            error("ip in { main = 1 requests every 1 seconds for { ip } jitter 101%; } as RateLimiter"),
+            "jitter can't be more than 100% of the interval"
+        );
+        syn::parse_str::<RateLimiterInput>(
+            "ip in { main = 1 requests every 1 seconds for { ip } jitter 100%; } as RateLimiter",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn leaky_clauses() {
+        for rule in [
+            "1 requests every 1 seconds for { ip } timeout 5 seconds leaky",
+            "1 requests every 1 seconds for { ip } leaky timeout 5 seconds",
+            "[1 requests every 1 seconds timeout 5 seconds] for { ip } leaky",
+        ] {
+            assert_eq!(
+                error(&format!("ip in {{ main = {rule}; }} as RateLimiter")),
+                "leaky rules can't have a timeout"
+            );
+        }
+        for rule in [
+            "1 requests every 1 seconds for { ip } jitter 10% leaky",
+            "1 requests every 1 seconds for { ip } leaky jitter 10%",
+        ] {
+            assert_eq!(
+                error(&format!("ip in {{ main = {rule}; }} as RateLimiter")),
+                "leaky rules can't have jitter"
+            );
+        }
+        // a timeout inherited from the base isn't given to the leaky rule
+        syn::parse_str::<RateLimiterInput>(
+            "ip in { base = 1 requests every 1 seconds for { ip } timeout 5 seconds; main = base for { ip } leaky; } as RateLimiter",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn grace() {
+        assert_eq!(
+            error("ip in { main = count every 1 seconds for { ip } grace 2; } as RateLimiter"),
+            "counting rules can't have a grace"
+        );
+        assert_eq!(
+            error("ip in { main = 1 requests every 1 seconds for { ip } leaky grace 2; } as RateLimiter"),
+            "leaky rules can't have a grace"
+        );
+        assert_eq!(
+            error("ip in { main = 0 requests every 1 seconds for { ip } grace 2; } as RateLimiter"),
+            "rules with a limit of zero can't have a grace"
+        );
+    }
+
+    #[test]
+    fn limit_overflow() {
+        let max = u32::MAX;
+        assert_eq!(
+            windows(&format!("{max} requests every 1 seconds for {{ ip }}")).unwrap(),
+            [(max, 1, 1)]
+        );
+        assert_eq!(
+            windows("4294967k requests every 1 seconds for { ip }").unwrap(),
+            [(4_294_967_000, 1, 1)]
+        );
+        assert_eq!(
+            windows("4294967296 requests every 1 seconds for { ip }")
+                .unwrap_err()
+                .to_string(),
+            "number too large to fit in target type"
+        );
+        assert_eq!(
+            windows("4295m requests every 1 seconds for { ip }")
+                .unwrap_err()
+                .to_string(),
+            "number too large, the maximum is 4294967295, found 4295000000"
+        );
+    }
+
+    #[test]
+    fn duration_overflow() {
+        assert_eq!(
+            windows("1 requests every 4294967295 seconds for { ip }").unwrap(),
+            [(1, u32::MAX, u32::MAX)]
+        );
+        assert_eq!(
+            windows("1 requests every 71582788 minutes for { ip }").unwrap(),
+            [(1, 4_294_967_280, 4_294_967_280)]
+        );
+        assert_eq!(
+            windows("1 requests every 49710 days for { ip }").unwrap(),
+            [(1, 4_294_944_000, 4_294_944_000)]
+        );
+        // these used to wrap around to a short duration
+        for duration in [
+            "71582789 minutes",
+            "1193047 hours",
+            "49711 days",
+            "100000000 days",
+        ] {
+            let err = windows(&format!("1 requests every {duration} for {{ ip }}")).unwrap_err();
+            assert!(
+                err.to_string()
+                    .starts_with("duration in seconds too large, the maximum is 4294967295"),
+                "{duration}: {err}"
+            );
+        }
+        assert_eq!(
+            windows("1 requests every 2147483648 seconds for { ip } timeout 2x")
+                .unwrap_err()
+                .to_string(),
+            "timeout in seconds too large, the maximum is 4294967295, found 4294967296"
+        );
+        assert_eq!(
+            windows("1 requests every 1 days for { ip } timeout 49710 days").unwrap(),
+            [(1, 86400, 4_294_944_000)]
+        );
     }
 }
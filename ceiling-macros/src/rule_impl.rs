@@ -0,0 +1,990 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Ident, Lifetime};
+
+use crate::rate_limiter_input::{OnError, Rule};
+
+/// How the generated code calls the store and handles its errors.
+#[derive(Clone, Copy)]
+pub enum StoreErrors {
+    /// The infallible store methods are used.
+    Infallible,
+    /// The fallible store methods are used and errors are returned from the generated method.
+    Propagate,
+    /// The fallible store methods are used and errors are handled by the policy.
+    Handle(OnError),
+}
+
+/// The generated code for a single rule, split into phases so they can be interleaved across rules.
+pub struct RuleImpl {
+    /// Locks the rule's bucket and loads its state, resetting it if the window has expired.
+    pub load: TokenStream,
+    /// Takes the cost of the hit from the bucket and saves it, tripping the rule if too few requests remain.
+    pub consume: TokenStream,
+    /// Evaluates to whether consuming would trip the rule.
+    pub trips: TokenStream,
+    /// Releases the bucket, prunes the store, and evaluates to the state of the rule for the hit struct.
+    pub finish: TokenStream,
+    /// A block evaluating the phases of the rule in order.
+    pub sequential: TokenStream,
+}
+
+/// Generates calls to a rule's store, using the fallible methods and handling their errors if needed.
+struct Store<'a> {
+    name: &'a Ident,
+    async_store: bool,
+    errors: StoreErrors,
+    label: Option<&'a Lifetime>,
+    /// The state of the rule to bail out with if the store errors and the policy allows the request.
+    allow: TokenStream,
+    /// The state of the rule to bail out with if the store errors and the policy denies the request.
+    deny: TokenStream,
+}
+
+impl Store<'_> {
+    fn call(&self, method: &str, args: TokenStream) -> TokenStream {
+        let name = self.name;
+        let method = match self.errors {
+            StoreErrors::Infallible => syn::parse_str::<Ident>(method).unwrap(),
+            _ => syn::parse_str::<Ident>(&format!("try_{method}")).unwrap(),
+        };
+        let call = if self.async_store {
+            quote!(self.#name.#method(#args).await)
+        } else {
+            quote!(self.#name.#method(#args))
+        };
+        let label = self.label;
+        let error = match self.errors {
+            StoreErrors::Infallible => return call,
+            StoreErrors::Propagate => quote!(return Err(e)),
+            StoreErrors::Handle(OnError::Allow) => {
+                let allow = &self.allow;
+                quote!(break #label #allow)
+            },
+            StoreErrors::Handle(OnError::Deny) => {
+                let deny = &self.deny;
+                quote! {{
+                    hit = true;
+                    break #label #deny
+                }}
+            },
+        };
+        quote! {
+            match #call {
+                Ok(v) => v,
+                #[allow(unused_variables)]
+                Err(e) => #error,
+            }
+        }
+    }
+
+    /// Reads a bucket without locking it, stores have no fallible variant of `read`.
+    fn read(&self, key: TokenStream) -> TokenStream {
+        let name = self.name;
+        if self.async_store {
+            quote!(self.#name.read(#key).await)
+        } else {
+            quote!(self.#name.read(#key))
+        }
+    }
+
+    fn prune(&self) -> TokenStream {
+        let name = self.name;
+        if self.async_store {
+            quote!(self.#name.prune(now.saturating_sub(self.__ceiling.clock_skew)).await)
+        } else {
+            quote!(self.#name.prune(now.saturating_sub(self.__ceiling.clock_skew)))
+        }
+    }
+}
+
+fn label(name: &str, errors: StoreErrors) -> Option<Lifetime> {
+    match errors {
+        StoreErrors::Handle(_) => Some(Lifetime::new(
+            &format!("'{name}"),
+            proc_macro2::Span::call_site(),
+        )),
+        _ => None,
+    }
+}
+
+/// Generates the time buckets are compared against to tell whether they've expired, now less the rate limiter's clock skew
+/// tolerance, so a bucket whose reset was written by an instance with a clock slightly behind isn't reset early.
+pub fn expiry_now() -> TokenStream {
+    quote!(now.saturating_sub(self.__ceiling.clock_skew))
+}
+
+/// Generates the limit, interval, and timeout of a window, read from the rate limiter so they can be overridden at runtime.
+/// The windows of every rule are numbered in order, so a rule's windows follow those of the rules before it.
+pub fn window_limits(window: usize) -> (TokenStream, TokenStream, TokenStream) {
+    (
+        quote!(self.__ceiling.limits[#window].limit),
+        quote!(self.__ceiling.limits[#window].interval),
+        quote!(self.__ceiling.limits[#window].timeout),
+    )
+}
+
+/// The cost of a hit on a rule, either fixed or the local variable holding the cost parsed from its cost input.
+fn cost(rule: &Rule) -> TokenStream {
+    match &rule.cost_input {
+        Some(_) => {
+            let cost = syn::parse_str::<Ident>(&format!("{}_cost", rule.name)).unwrap();
+            quote!(#cost)
+        },
+        None => {
+            let cost = rule.cost;
+            quote!(#cost)
+        },
+    }
+}
+
+/// The local variable holding whether a rule is enabled, loaded once per hit so every window of the rule sees the same value.
+pub fn enabled(rule: &Rule) -> Ident {
+    syn::parse_str::<Ident>(&format!("{}_enabled", rule.name)).unwrap()
+}
+
+pub fn impl_rule(rule: &Rule, offset: usize, async_store: bool, errors: StoreErrors) -> RuleImpl {
+    let name = &rule.name;
+    let store = syn::parse_str::<Ident>(name).unwrap();
+    let key = syn::parse_str::<Ident>(&format!("{name}_key")).unwrap();
+    if rule.windows.len() == 1 {
+        return impl_window(
+            rule,
+            offset,
+            name,
+            &store,
+            &key,
+            true,
+            async_store,
+            errors,
+            false,
+        );
+    }
+    let public = rule.public;
+    // an async rule gets and sets the buckets of its windows with one call to its store each, so a store backed by a
+    // remote service can batch them into a single round trip, the fallible store methods have no batched variants
+    let batched = async_store && matches!(errors, StoreErrors::Infallible);
+    let (mut load, mut consume, mut trips, mut finish, mut sequential) =
+        (quote!(), quote!(), quote!(false), quote!(), quote!());
+    let mut hits = Vec::with_capacity(rule.windows.len());
+    let mut configs = Vec::with_capacity(rule.windows.len());
+    let mut window_keys = Vec::with_capacity(rule.windows.len());
+    for i in 0..rule.windows.len() {
+        let window = offset + i;
+        // each window has its own bucket in the rule's store, keyed by the window's index
+        let prefix = format!("{name}_w{i}");
+        let window_key = syn::parse_str::<Ident>(&format!("{prefix}_key")).unwrap();
+        let hit = syn::parse_str::<Ident>(&format!("{prefix}_hit")).unwrap();
+        let window_impl = impl_window(
+            rule,
+            window,
+            &prefix,
+            &store,
+            &window_key,
+            i == 0,
+            async_store,
+            errors,
+            batched,
+        );
+        let define_key = quote!(let #window_key = format!("{}#{}", #key, #i););
+        let RuleImpl {
+            load: window_load,
+            consume: window_consume,
+            trips: window_trips,
+            finish: window_finish,
+            sequential: window_sequential,
+        } = window_impl;
+        if batched {
+            load.extend(window_load);
+        } else {
+            load.extend(quote! {
+                #define_key
+                #window_load
+            });
+            sequential.extend(quote! {
+                #define_key
+                let #hit = #window_sequential;
+            });
+        }
+        consume.extend(window_consume);
+        trips.extend(quote!(|| #window_trips));
+        finish.extend(quote!(let #hit = { #window_finish };));
+        configs.push(quote!(self.__ceiling.limits[#window]));
+        hits.push(hit);
+        window_keys.push((window_key, define_key));
+    }
+    if batched {
+        let (locks, sets) = batch_names(rule);
+        let enabled = enabled(rule);
+        let (window_keys, define_keys): (Vec<_>, Vec<_>) = window_keys.into_iter().unzip();
+        let num_windows = rule.windows.len();
+        load = quote! {
+            #(#define_keys)*
+            let mut #locks = if #enabled {
+                self.#store.get_many(&[#(#window_keys.as_str()),*]).await
+            } else {
+                Vec::new()
+            }
+            .into_iter();
+            let mut #sets = Vec::with_capacity(#num_windows);
+            #load
+        };
+        consume.extend(quote! {
+            if !#sets.is_empty() {
+                self.#store.set_many(&#sets).await;
+            }
+        });
+        // every window's bucket is locked by the load, so the phases of the windows can't be interleaved
+        sequential = quote! {
+            #load
+            #consume
+            #finish
+        };
+    }
+    let combine = quote! {
+        let windows = [#(ceiling::Window {
+            rule: #configs,
+            remaining: #hits.0,
+            reset: #hits.1,
+        }),*];
+        let window = ceiling::Window::most_restrictive(&windows);
+        ((window.remaining, window.reset, #public, #key), windows)
+    };
+    RuleImpl {
+        load,
+        consume,
+        trips: quote!((#trips)),
+        finish: quote! {
+            #finish
+            #combine
+        },
+        sequential: quote! {{
+            #sequential
+            #combine
+        }},
+    }
+}
+
+/// The local variables holding the locks a rule with batched windows got from its store, and the values it queued to set.
+fn batch_names(rule: &Rule) -> (Ident, Ident) {
+    (
+        syn::parse_str::<Ident>(&format!("{}_locks", rule.name)).unwrap(),
+        syn::parse_str::<Ident>(&format!("{}_sets", rule.name)).unwrap(),
+    )
+}
+
+/// Generates the get and set of a window's bucket, either calls to the store or, for a window of a rule with batched windows,
+/// taking the window's lock from those the rule got and queueing its value for the rule to set.
+fn get_set(
+    store: &Store,
+    rule: &Rule,
+    key: &Ident,
+    value: TokenStream,
+    batched: bool,
+) -> (TokenStream, TokenStream) {
+    if batched {
+        let (locks, sets) = batch_names(rule);
+        (
+            quote!(#locks.next().expect("get_many returns a lock for each key")),
+            quote!(#sets.push((#key.as_str(), #value))),
+        )
+    } else {
+        (
+            store.call("get", quote!(&#key)),
+            store.call("set", quote!(&#key, #value)),
+        )
+    }
+}
+
+/// Generates a block labeled with the label if there is one.
+fn block(label: Option<&Lifetime>, body: TokenStream) -> TokenStream {
+    let label = label.map(|l| quote!(#l:));
+    quote! {
+        #label {
+            #body
+        }
+    }
+}
+
+/// Generates the tracking of whether a hit creates the bucket of a rule's first window, so the `on_first_hit` callback
+/// is invoked once per rule. Returns the statements declaring the flag, setting it while the bucket is locked, and
+/// invoking the callback once the bucket is released.
+fn first_hit(
+    rule: &Rule,
+    prefix: &str,
+    lock: &Ident,
+    first_window: bool,
+) -> (TokenStream, TokenStream, TokenStream) {
+    if !first_window {
+        return (quote!(), quote!(), quote!());
+    }
+    let first = syn::parse_str::<Ident>(&format!("{prefix}_first")).unwrap();
+    let key = syn::parse_str::<Ident>(&format!("{}_key", rule.name)).unwrap();
+    let name = &rule.name;
+    (
+        quote!(let mut #first = false;),
+        quote!(#first = #lock.as_deref().is_some_and(Option::is_none);),
+        quote! {
+            if #first {
+                if let Some(on_first_hit) = &self.__ceiling.on_first_hit {
+                    on_first_hit.call(#name, &#key);
+                }
+            }
+        },
+    )
+}
+
+/// Generates the publishing of a window's `BucketEvent` once its bucket is released, if the rule is enabled.
+/// `fresh` is the local variable holding whether the hit started a new window.
+fn publish(
+    rule: &Rule,
+    key: &Ident,
+    remaining: &TokenStream,
+    tripped: &TokenStream,
+    fresh: &Ident,
+    consumed: &Ident,
+) -> TokenStream {
+    let name = &rule.name;
+    let enabled = enabled(rule);
+    // an atomic hit rejected by another rule leaves the bucket untouched, so only the rules that tripped publish
+    quote! {
+        #[cfg(feature = "events")]
+        if #enabled && (#consumed || #tripped) {
+            let outcome = if #tripped {
+                ceiling::BucketOutcome::Trip
+            } else if #fresh {
+                ceiling::BucketOutcome::Reset
+            } else {
+                ceiling::BucketOutcome::Hit
+            };
+            self.__ceiling.events.publish(#name, &#key, #remaining, outcome);
+        }
+    }
+}
+
+/// Generates a single window of a rule, the prefix is used to name the window's local variables.
+#[allow(clippy::too_many_arguments)]
+fn impl_window(
+    rule: &Rule,
+    window: usize,
+    prefix: &str,
+    store: &Ident,
+    key: &Ident,
+    first_window: bool,
+    async_store: bool,
+    errors: StoreErrors,
+    batched: bool,
+) -> RuleImpl {
+    if rule.leaky {
+        return impl_leaky_window(
+            rule,
+            window,
+            prefix,
+            store,
+            key,
+            first_window,
+            async_store,
+            errors,
+            batched,
+        );
+    }
+    // a counting rule never limits a request, even if the policy denies requests on store errors
+    let errors = match errors {
+        StoreErrors::Handle(_) if rule.count => StoreErrors::Handle(OnError::Allow),
+        errors => errors,
+    };
+    let Rule {
+        public,
+        jitter,
+        aligned,
+        grace,
+        ..
+    } = rule;
+    let cost = cost(rule);
+    let (limit, interval, timeout) = window_limits(window);
+    let ident = |suffix: &str| syn::parse_str::<Ident>(&format!("{prefix}_{suffix}")).unwrap();
+    let lock = ident("lock");
+    let applied = ident("applied");
+    let reset_updated = ident("reset_updated");
+    let jitter_value = ident("jitter");
+    let label = label(prefix, errors);
+    let name = syn::parse_str::<Ident>(prefix).unwrap();
+    let enabled = enabled(rule);
+    // the jitter moves a reset either way, but never before now so the bucket isn't expired as soon as it's saved
+    let (jitter, add_jitter) = if *jitter > 0 {
+        let spread = quote!((#interval as u64) * (#jitter as u64) / 100);
+        (
+            quote!(let #jitter_value = ceiling::jitter_offset(&#key, #spread);),
+            quote!(.saturating_add_signed(#jitter_value).max(now)),
+        )
+    } else {
+        (quote!(), quote!())
+    };
+    // an aligned bucket resets after the last second of the window containing now, the window ends
+    // with the timeout when the limit is reached so the timeout is ignored
+    let (reset, timeout_reset) = if *aligned {
+        let reset = quote!((now - now % (#interval as u64)).saturating_add(#interval as u64 - 1) #add_jitter);
+        (reset.clone(), reset)
+    } else {
+        (
+            quote!(now.saturating_add(#interval as u64) #add_jitter),
+            quote!(now.saturating_add(#timeout as u64) #add_jitter),
+        )
+    };
+    // the reset of an aligned bucket is reported as the boundary the next window starts at, the second after it's stored as
+    let reported = |reset: TokenStream| {
+        if *aligned {
+            quote!((#reset).saturating_add(1))
+        } else {
+            reset
+        }
+    };
+    let store = Store {
+        name: store,
+        async_store,
+        errors,
+        label: label.as_ref(),
+        allow: {
+            let reset = reported(reset.clone());
+            quote!((#limit, #reset, #public, #key))
+        },
+        deny: {
+            let reset = reported(timeout_reset.clone());
+            quote!((0, #reset, #public, #key))
+        },
+    };
+    let (get, set) = get_set(&store, rule, key, quote!(#name, #reset_updated), batched);
+    let prune = store.prune();
+    // the bucket is loaded by applying a hit without a cost, so the state for the hit struct is
+    // available if the hit is never consumed
+    let expiry_now = expiry_now();
+    let apply = |state: TokenStream, cost: &TokenStream| quote!(ceiling::apply_hit(#state, #limit, #reset, #timeout_reset, #expiry_now, #cost));
+    // a disabled rule doesn't lock its bucket, so it's loaded as a new bucket
+    let load_state = apply(quote!(#lock.as_deref().copied().flatten()), &quote!(0));
+    // a rule with a grace starts a bucket that isn't in the store with the cost of the grace's hits on top of the limit,
+    // the requests remaining are reported as at most the limit so the first hits don't appear to take from it
+    let (add_grace, remaining) = if *grace > 0 {
+        (
+            quote! {
+                if #lock.as_deref().is_some_and(Option::is_none) {
+                    #name.0 = #name.0.saturating_add(#grace.saturating_mul(#cost));
+                }
+            },
+            quote!(#name.0.min(#limit)),
+        )
+    } else {
+        (quote!(), quote!(#name.0))
+    };
+    let (declare_first, mark_first, call_first) = first_hit(rule, prefix, &lock, first_window);
+    let consumed = ident("consumed");
+    let mark_consumed = quote! {
+        #[cfg(feature = "events")]
+        {
+            #consumed = true;
+        }
+    };
+    let fresh = ident("fresh");
+    let load = quote! {
+        #jitter
+        let #lock = if #enabled { Some(#get) } else { None };
+        let (mut #name, _, mut #reset_updated) = #load_state;
+        #[cfg(feature = "events")]
+        let #fresh = #reset_updated;
+        #add_grace
+        #declare_first
+        #[cfg(feature = "events")]
+        let mut #consumed = false;
+    };
+    // a counting rule takes from its bucket but never trips
+    let (load, consume, trips) = if rule.count {
+        let consume = quote! {
+            if #enabled {
+                #mark_first
+                #mark_consumed
+                #name.0 = #name.0.saturating_sub(#cost);
+                #set;
+            }
+        };
+        (load, consume, quote!(false))
+    } else {
+        let apply_state = apply(quote!(Some(#name)), &cost);
+        // a rule extending on abuse restarts the timeout on every limited hit, not just the one emptying the bucket
+        let extend = if rule.extend {
+            quote! {
+                let #applied = match #applied {
+                    (_, true, _) => ((0, #timeout_reset), true, true),
+                    applied => applied,
+                };
+            }
+        } else {
+            quote!()
+        };
+        // a distinct rule only takes from its bucket for values that haven't been seen in the current window,
+        // each value seen is marked by a bucket keyed by the value that expires with the window
+        let (seen, apply_state, mark) = if rule.distinct.is_empty() {
+            (quote!(), apply_state, quote!())
+        } else {
+            let seen = ident("seen");
+            let seen_key = ident("seen_key");
+            let value = syn::parse_str::<Ident>(&format!("{}_distinct", rule.name)).unwrap();
+            let read = store.read(quote!(&#seen_key));
+            let mark = store.call("set", quote!(&#seen_key, (0, #name.1), true));
+            (
+                quote! {
+                    let #seen_key = format!("{}#{}", #key, #value);
+                    let #seen = #enabled && matches!(#read, Some((_, reset)) if reset >= #expiry_now);
+                },
+                quote! {
+                    if #seen {
+                        (#name, false, false)
+                    } else {
+                        #apply_state
+                    }
+                },
+                quote! {
+                    if !#seen && !#applied.1 {
+                        #mark;
+                    }
+                },
+            )
+        };
+        // a forgiving rule keeps the time of the last limited hit in a bucket keyed by `last` that expires with the timeout,
+        // as the time until the reset and the reset at that hit. A client that has been quiet for longer than the rule's
+        // time to forgive has the rest of its timeout shortened in proportion, so it's halved after twice that time
+        let (forgive, record) = if rule.forgive > 0 {
+            let forgive = rule.forgive as u64;
+            let last_key = ident("last_key");
+            let read = store.read(quote!(&#last_key));
+            let record = store.call(
+                "set",
+                quote!(&#last_key, (#name.1.saturating_sub(now).min(u32::MAX as u64) as u32, #name.1), true),
+            );
+            (
+                quote! {
+                    let #last_key = format!("{}#last", #key);
+                    if #enabled && #name.0 == 0 && #name.1 >= now {
+                        if let Some((until_reset, reset)) = #read {
+                            let quiet = now.saturating_sub(reset.saturating_sub(until_reset as u64));
+                            if quiet >= #forgive {
+                                #name.1 = now + ((#name.1 - now) as u128 * #forgive as u128 / quiet as u128) as u64;
+                                #reset_updated = true;
+                            }
+                        }
+                    }
+                },
+                quote! {
+                    if #applied.1 {
+                        #record;
+                    }
+                },
+            )
+        } else {
+            (quote!(), quote!())
+        };
+        let load = quote! {
+            #load
+            #seen
+            #forgive
+            let #applied = #apply_state;
+            #extend
+        };
+        // an empty bucket isn't saved again unless it was just reset
+        let consume = quote! {
+            if #enabled {
+                #mark_first
+                #mark_consumed
+                #reset_updated |= #applied.2;
+                if #applied.0 != #name || #reset_updated {
+                    #name = #applied.0;
+                    #set;
+                }
+                #mark
+                #record
+                if #applied.1 {
+                    hit = true;
+                }
+            }
+        };
+        (load, consume, quote!((#enabled && #applied.1)))
+    };
+    let tripped = if rule.count {
+        quote!(false)
+    } else {
+        quote!(#applied.1)
+    };
+    let publish = publish(rule, key, &remaining, &tripped, &fresh, &consumed);
+    // a bucket kept past its reset by the clock skew tolerance is reported as resetting once the tolerance is up,
+    // so clients aren't told to retry while it's still limited
+    let reported_reset = reported(quote! {
+        if #name.1 < now {
+            #name.1.saturating_add(self.__ceiling.clock_skew)
+        } else {
+            #name.1
+        }
+    });
+    let finish = quote! {
+        drop(#lock);
+        #prune;
+        #call_first
+        #publish
+        (#remaining, #reported_reset, #public, #key)
+    };
+    RuleImpl {
+        sequential: block(
+            label.as_ref(),
+            quote! {
+                #load
+                #consume
+                #finish
+            },
+        ),
+        load,
+        consume,
+        trips,
+        finish,
+    }
+}
+
+/// A leaky rule stores its bucket as the remaining requests and the time of the last leak plus the time to fill an
+/// empty bucket, the latest the bucket can be full again. This means the store can prune the bucket like any other once
+/// it's expired, since a bucket that's full is the same as a missing one. Requests leak back into the bucket at `refill`
+/// per `interval` since the last leak, found by taking the time to fill back off, and the time of the last leak is only
+/// moved forward by the time taken to leak whole requests so no partial requests are lost between frequent hits.
+/// The reset reported is the time the bucket is actually full again. The capacity and refill are both the limit unless
+/// they're resolved from the key.
+#[allow(clippy::too_many_arguments)]
+fn impl_leaky_window(
+    rule: &Rule,
+    window: usize,
+    prefix: &str,
+    store: &Ident,
+    key: &Ident,
+    first_window: bool,
+    async_store: bool,
+    errors: StoreErrors,
+    batched: bool,
+) -> RuleImpl {
+    let public = rule.public;
+    let cost = cost(rule);
+    let ident = |suffix: &str| syn::parse_str::<Ident>(&format!("{prefix}_{suffix}")).unwrap();
+    let lock = ident("lock");
+    let label = label(prefix, errors);
+    let name = syn::parse_str::<Ident>(prefix).unwrap();
+    let enabled = enabled(rule);
+    let (limit, interval, _) = window_limits(window);
+    let (limit, interval) = (quote!((#limit as u64)), quote!((#interval as u64)));
+    let (capacity, refill) = (ident("capacity"), ident("refill"));
+    let (capacity, refill) = (quote!(#capacity), quote!(#refill));
+    let fill = quote!((#capacity * #interval).div_ceil(#refill));
+    let store = Store {
+        name: store,
+        async_store,
+        errors,
+        label: label.as_ref(),
+        allow: quote!((#limit as u32, now, #public, #key)),
+        deny: quote!((0, now.saturating_add(#interval), #public, #key)),
+    };
+    let (get, set) = get_set(
+        &store,
+        rule,
+        key,
+        quote!((#name.0, #name.1.saturating_add(#fill)), true),
+        batched,
+    );
+    let prune = store.prune();
+    let leak = leak(
+        quote!(#lock.as_deref().copied().flatten()),
+        &capacity,
+        &refill,
+        &interval,
+    );
+    // a resolved capacity is cached by the resolver until a bucket emptied when it was resolved would be full again
+    let resolve = if rule.windows.len() == 1 {
+        let resolved = resolved_capacity(
+            window,
+            &limit,
+            quote!(resolver.resolve(&#key, now, #interval)),
+            Some(&enabled),
+        );
+        quote!(let (#capacity, #refill) = #resolved;)
+    } else {
+        quote!(let (#capacity, #refill) = (#limit, #limit);)
+    };
+    let (declare_first, mark_first, call_first) = first_hit(rule, prefix, &lock, first_window);
+    let consumed = ident("consumed");
+    let mark_consumed = quote! {
+        #[cfg(feature = "events")]
+        {
+            #consumed = true;
+        }
+    };
+    // a full bucket is the same as a missing one, so a hit on it starts a new window
+    let fresh = ident("fresh");
+    let load = quote! {
+        #resolve
+        let #lock = if #enabled { Some(#get) } else { None };
+        let mut #name = #leak;
+        #[cfg(feature = "events")]
+        let #fresh = #name.0 as u64 == #capacity;
+        #declare_first
+        #[cfg(feature = "events")]
+        let mut #consumed = false;
+    };
+    let consume = quote! {
+        if #enabled {
+            #mark_first
+            #mark_consumed
+            let trips = #name.0 <= #cost;
+            #name.0 = #name.0.saturating_sub(#cost);
+            #set;
+            if trips {
+                hit = true;
+            }
+        }
+    };
+    // a bucket that wasn't consumed is only published if it would have tripped
+    let publish = publish(
+        rule,
+        key,
+        &quote!(#name.0),
+        &quote!((!#consumed || #name.0 == 0)),
+        &fresh,
+        &consumed,
+    );
+    let finish = quote! {
+        drop(#lock);
+        #prune;
+        #call_first
+        #publish
+        (#name.0, #name.1.saturating_add(((#capacity - #name.0 as u64) * #interval).div_ceil(#refill)), #public, #key)
+    };
+    RuleImpl {
+        sequential: block(
+            label.as_ref(),
+            quote! {
+                #load
+                #consume
+                #finish
+            },
+        ),
+        load,
+        consume,
+        trips: quote!(#name.0 <= #cost),
+        finish,
+    }
+}
+
+/// Generates an expression evaluating to the capacity and refill rate of a leaky window as `u64`s, the limit for both
+/// unless a capacity resolver is set for the window, in which case `resolve` evaluates to them with `resolver` in scope.
+/// The resolver is only consulted while the rule is enabled if `enabled` is given.
+fn resolved_capacity(
+    window: usize,
+    limit: &TokenStream,
+    resolve: TokenStream,
+    enabled: Option<&Ident>,
+) -> TokenStream {
+    let guard = enabled.map(|enabled| quote!(if #enabled));
+    quote! {
+        match &self.__ceiling.capacity_resolvers[#window] {
+            Some(resolver) #guard => #resolve,
+            _ => (#limit, #limit),
+        }
+    }
+}
+
+/// Generates an expression leaking requests back into the stored state of a leaky bucket, evaluating to the
+/// requests remaining and the time of the last leak. The capacity, refill, and interval are expected as `u64`s.
+fn leak(
+    state: TokenStream,
+    capacity: &TokenStream,
+    refill: &TokenStream,
+    interval: &TokenStream,
+) -> TokenStream {
+    quote! {
+        match #state {
+            Some((remaining, full)) => {
+                let last = full.saturating_sub((#capacity * #interval).div_ceil(#refill));
+                let leaked = now.saturating_sub(last).saturating_mul(#refill) / #interval;
+                let remaining = (remaining as u64).saturating_add(leaked).min(#capacity);
+                if remaining == #capacity {
+                    (remaining as u32, now)
+                } else {
+                    (remaining as u32, last + leaked * #interval / #refill)
+                }
+            },
+            None => (#capacity as u32, now),
+        }
+    }
+}
+
+/// Generates statements giving `amount` requests back to each window of an enabled rule, up to the limit.
+/// A window without a bucket or whose bucket has expired is already full, so it's left alone.
+pub fn impl_credit(rule: &Rule, offset: usize, async_store: bool) -> TokenStream {
+    let expiry_now = expiry_now();
+    let name = &rule.name;
+    let store = syn::parse_str::<Ident>(name).unwrap();
+    let key = syn::parse_str::<Ident>(&format!("{name}_key")).unwrap();
+    let enabled = enabled(rule);
+    let await_store = if async_store {
+        quote!(.await)
+    } else {
+        quote!()
+    };
+    let windows = (0..rule.windows.len()).map(|i| {
+        let key = if rule.windows.len() > 1 {
+            quote!(&format!("{}#{}", #key, #i))
+        } else {
+            quote!(&#key)
+        };
+        let (limit, interval, _) = window_limits(offset + i);
+        if rule.leaky {
+            let (limit, interval) = (quote!((#limit as u64)), quote!((#interval as u64)));
+            let (capacity, refill) = (quote!(capacity), quote!(refill));
+            let leak = leak(quote!(Some(state)), &capacity, &refill, &interval);
+            let resolved = resolved_leaky(rule, offset + i, &limit);
+            quote! {{
+                let key = #key;
+                let (capacity, refill) = #resolved;
+                let lock = self.#store.get(key)#await_store;
+                if let Some(state) = *lock {
+                    let (remaining, last) = #leak;
+                    let credited = (remaining as u64).saturating_add(amount as u64).min(capacity) as u32;
+                    self.#store.set(key, (credited, last.saturating_add((capacity * #interval).div_ceil(refill))), true)#await_store;
+                }
+            }}
+        } else {
+            // a bucket holding more than the limit from a grace keeps it
+            quote! {{
+                let key = #key;
+                let lock = self.#store.get(key)#await_store;
+                if let Some((remaining, reset)) = *lock {
+                    if reset >= #expiry_now {
+                        let credited = remaining.saturating_add(amount).min(#limit).max(remaining);
+                        self.#store.set(key, (credited, reset), false)#await_store;
+                    }
+                }
+            }}
+        }
+    });
+    quote! {
+        if #enabled {
+            #(#windows)*
+        }
+    }
+}
+
+/// Generates statements setting a full bucket for each window of a rule keyed by `key`, the state a hit without a cost
+/// would give a new bucket, counting the buckets set in `preloaded`. Buckets already in the store that haven't expired are left alone.
+pub fn impl_preload(rule: &Rule, offset: usize, async_store: bool) -> TokenStream {
+    let expiry_now = expiry_now();
+    let store = syn::parse_str::<Ident>(&rule.name).unwrap();
+    let await_store = if async_store {
+        quote!(.await)
+    } else {
+        quote!()
+    };
+    let windows = (0..rule.windows.len()).map(|i| {
+        let window_key = if rule.windows.len() > 1 {
+            quote!(&format!("{}#{}", key, #i))
+        } else {
+            quote!(key)
+        };
+        let (limit, interval, _) = window_limits(offset + i);
+        let state = if rule.leaky {
+            let limit = quote!((#limit as u64));
+            let resolved = resolved_leaky(rule, offset + i, &limit);
+            quote! {{
+                let (capacity, refill) = #resolved;
+                (capacity as u32, now.saturating_add((capacity * (#interval as u64)).div_ceil(refill)))
+            }}
+        } else {
+            let jitter = if rule.jitter > 0 {
+                let jitter = rule.jitter;
+                quote!(.saturating_add_signed(ceiling::jitter_offset(window_key, (#interval as u64) * (#jitter as u64) / 100)).max(now))
+            } else {
+                quote!()
+            };
+            let reset = if rule.aligned {
+                quote!((now - now % (#interval as u64)).saturating_add(#interval as u64 - 1) #jitter)
+            } else {
+                quote!(now.saturating_add(#interval as u64) #jitter)
+            };
+            // without a hit a cost taken from an input is 1
+            let grace = rule.grace.saturating_mul(rule.cost);
+            quote!((#limit.saturating_add(#grace), #reset))
+        };
+        quote! {{
+            let window_key = #window_key;
+            let lock = self.#store.get(window_key)#await_store;
+            if !matches!(*lock, Some((_, reset)) if reset >= #expiry_now) {
+                self.#store.set(window_key, #state, true)#await_store;
+                preloaded += 1;
+            }
+        }}
+    });
+    quote!(#(#windows)*)
+}
+
+/// Generates an expression evaluating to the capacity and refill rate of a leaky window with the bucket keyed by `key`
+/// outside of a hit, resolving them without caching them if they aren't cached.
+fn resolved_leaky(rule: &Rule, window: usize, limit: &TokenStream) -> TokenStream {
+    if rule.windows.len() > 1 {
+        return quote!((#limit, #limit));
+    }
+    resolved_capacity(
+        window,
+        limit,
+        quote!(resolver
+            .cached(key, now)
+            .unwrap_or_else(|| resolver.call(key))),
+        None,
+    )
+}
+
+/// Generates an expression evaluating to the requests remaining for a rule without locking or changing
+/// its buckets, the fewest remaining in any window if the rule has several.
+pub fn impl_remaining(rule: &Rule, offset: usize, async_store: bool) -> TokenStream {
+    let expiry_now = expiry_now();
+    let name = &rule.name;
+    let store = syn::parse_str::<Ident>(name).unwrap();
+    let key = syn::parse_str::<Ident>(&format!("{name}_key")).unwrap();
+    let await_store = if async_store {
+        quote!(.await)
+    } else {
+        quote!()
+    };
+    let windows = (0..rule.windows.len()).map(|i| {
+        let key = if rule.windows.len() > 1 {
+            quote!(&format!("{}#{}", #key, #i))
+        } else {
+            quote!(&#key)
+        };
+        let (limit, interval, _) = window_limits(offset + i);
+        let interval = quote!((#interval as u64));
+        if rule.leaky {
+            let (capacity, refill) = (quote!(capacity), quote!(refill));
+            let leak = leak(
+                quote!(self.#store.read(key)#await_store),
+                &capacity,
+                &refill,
+                &interval,
+            );
+            let resolved = resolved_leaky(rule, offset + i, &quote!((#limit as u64)));
+            quote! {{
+                let key = #key;
+                let (capacity, refill) = #resolved;
+                #leak.0
+            }}
+        } else {
+            quote! {
+                match self.#store.read(#key)#await_store {
+                    Some((remaining, reset)) if reset >= #expiry_now => remaining.min(#limit),
+                    _ => #limit,
+                }
+            }
+        }
+    });
+    let windows = windows.collect::<Vec<_>>();
+    let (first, rest) = windows.split_first().unwrap();
+    quote!((#first) #(.min(#rest))*)
+}
@@ -0,0 +1,28 @@
+use ceiling::SyncStore;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+ceiling::rate_limiter! {
+    ip, route in {
+        main = pub 1m requests every 1 hour for { ip };
+        routes = 1m requests every 1 hour for { ip + route };
+    } as RateLimiter
+}
+
+fn hit(c: &mut Criterion) {
+    let limiter = RateLimiter::new();
+    c.bench_function("hit", |b| b.iter(|| limiter.hit("1.1.1.1", "/route")));
+}
+
+#[cfg(feature = "clock")]
+fn hit_coarse_clock(c: &mut Criterion) {
+    let limiter = RateLimiter::new().with_clock(ceiling::CoarseClock::new());
+    c.bench_function("hit_coarse_clock", |b| {
+        b.iter(|| limiter.hit("1.1.1.1", "/route"))
+    });
+}
+
+#[cfg(feature = "clock")]
+criterion_group!(benches, hit, hit_coarse_clock);
+#[cfg(not(feature = "clock"))]
+criterion_group!(benches, hit);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use ceiling::{DefaultStore, SyncStore};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn hit(store: &DefaultStore, key: &str) {
+    let lock = store.get(key);
+    let value = (*lock).unwrap_or((u32::MAX, u64::MAX));
+    store.set(key, (value.0.saturating_sub(1), value.1), false);
+}
+
+fn uncontended(c: &mut Criterion) {
+    let store = DefaultStore::new();
+    c.bench_function("uncontended hit", |b| b.iter(|| hit(&store, "1.1.1.1")));
+}
+
+fn contended(c: &mut Criterion) {
+    let store = Arc::new(DefaultStore::new());
+    c.bench_function("contended hit, 4 threads", |b| {
+        b.iter(|| {
+            let threads = (0..4)
+                .map(|_| {
+                    let store = store.clone();
+                    std::thread::spawn(move || {
+                        for _ in 0..100 {
+                            hit(&store, "1.1.1.1");
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+        })
+    });
+}
+
+criterion_group!(benches, uncontended, contended);
+criterion_main!(benches);
@@ -0,0 +1,141 @@
+use std::borrow::Cow;
+
+/// Applies a hit costing `cost` requests to the state of a fixed window bucket, returning the new state,
+/// whether the hit is limited, and whether the reset was updated.
+/// This is the algorithm used by the code generated by `rate_limiter!`, so stores that apply hits themselves
+/// (e.g. in a script running on a remote store) can stay consistent with it.
+///
+/// A missing bucket or one whose reset is before now starts full with `limit` requests resetting at `reset`.
+/// If at least `cost` requests remain they're taken from the bucket, otherwise the hit is limited and a bucket
+/// that wasn't already empty is emptied and resets at `timeout_reset`, so a hit that empties the bucket is limited.
+/// The resets are absolute times in seconds rather than durations, as jitter and aligned windows change how they're computed.
+pub fn apply_hit(
+    state: Option<(u32, u64)>,
+    limit: u32,
+    reset: u64,
+    timeout_reset: u64,
+    now: u64,
+    cost: u32,
+) -> ((u32, u64), bool, bool) {
+    let (state, reset_updated) = match state {
+        Some(state) if state.1 >= now => (state, false),
+        _ => ((limit, reset), true),
+    };
+    if state.0 > cost {
+        ((state.0 - cost, state.1), false, reset_updated)
+    } else if state.0 > 0 {
+        ((0, timeout_reset), true, true)
+    } else {
+        (state, true, reset_updated)
+    }
+}
+
+/// Parses the cost of a hit from the value of an input, used by rules with a `cost { input }` clause.
+/// A whole number too large for a `u32` costs `u32::MAX`, and a value that isn't a whole number costs 1 like a rule without a cost.
+pub fn parse_cost(value: &str) -> u32 {
+    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+        value.parse().unwrap_or(u32::MAX)
+    } else {
+        1
+    }
+}
+
+/// Escapes each `\` and `#` in a key with a `\`, used for the keys of distinct and forgiving rules by the code generated by `rate_limiter!`.
+/// Their stores also hold buckets keyed by the rule's key followed by a `#` and a suffix, so escaping the key keeps a key
+/// containing `#` from colliding with the suffixed bucket of another key. Keys without either character are returned as they are.
+pub fn escape_key(key: String) -> String {
+    if !key.contains(['\\', '#']) {
+        return key;
+    }
+    let mut escaped = String::with_capacity(key.len() + 2);
+    for c in key.chars() {
+        if matches!(c, '\\' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Returns the key given to `escape_key` for a bucket keyed by an escaped key, dropping any suffix after the first unescaped `#`.
+pub fn unescape_key(bucket: &str) -> Cow<'_, str> {
+    if !bucket.contains('\\') {
+        return Cow::Borrowed(bucket.split_once('#').map_or(bucket, |(key, _)| key));
+    }
+    let mut key = String::with_capacity(bucket.len());
+    let mut chars = bucket.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => key.extend(chars.next()),
+            '#' => break,
+            c => key.push(c),
+        }
+    }
+    Cow::Owned(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_costs() {
+        assert_eq!(parse_cost("25"), 25);
+        assert_eq!(parse_cost("0"), 0);
+        assert_eq!(parse_cost("99999999999999999999"), u32::MAX);
+        assert_eq!(parse_cost("-5"), 1);
+        assert_eq!(parse_cost(""), 1);
+    }
+
+    #[test]
+    fn escaped_keys() {
+        assert_eq!(escape_key("1.1.1.1+/".into()), "1.1.1.1+/");
+        assert_eq!(escape_key("a#b\\c".into()), "a\\#b\\\\c");
+        assert_eq!(unescape_key("1.1.1.1+/"), "1.1.1.1+/");
+        assert_eq!(unescape_key("a#last"), "a");
+        assert_eq!(unescape_key("a\\#b\\\\c"), "a#b\\c");
+        assert_eq!(unescape_key("a\\#b\\\\c#/1#2"), "a#b\\c");
+        // the marker of key `a` and value `b#c` and the marker of key `a#b` and value `c` differ
+        assert_ne!(
+            format!("{}#{}", escape_key("a".into()), "b#c"),
+            format!("{}#{}", escape_key("a#b".into()), "c")
+        );
+    }
+
+    #[test]
+    fn apply_hit_transitions() {
+        // a missing bucket starts full
+        assert_eq!(apply_hit(None, 3, 10, 20, 0, 1), ((2, 10), false, true));
+        assert_eq!(
+            apply_hit(Some((2, 10)), 3, 11, 21, 1, 1),
+            ((1, 10), false, false)
+        );
+        // the hit that empties the bucket is limited and the timeout starts
+        assert_eq!(
+            apply_hit(Some((1, 10)), 3, 12, 22, 2, 1),
+            ((0, 22), true, true)
+        );
+        assert_eq!(
+            apply_hit(Some((0, 22)), 3, 13, 23, 3, 1),
+            ((0, 22), true, false)
+        );
+        // the bucket resets in the second after its reset
+        assert_eq!(
+            apply_hit(Some((0, 22)), 3, 32, 42, 22, 1),
+            ((0, 22), true, false)
+        );
+        assert_eq!(
+            apply_hit(Some((0, 22)), 3, 33, 43, 23, 1),
+            ((2, 33), false, true)
+        );
+        // a cost of zero only loads the bucket
+        assert_eq!(
+            apply_hit(Some((0, 22)), 3, 13, 23, 3, 0),
+            ((0, 22), true, false)
+        );
+        assert_eq!(
+            apply_hit(Some((2, 10)), 3, 13, 23, 3, 0),
+            ((2, 10), false, false)
+        );
+    }
+}
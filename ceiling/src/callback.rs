@@ -0,0 +1,145 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use dashmap::DashMap;
+
+type Callback = dyn Fn(&str, &str) + Send + Sync;
+
+/// A callback invoked by a rate limiter with the name of a rule and the key of a bucket, set with the generated `with_on_first_hit` method.
+/// Clones share the callback.
+#[derive(Clone)]
+pub struct RuleCallback(Arc<Callback>);
+
+impl RuleCallback {
+    /// Creates a callback from a function taking the name of the rule and the key of the bucket.
+    pub fn new<F: Fn(&str, &str) + Send + Sync + 'static>(callback: F) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    /// Invokes the callback.
+    pub fn call(&self, rule: &str, key: &str) {
+        (self.0)(rule, key)
+    }
+}
+
+impl std::fmt::Debug for RuleCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RuleCallback").finish_non_exhaustive()
+    }
+}
+
+type Resolver = dyn Fn(&str) -> (u32, u32) + Send + Sync;
+
+/// A function resolving the capacity and refill rate of a leaky rule's bucket from its key, set with the generated
+/// `with_capacity_resolver` method, e.g. to look up the tier of a tenant. Clones share the resolver and its cache.
+#[derive(Clone)]
+pub struct CapacityResolver {
+    resolver: Arc<Resolver>,
+    // the capacity, refill rate, and the time the values expire for each key resolved during a hit
+    cache: Arc<DashMap<String, (u64, u64, u64)>>,
+    // the time expired values were last removed from the cache
+    pruned: Arc<AtomicU64>,
+}
+
+impl CapacityResolver {
+    /// Creates a resolver from a function taking the key of the bucket and returning its capacity and the requests
+    /// leaked back into it every interval of the rule.
+    pub fn new<F: Fn(&str) -> (u32, u32) + Send + Sync + 'static>(resolver: F) -> Self {
+        Self {
+            resolver: Arc::new(resolver),
+            cache: Arc::new(DashMap::new()),
+            pruned: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Resolves the capacity and refill rate of the bucket, both at least one.
+    pub fn call(&self, key: &str) -> (u64, u64) {
+        let (capacity, refill) = (self.resolver)(key);
+        (capacity.max(1) as u64, refill.max(1) as u64)
+    }
+
+    /// Returns the cached capacity and refill rate of the bucket, resolving and caching them if they aren't cached or have expired.
+    /// The values are cached until a bucket emptied now would be full again, given the rule's interval in seconds.
+    /// Expired values are removed from the cache at most once a second.
+    pub fn resolve(&self, key: &str, now: u64, interval: u64) -> (u64, u64) {
+        if let Some(resolved) = self.cached(key, now) {
+            return resolved;
+        }
+        let (capacity, refill) = self.call(key);
+        let expires = now.saturating_add((capacity * interval).div_ceil(refill));
+        if self.pruned.swap(now, Ordering::Relaxed) < now {
+            self.cache.retain(|_, (_, _, expires)| *expires >= now);
+        }
+        self.cache
+            .insert(key.to_string(), (capacity, refill, expires));
+        (capacity, refill)
+    }
+
+    /// Returns the cached capacity and refill rate of the bucket, if they haven't expired.
+    pub fn cached(&self, key: &str, now: u64) -> Option<(u64, u64)> {
+        self.cache
+            .get(key)
+            .filter(|resolved| resolved.2 >= now)
+            .map(|resolved| (resolved.0, resolved.1))
+    }
+
+    /// Removes the cached values of the bucket, so the next hit resolves them again.
+    pub fn forget(&self, key: &str) {
+        self.cache.remove(key);
+    }
+
+    /// Removes every cached value.
+    pub fn clear(&self) {
+        self.cache.clear();
+    }
+}
+
+impl std::fmt::Debug for CapacityResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CapacityResolver").finish_non_exhaustive()
+    }
+}
+
+/// The decision of a rule on a hit, passed to the sink set with the generated `with_decision_sink` method.
+/// With the crate feature `serde` enabled this implements `serde::Serialize`, e.g. for writing decisions as JSON lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Decision<'a> {
+    /// The name of the rule.
+    pub rule: &'static str,
+    /// The key of the rule's bucket, even if the rule hides its key.
+    pub key: &'a str,
+    /// The requests remaining in the bucket after the hit.
+    pub remaining: u32,
+    /// Whether the rule limited the hit, counting rules never do.
+    pub limited: bool,
+    /// The time of the hit in seconds since the epoch.
+    pub now: u64,
+}
+
+type Sink = dyn Fn(&Decision<'_>) + Send + Sync;
+
+/// A sink receiving the decision of every rule on each hit, set with the generated `with_decision_sink` method.
+/// The sink is called synchronously on the thread making the hit. Clones share the sink.
+#[derive(Clone)]
+pub struct DecisionSink(Arc<Sink>);
+
+impl DecisionSink {
+    /// Creates a sink from a function taking a decision.
+    pub fn new<F: Fn(&Decision<'_>) + Send + Sync + 'static>(sink: F) -> Self {
+        Self(Arc::new(sink))
+    }
+
+    /// Passes a decision to the sink.
+    pub fn call(&self, decision: &Decision<'_>) {
+        (self.0)(decision)
+    }
+}
+
+impl std::fmt::Debug for DecisionSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("DecisionSink").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,84 @@
+use crate::RateLimitError;
+
+/// The client-facing state of a hit, implemented by every hit struct generated by the `rate_limiter!` macro
+/// so hits on different rate limiters can be combined with `chain!`.
+pub trait HitSummary {
+    /// The headers of the hit, the same as the hit struct's `to_headers` method.
+    fn to_headers(&self) -> Vec<(&'static str, String)>;
+
+    /// The error body if the request was limited, the same as the hit struct's `to_error_body` method.
+    fn to_error_body(&self) -> Option<RateLimitError>;
+}
+
+/// The hits on several rate limiters combined by `chain!`, in the order they were made.
+#[derive(Default)]
+pub struct ChainedHit {
+    hits: Vec<Box<dyn HitSummary + Send + Sync>>,
+}
+
+impl ChainedHit {
+    /// Adds a hit to the end of the chain.
+    pub fn push<H: HitSummary + Send + Sync + 'static>(&mut self, hit: H) {
+        self.hits.push(Box::new(hit));
+    }
+
+    /// The number of hits in the chain.
+    pub fn len(&self) -> usize {
+        self.hits.len()
+    }
+
+    /// Whether the chain has no hits.
+    pub fn is_empty(&self) -> bool {
+        self.hits.is_empty()
+    }
+}
+
+impl std::fmt::Debug for ChainedHit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChainedHit")
+            .field("hits", &self.hits.len())
+            .finish()
+    }
+}
+
+impl HitSummary for ChainedHit {
+    /// The headers of every hit in order.
+    fn to_headers(&self) -> Vec<(&'static str, String)> {
+        self.hits.iter().flat_map(|hit| hit.to_headers()).collect()
+    }
+
+    /// The error body of the hit whose limited rule resets last, like the error body of a single hit.
+    fn to_error_body(&self) -> Option<RateLimitError> {
+        self.hits
+            .iter()
+            .filter_map(|hit| hit.to_error_body())
+            .reduce(|a, b| if b.retry_after > a.retry_after { b } else { a })
+    }
+}
+
+/// Hits several rate limiters in one call, e.g. a global rate limiter and a per-route one.
+/// Every hit is made, and the result is whether any of them limited the request along with a `ceiling::ChainedHit` combining them.
+///
+/// ```
+/// use ceiling::HitSummary;
+/// # use ceiling::SyncStore;
+/// # ceiling::rate_limiter! { ip in { all = 100 requests every 1 second for { ip }; } as GlobalRateLimiter }
+/// # ceiling::rate_limiter! { ip, route in { main = 2 requests every 2 seconds for { ip + route }; } as RouteRateLimiter }
+/// # let (global, routes, ip, route) = (GlobalRateLimiter::new(), RouteRateLimiter::new(), "1.1.1.1", "/example");
+///
+/// let (limited, hit) = ceiling::chain!(global.hit(ip), routes.hit(ip, route));
+/// let headers = hit.to_headers();
+/// ```
+#[macro_export]
+macro_rules! chain {
+    ($($hit:expr),+ $(,)?) => {{
+        let mut chained = $crate::ChainedHit::default();
+        let mut limited = false;
+        $(
+            let (hit_limited, hit) = $hit;
+            limited |= hit_limited;
+            chained.push(hit);
+        )+
+        (limited, chained)
+    }};
+}
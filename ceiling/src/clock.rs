@@ -0,0 +1,98 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
+
+/// A source of the current time in seconds since the epoch, read by the generated methods of a rate limiter given one with `with_clock`.
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    /// Returns the current time in seconds since the epoch.
+    fn now_secs(&self) -> u64;
+}
+
+/// The clock rate limiters use without one, reading the system time on every call.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_secs(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+}
+
+/// A clock caching the current second, refreshed from the system time by a background thread, so reading it is an atomic load
+/// instead of a system call. The time read lags the system time by up to the refresh interval, which is plenty for rules
+/// counted in whole seconds. The thread is started when the clock is created and stopped when it's dropped.
+#[derive(Debug)]
+pub struct CoarseClock {
+    now: Arc<AtomicU64>,
+    // dropping the sender wakes the thread and stops it
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl CoarseClock {
+    /// Creates a clock refreshed every 250 milliseconds.
+    pub fn new() -> Self {
+        Self::with_interval(Duration::from_millis(250))
+    }
+
+    /// Creates a clock refreshed every interval.
+    pub fn with_interval(interval: Duration) -> Self {
+        let now = Arc::new(AtomicU64::new(SystemClock.now_secs()));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = std::thread::spawn({
+            let now = now.clone();
+            move || {
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    now.store(SystemClock.now_secs(), Ordering::Relaxed);
+                }
+            }
+        });
+        Self {
+            now,
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Default for CoarseClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for CoarseClock {
+    fn now_secs(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for CoarseClock {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coarse_clock() {
+        let clock = CoarseClock::with_interval(Duration::from_millis(10));
+        assert!(SystemClock.now_secs() - clock.now_secs() <= 1);
+        std::thread::sleep(Duration::from_millis(1100));
+        assert!(SystemClock.now_secs() - clock.now_secs() <= 1);
+        drop(clock);
+    }
+}
@@ -0,0 +1,57 @@
+use std::borrow::Cow;
+
+use crate::Rule;
+
+/// The configuration of a rule given in the `rate_limiter!` macro, listed in the generated `RULES` constant.
+/// With the crate feature `serde` enabled this implements `serde::Serialize`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RuleInfo {
+    /// The name of the rule.
+    pub name: &'static str,
+    /// The limit, interval, and timeout of each window of the rule, in `RateLimiter::config` this includes any runtime override.
+    pub windows: Cow<'static, [Rule]>,
+    /// The parts of the key, each part is the first non-empty input of a list, which only has more than one input for `coalesce(...)` parts.
+    pub key: &'static [&'static [&'static str]],
+    /// Whether the rule is a global limit given with `for global`, with a single bucket keyed by the rule's name.
+    pub global: bool,
+    /// Whether the rule is public, in `RateLimiter::config` this includes any runtime override.
+    pub public: bool,
+    /// The cost of a hit, unused if the cost is taken from an input.
+    pub cost: u32,
+    /// The input the cost of each hit is parsed from, given with `cost { input }`.
+    pub cost_input: Option<&'static str>,
+    /// The most the reset is moved earlier or later by jitter, in percent of the interval.
+    pub jitter: u32,
+    /// Whether the rule is a leaky bucket.
+    pub leaky: bool,
+    /// Whether the windows are aligned to the clock instead of the first hit.
+    pub aligned: bool,
+    /// Whether each limited hit restarts the timeout.
+    pub extend: bool,
+    /// Whether the rule only counts requests.
+    pub count: bool,
+    /// Whether the key is hidden from the headers and serialized output.
+    pub hide_key: bool,
+    /// The static labels added to the rule's metrics.
+    pub tags: &'static [(&'static str, &'static str)],
+    /// The parts of the value counted by a distinct rule, empty if the rule isn't distinct.
+    pub distinct: &'static [&'static [&'static str]],
+    /// The reason given to clients when the rule limits a request.
+    pub reason: Option<&'static str>,
+    /// The number of hits on a new bucket that aren't taken from it.
+    pub grace: u32,
+    /// The seconds a limited client has to stay quiet before its timeout starts being shortened, zero if the rule doesn't forgive.
+    pub forgive: u32,
+}
+
+/// The configuration of a rate limiter, returned from the generated `config` method.
+/// With the crate feature `serde` enabled this implements `serde::Serialize`, e.g. for diffing the configuration between deploys.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LimiterConfig {
+    /// The names of the inputs of the rate limiter, in the order of the parameters of `hit`.
+    pub inputs: &'static [&'static str],
+    /// The rules of the rate limiter.
+    pub rules: Vec<RuleInfo>,
+}
@@ -0,0 +1,32 @@
+use crate::InputCountError;
+
+/// The state of a single rule after a hit through `DynLimiter`, the same as the rule's field of the hit struct.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RuleState {
+    /// The name of the rule.
+    pub name: &'static str,
+    /// The requests remaining, for a rule with several windows this is the most restrictive window.
+    pub remaining: u32,
+    /// The time in seconds since the epoch when the rule resets.
+    pub reset: u64,
+    /// Whether the rule is public.
+    pub public: bool,
+    /// The key of the bucket hit.
+    pub key: String,
+    /// The limit of the rule.
+    pub limit: u32,
+    /// The interval of the rule in seconds.
+    pub interval: u32,
+    /// The timeout of the rule in seconds.
+    pub timeout: u32,
+}
+
+/// An object-safe rate limiter, implemented by every rate limiter generated by the `rate_limiter!` macro with a synchronous store,
+/// so rate limiters with different inputs can be stored together, e.g. in a `HashMap<String, Box<dyn DynLimiter>>` selected at runtime.
+pub trait DynLimiter {
+    /// Hits the rate limiter with the inputs given in the order of the parameters of `hit`, returning whether the request is limited
+    /// and the state of every rule in the order of the rules.
+    /// Returns an error if the number of inputs isn't the number of inputs of the rate limiter.
+    fn hit_dyn(&self, inputs: &[&str]) -> Result<(bool, Vec<RuleState>), InputCountError>;
+}
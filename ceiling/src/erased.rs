@@ -0,0 +1,188 @@
+use crate::{
+    error::StoreError,
+    store::{DefaultStore, StoreLock, SyncStore},
+};
+
+/// A `StoreLock` with its type erased, returned by `ErasedStore` for the lock of any store.
+#[derive(Debug)]
+pub struct ErasedLock(Box<dyn StoreLock>);
+
+impl StoreLock for ErasedLock {}
+
+impl std::ops::Deref for ErasedLock {
+    type Target = Option<(u32, u64)>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl ErasedLock {
+    /// Erases the type of a lock.
+    pub fn new<L: StoreLock + 'static>(lock: L) -> Self {
+        Self(Box::new(lock))
+    }
+}
+
+/// Wraps a store so its lock is erased, which makes it usable as a `dyn SyncStore<Lock = ErasedLock>`.
+#[derive(Debug)]
+struct Erase<S>(S);
+
+impl<S> SyncStore for Erase<S>
+where
+    S: SyncStore,
+    S::Lock: 'static,
+{
+    type Lock = ErasedLock;
+
+    fn new() -> Self {
+        Self(S::new())
+    }
+
+    fn get(&self, key: &str) -> Self::Lock {
+        ErasedLock::new(self.0.get(key))
+    }
+
+    fn read(&self, key: &str) -> Option<(u32, u64)> {
+        self.0.read(key)
+    }
+
+    fn set(&self, key: &str, value: (u32, u64), reset_updated: bool) {
+        self.0.set(key, value, reset_updated)
+    }
+
+    fn try_get(&self, key: &str) -> Result<Self::Lock, StoreError> {
+        self.0.try_get(key).map(ErasedLock::new)
+    }
+
+    fn try_set(&self, key: &str, value: (u32, u64), reset_updated: bool) -> Result<(), StoreError> {
+        self.0.try_set(key, value, reset_updated)
+    }
+
+    fn compare_and_set(&self, key: &str, expected: Option<(u32, u64)>, new: (u32, u64)) -> bool {
+        self.0.compare_and_set(key, expected, new)
+    }
+
+    fn remove(&self, key: &str) {
+        self.0.remove(key)
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        self.0.keys_with_prefix(prefix)
+    }
+
+    fn clear(&self) {
+        self.0.clear()
+    }
+
+    fn bucket_count(&self) -> usize {
+        self.0.bucket_count()
+    }
+
+    fn evict(&self, count: usize) -> usize {
+        self.0.evict(count)
+    }
+
+    fn prune(&self, now: u64) {
+        self.0.prune(now)
+    }
+
+    fn prune_returning(&self, now: u64) -> Vec<String> {
+        self.0.prune_returning(now)
+    }
+}
+
+/// A synchronous store of any type behind a trait object, used by rate limiters given the `erased` option so a single
+/// rate limiter type can use several stores without being generic over them. Each call is dispatched dynamically and
+/// each lock is boxed. `SyncStore::new` creates a `DefaultStore`.
+#[derive(Debug)]
+pub struct ErasedStore(Box<dyn SyncStore<Lock = ErasedLock>>);
+
+impl ErasedStore {
+    /// Erases the type of a store.
+    pub fn from_store<S>(store: S) -> Self
+    where
+        S: SyncStore + 'static,
+        S::Lock: 'static,
+    {
+        Self(Box::new(Erase(store)))
+    }
+}
+
+impl SyncStore for ErasedStore {
+    type Lock = ErasedLock;
+
+    fn new() -> Self {
+        Self::from_store(DefaultStore::new())
+    }
+
+    fn get(&self, key: &str) -> Self::Lock {
+        self.0.get(key)
+    }
+
+    fn read(&self, key: &str) -> Option<(u32, u64)> {
+        self.0.read(key)
+    }
+
+    fn set(&self, key: &str, value: (u32, u64), reset_updated: bool) {
+        self.0.set(key, value, reset_updated)
+    }
+
+    fn try_get(&self, key: &str) -> Result<Self::Lock, StoreError> {
+        self.0.try_get(key)
+    }
+
+    fn try_set(&self, key: &str, value: (u32, u64), reset_updated: bool) -> Result<(), StoreError> {
+        self.0.try_set(key, value, reset_updated)
+    }
+
+    fn compare_and_set(&self, key: &str, expected: Option<(u32, u64)>, new: (u32, u64)) -> bool {
+        self.0.compare_and_set(key, expected, new)
+    }
+
+    fn remove(&self, key: &str) {
+        self.0.remove(key)
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        self.0.keys_with_prefix(prefix)
+    }
+
+    fn clear(&self) {
+        self.0.clear()
+    }
+
+    fn bucket_count(&self) -> usize {
+        self.0.bucket_count()
+    }
+
+    fn evict(&self, count: usize) -> usize {
+        self.0.evict(count)
+    }
+
+    fn prune(&self, now: u64) {
+        self.0.prune(now)
+    }
+
+    fn prune_returning(&self, now: u64) -> Vec<String> {
+        self.0.prune_returning(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn erased_default_store() {
+        let store = ErasedStore::new();
+        let lock = store.get("key");
+        assert_eq!(*lock, None);
+        store.set("key", (1, 10), true);
+        drop(lock);
+        assert_eq!(*store.get("key"), Some((1, 10)));
+        assert_eq!(store.keys_with_prefix(""), ["key"]);
+        store.prune(12);
+        assert_eq!(store.read("key"), None);
+    }
+}
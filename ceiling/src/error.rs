@@ -0,0 +1,97 @@
+/// A response body describing why a request was rate limited, created by the generated `to_error_body` method.
+/// With the crate feature `serde` enabled this implements `serde::Serialize`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RateLimitError {
+    /// Seconds until the client can retry, taken from the limited rule that resets last.
+    pub retry_after: u64,
+    /// The limit of the rule that resets last, `None` if the rule is private.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub limit: Option<u32>,
+    /// The name of the rule that resets last, `None` if the rule is private.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub rule: Option<&'static str>,
+    /// The reason given with the `reason` clause of the rule that resets last, if it has one, even if the rule is private.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub reason: Option<&'static str>,
+}
+
+/// An error overriding the limits of a rate limiter, returned from the generated `with_rule_limits` and `from_env` methods.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The rule or the window of the rule doesn't exist.
+    UnknownRule { rule: String, window: usize },
+    /// The limits aren't valid for the rule, e.g. a leaky rule with an interval of zero.
+    InvalidLimits {
+        rule: String,
+        window: usize,
+        reason: &'static str,
+    },
+    /// An environment variable isn't a whole number.
+    InvalidEnv { var: String, value: String },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownRule { rule, window } => {
+                write!(f, "rule '{rule}' has no window {window}")
+            },
+            Self::InvalidLimits {
+                rule,
+                window,
+                reason,
+            } => write!(
+                f,
+                "invalid limits for window {window} of rule '{rule}': {reason}"
+            ),
+            Self::InvalidEnv { var, value } => write!(
+                f,
+                "environment variable {var} is '{value}', expected a whole number"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// An error from a store, returned from the fallible `try_` store methods and the generated `try_hit` method.
+/// Wraps the underlying error of the store, e.g. a connection error from a remote store.
+#[derive(Debug)]
+pub struct StoreError(pub Box<dyn std::error::Error + Send + Sync>);
+
+impl StoreError {
+    /// Creates a new store error wrapping the provided error.
+    pub fn new<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> Self {
+        Self(error.into())
+    }
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "store error: {}", self.0)
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
+/// An error hitting a rate limiter with the wrong number of inputs, returned from the generated `hit_dyn` method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputCountError {
+    /// The number of inputs of the rate limiter.
+    pub expected: usize,
+    /// The number of inputs given.
+    pub found: usize,
+}
+
+impl std::fmt::Display for InputCountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected {} inputs, found {}", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for InputCountError {}
@@ -0,0 +1,69 @@
+use tokio::sync::broadcast;
+
+/// The number of events a subscriber can fall behind by before it misses the oldest, see `tokio::sync::broadcast`.
+const CAPACITY: usize = 1024;
+
+/// What a hit did to a bucket, carried by a `BucketEvent`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum BucketOutcome {
+    /// The hit took from a bucket in its current window.
+    Hit,
+    /// The hit started a new window, the bucket was missing or had expired.
+    Reset,
+    /// The hit was limited by the bucket.
+    Trip,
+}
+
+/// A change to the bucket of a rule's window, published by the generated hit methods to the receivers from `subscribe`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BucketEvent {
+    /// The name of the rule.
+    pub rule: &'static str,
+    /// The key of the bucket, including the index of the window for rules with several windows.
+    pub key: String,
+    /// The requests remaining in the bucket after the hit.
+    pub remaining: u32,
+    /// What the hit did to the bucket.
+    pub outcome: BucketOutcome,
+}
+
+/// A receiver of the events of a rate limiter, returned from the generated `subscribe` method.
+pub type EventReceiver = broadcast::Receiver<BucketEvent>;
+
+/// The channel a rate limiter publishes its `BucketEvent`s to, shared between clones.
+/// Nothing is built or sent while there are no receivers.
+#[derive(Clone, Debug)]
+pub struct EventChannel(broadcast::Sender<BucketEvent>);
+
+impl EventChannel {
+    /// Creates a channel without any receivers.
+    pub fn new() -> Self {
+        Self(broadcast::channel(CAPACITY).0)
+    }
+
+    /// Returns a receiver of the events published from now on.
+    pub fn subscribe(&self) -> EventReceiver {
+        self.0.subscribe()
+    }
+
+    /// Publishes an event if there are any receivers.
+    pub fn publish(&self, rule: &'static str, key: &str, remaining: u32, outcome: BucketOutcome) {
+        if self.0.receiver_count() > 0 {
+            let _ = self.0.send(BucketEvent {
+                rule,
+                key: key.to_owned(),
+                remaining,
+                outcome,
+            });
+        }
+    }
+}
+
+impl Default for EventChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,81 @@
+use std::net::{IpAddr, SocketAddr};
+
+/// Extracts the client's IP address from an `X-Forwarded-For` header, for keying rules by client behind reverse proxies.
+/// Each proxy appends the address it received the request from, so with `trusted_proxies` proxies in front of the server
+/// the client is the `trusted_proxies`th address from the right, and anything left of it may have been forged by the client.
+/// With no trusted proxies the leftmost address is used, which is only safe if every proxy in the chain is trusted.
+///
+/// Addresses are separated by commas with any whitespace around them, ports are stripped from IPv4 addresses
+/// and bracketed IPv6 addresses like `[::1]:8080`, and the address is returned in its canonical form,
+/// with IPv4-mapped IPv6 addresses as IPv4, so different spellings of an address share a bucket.
+/// Returns `None` if the chain is shorter than the number of trusted proxies or the address isn't an IP address, e.g. `unknown`.
+pub fn client_ip_from_forwarded(header: &str, trusted_proxies: usize) -> Option<String> {
+    let mut chain = header.split(',').map(str::trim).filter(|a| !a.is_empty());
+    let address = if trusted_proxies == 0 {
+        chain.next()?
+    } else {
+        chain.rev().nth(trusted_proxies - 1)?
+    };
+    parse_address(address).map(|ip| ip.to_canonical().to_string())
+}
+
+fn parse_address(address: &str) -> Option<IpAddr> {
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(socket) = address.parse::<SocketAddr>() {
+        return Some(socket.ip());
+    }
+    // a bracketed IPv6 address without a port
+    address
+        .strip_prefix('[')
+        .and_then(|a| a.strip_suffix(']'))
+        .and_then(|a| a.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_chain() {
+        let chain = "203.0.113.7, 10.0.0.1,10.0.0.2";
+        assert_eq!(client_ip_from_forwarded(chain, 0).unwrap(), "203.0.113.7");
+        assert_eq!(client_ip_from_forwarded(chain, 1).unwrap(), "10.0.0.2");
+        assert_eq!(client_ip_from_forwarded(chain, 3).unwrap(), "203.0.113.7");
+        assert_eq!(client_ip_from_forwarded(chain, 4), None);
+        // a forged address left of the trusted proxies is ignored
+        assert_eq!(
+            client_ip_from_forwarded("1.1.1.1, 203.0.113.7, 10.0.0.1", 2).unwrap(),
+            "203.0.113.7"
+        );
+        assert_eq!(client_ip_from_forwarded("", 0), None);
+        assert_eq!(client_ip_from_forwarded(" , ", 1), None);
+    }
+
+    #[test]
+    fn forwarded_formats() {
+        assert_eq!(
+            client_ip_from_forwarded("203.0.113.7:4711", 1).unwrap(),
+            "203.0.113.7"
+        );
+        assert_eq!(
+            client_ip_from_forwarded("[2001:db8::1]:8080", 1).unwrap(),
+            "2001:db8::1"
+        );
+        assert_eq!(
+            client_ip_from_forwarded("[2001:DB8:0::1]", 1).unwrap(),
+            "2001:db8::1"
+        );
+        assert_eq!(
+            client_ip_from_forwarded("2001:db8::1, 10.0.0.1", 2).unwrap(),
+            "2001:db8::1"
+        );
+        assert_eq!(
+            client_ip_from_forwarded("::ffff:203.0.113.7", 1).unwrap(),
+            "203.0.113.7"
+        );
+        assert_eq!(client_ip_from_forwarded("unknown", 1), None);
+        assert_eq!(client_ip_from_forwarded("_hidden, 10.0.0.1", 2), None);
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    fmt::{self, Display},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::RateLimitError;
+
+/// A rate limiter keyed by a single value that can be used by `KeyedRateLimiter`.
+/// The `rate_limiter!` macro implements this for rate limiters with a synchronous store and exactly one input.
+pub trait KeyedLimiter {
+    /// Hits the rate limiter for the key, returning when the client can retry if the request is limited.
+    #[must_use = "check whether the request is rate limited"]
+    fn check_key(&self, key: &str) -> Result<(), NotUntil>;
+}
+
+/// A rate limiter without inputs that can be used by `DirectRateLimiter`.
+/// The `rate_limiter!` macro implements this for rate limiters with a synchronous store and no inputs.
+pub trait DirectLimiter {
+    /// Hits the rate limiter, returning when the client can retry if the request is limited.
+    #[must_use = "check whether the request is rate limited"]
+    fn check(&self) -> Result<(), NotUntil>;
+}
+
+/// The error returned when a request is limited, in place of `governor::NotUntil`.
+/// The earliest possible time is when the limited rule that resets last resets, the same rule described by `to_error_body`,
+/// so it's rounded to whole seconds like the rate limiter's `Retry-After` and only guaranteed to be after the reset with `rounding up`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NotUntil {
+    earliest: u64,
+    rule: Option<&'static str>,
+}
+
+impl NotUntil {
+    /// Creates the error for a request limited at `now` in seconds since the epoch, from the error body of the hit.
+    pub fn new(now: u64, error: Option<RateLimitError>) -> Self {
+        Self {
+            earliest: now.saturating_add(error.as_ref().map_or(0, |e| e.retry_after)),
+            rule: error.and_then(|e| e.rule),
+        }
+    }
+
+    /// The earliest time a request can be made without being limited.
+    pub fn earliest_possible(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.earliest)
+    }
+
+    /// The time to wait from the time given until a request can be made, zero if that's already past.
+    pub fn wait_time_from(&self, from: SystemTime) -> Duration {
+        self.earliest_possible()
+            .duration_since(from)
+            .unwrap_or_default()
+    }
+
+    /// The name of the limited rule, `None` if the rule is private.
+    pub fn rule(&self) -> Option<&'static str> {
+        self.rule
+    }
+}
+
+impl Display for NotUntil {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate-limited until {}", self.earliest)
+    }
+}
+
+impl std::error::Error for NotUntil {}
+
+/// Wraps a rate limiter keyed by a single value in the interface of `governor`'s keyed rate limiters,
+/// for code written against `governor::RateLimiter::check_key`.
+#[derive(Clone, Debug)]
+pub struct KeyedRateLimiter<L> {
+    limiter: L,
+}
+
+impl<L: KeyedLimiter> KeyedRateLimiter<L> {
+    /// Wraps the rate limiter, clones of generated rate limiters share their buckets.
+    pub fn new(limiter: L) -> Self {
+        Self { limiter }
+    }
+
+    /// Hits the rate limiter for the key, returning `Err` with when the client can retry if the request is limited.
+    #[must_use = "check whether the request is rate limited"]
+    pub fn check_key<K: Display + ?Sized>(&self, key: &K) -> Result<(), NotUntil> {
+        self.limiter.check_key(&key.to_string())
+    }
+
+    /// Returns the wrapped rate limiter, e.g. to read the full state of a hit.
+    pub fn into_inner(self) -> L {
+        self.limiter
+    }
+}
+
+/// Wraps a rate limiter without inputs in the interface of `governor`'s direct rate limiters,
+/// for code written against `governor::RateLimiter::check`.
+#[derive(Clone, Debug)]
+pub struct DirectRateLimiter<L> {
+    limiter: L,
+}
+
+impl<L: DirectLimiter> DirectRateLimiter<L> {
+    /// Wraps the rate limiter, clones of generated rate limiters share their buckets.
+    pub fn new(limiter: L) -> Self {
+        Self { limiter }
+    }
+
+    /// Hits the rate limiter, returning `Err` with when the client can retry if the request is limited.
+    #[must_use = "check whether the request is rate limited"]
+    pub fn check(&self) -> Result<(), NotUntil> {
+        self.limiter.check()
+    }
+
+    /// Returns the wrapped rate limiter, e.g. to read the full state of a hit.
+    pub fn into_inner(self) -> L {
+        self.limiter
+    }
+}
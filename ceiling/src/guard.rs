@@ -0,0 +1,56 @@
+/// A hit taken by the generated `hit_guarded` method, given back to the rate limiter's buckets when the guard is dropped
+/// unless it's committed, e.g. for handlers that take a request up front but bail out on a validation error before doing any work.
+///
+/// The guard borrows the rate limiter, so it can't outlive it and a handler holding it can't move the rate limiter,
+/// clone the rate limiter first to hold a guard in a task that needs an owned value.
+/// The hit is taken like `hit_atomic`, so a hit that was limited took nothing from the buckets and nothing is refunded.
+#[must_use = "dropping the guard refunds the hit, call `commit` to keep it"]
+pub struct HitGuard<F: FnOnce()> {
+    refund: Option<F>,
+}
+
+impl<F: FnOnce()> HitGuard<F> {
+    /// Creates a guard calling `refund` when it's dropped without being committed.
+    pub fn new(refund: F) -> Self {
+        Self {
+            refund: Some(refund),
+        }
+    }
+
+    /// Keeps the hit, so nothing is refunded when the guard is dropped.
+    pub fn commit(mut self) {
+        self.refund = None;
+    }
+}
+
+impl<F: FnOnce()> Drop for HitGuard<F> {
+    fn drop(&mut self) {
+        if let Some(refund) = self.refund.take() {
+            refund();
+        }
+    }
+}
+
+impl<F: FnOnce()> std::fmt::Debug for HitGuard<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HitGuard")
+            .field("committed", &self.refund.is_none())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn refund_on_drop() {
+        let refunded = Cell::new(0);
+        drop(HitGuard::new(|| refunded.set(refunded.get() + 1)));
+        assert_eq!(refunded.get(), 1);
+        HitGuard::new(|| refunded.set(refunded.get() + 1)).commit();
+        assert_eq!(refunded.get(), 1);
+    }
+}
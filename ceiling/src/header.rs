@@ -0,0 +1,52 @@
+use std::{collections::HashMap, hash::BuildHasher};
+
+/// The headers of a request, read by the `header("Name")` parts of rule keys.
+/// Rate limiters with such parts take the headers as an extra parameter of `hit` after the inputs.
+pub trait HeaderLookup {
+    /// Returns the value of the header with the name, matched case-insensitively, or `None` if the request doesn't have it.
+    fn header(&self, name: &str) -> Option<&str>;
+}
+
+impl<S: BuildHasher> HeaderLookup for HashMap<String, String, S> {
+    fn header(&self, name: &str) -> Option<&str> {
+        match self.get(name) {
+            Some(value) => Some(value),
+            None => self
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str()),
+        }
+    }
+}
+
+impl HeaderLookup for [(&str, &str)] {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| *v)
+    }
+}
+
+/// Values that aren't visible ASCII are treated as missing.
+#[cfg(feature = "http")]
+impl HeaderLookup for http::HeaderMap {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.get(name)?.to_str().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_insensitive() {
+        let map = HashMap::from([(String::from("X-Api-Key"), String::from("abc"))]);
+        assert_eq!(map.header("X-Api-Key"), Some("abc"));
+        assert_eq!(map.header("x-api-key"), Some("abc"));
+        assert_eq!(map.header("X-Other"), None);
+        let slice: &[(&str, &str)] = &[("x-api-key", "abc")];
+        assert_eq!(slice.header("X-API-KEY"), Some("abc"));
+        assert_eq!(slice.header("X-Other"), None);
+    }
+}
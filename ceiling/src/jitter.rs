@@ -0,0 +1,20 @@
+/// Computes a deterministic jitter in the range `0..range` for a bucket key.
+/// The key is hashed with 64 bit FNV-1a so the jitter is stable across processes and builds,
+/// meaning a client's reset stays the same for the entirety of a window.
+pub fn jitter(key: &str, range: u64) -> u64 {
+    if range == 0 {
+        return 0;
+    }
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in key.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash % range
+}
+
+/// Computes a deterministic offset in the range `-spread..=spread` for a bucket key, moving its reset earlier or later
+/// by up to the spread, so the resets of different keys are spread out around the time they'd otherwise be at.
+pub fn jitter_offset(key: &str, spread: u64) -> i64 {
+    jitter(key, spread.saturating_mul(2).saturating_add(1)) as i64 - spread as i64
+}
@@ -1,28 +1,453 @@
 //! Ceiling is a simple, lightweight, and highly configurable library for handling and creating rate limiting rules.
 //!
 //! The main entrypoint to the library is the `rate_limiter!` macro found below.
+mod bucket;
+mod callback;
+mod chain;
+#[cfg(feature = "clock")]
+mod clock;
+mod config;
+mod dynamic;
+mod erased;
+mod error;
+#[cfg(feature = "events")]
+mod event;
+mod forwarded;
+#[cfg(feature = "governor-compat")]
+pub mod governor;
+mod guard;
+mod header;
+mod jitter;
+#[cfg(feature = "otel")]
+pub mod otel;
+mod pruner;
+mod route;
+mod rule;
 mod store;
+#[cfg(feature = "tokio")]
+mod tokio_store;
+#[cfg(feature = "tonic")]
+pub mod tonic;
 
-pub use ceiling_macros::{group, rate_limiter};
+pub use bucket::{apply_hit, escape_key, parse_cost, unescape_key};
+pub use callback::{CapacityResolver, Decision, DecisionSink, RuleCallback};
+pub use ceiling_macros::{group, group_fn, rate_limiter};
+pub use chain::{ChainedHit, HitSummary};
+#[cfg(feature = "clock")]
+pub use clock::{Clock, CoarseClock, SystemClock};
+pub use config::{LimiterConfig, RuleInfo};
+pub use dynamic::{DynLimiter, RuleState};
+pub use erased::{ErasedLock, ErasedStore};
+pub use error::{ConfigError, InputCountError, RateLimitError, StoreError};
+#[cfg(feature = "events")]
+pub use event::{BucketEvent, BucketOutcome, EventChannel, EventReceiver};
+pub use forwarded::client_ip_from_forwarded;
+pub use guard::HitGuard;
+pub use header::HeaderLookup;
+pub use jitter::{jitter, jitter_offset};
+pub use pruner::Pruner;
+pub use route::{normalize_route, normalize_route_strip_id};
+pub use rule::{DurationOverflow, Rule, Seconds, Window};
 #[cfg(feature = "async")]
 pub use store::AsyncStore;
-pub use store::{DefaultStore, StoreLock, SyncStore};
+pub use store::{DefaultStore, PruneStats, StoreLock, SyncStore};
+#[cfg(feature = "tokio")]
+pub use tokio_store::TokioStore;
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
     pub mod ceiling {
-        pub use crate::store::{DefaultStore, SyncStore};
+        #[cfg(feature = "clock")]
+        pub use crate::clock::{Clock, CoarseClock};
+        #[cfg(feature = "events")]
+        pub use crate::event::{BucketEvent, BucketOutcome, EventChannel, EventReceiver};
+        #[cfg(feature = "governor-compat")]
+        pub use crate::governor;
+        #[cfg(feature = "otel")]
+        pub use crate::otel;
+        #[cfg(feature = "tonic")]
+        pub use crate::tonic;
+        pub use crate::{
+            bucket::{apply_hit, escape_key, parse_cost, unescape_key},
+            callback::{CapacityResolver, Decision, DecisionSink, RuleCallback},
+            chain::HitSummary,
+            config::{LimiterConfig, RuleInfo},
+            dynamic::{DynLimiter, RuleState},
+            erased::ErasedStore,
+            error::{ConfigError, InputCountError, RateLimitError, StoreError},
+            guard::HitGuard,
+            header::HeaderLookup,
+            jitter::jitter_offset,
+            pruner::Pruner,
+            rule::{Rule, Window},
+            store::{DefaultStore, SyncStore},
+        };
+        #[cfg(feature = "tokio")]
+        pub use crate::{store::AsyncStore, TokioStore};
     }
 
     ceiling_macros::rate_limiter! {
         ip, route, method in {
             main = pub 2 requests every 2 seconds for { ip + route + method } timeout 3 seconds;
-            max = 3 requests every 2 seconds for { ip + route };
+            max = 3 requests every 2 seconds for { ip + route } tags { tier = "free", note = "a \"b\"" };
         } as RateLimiter
     }
 
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = 2 requests every 2 seconds for { ip };
+        } as pub(crate) GenericRateLimiter<S>
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = 1 requests every 100 seconds for { ip } jitter 10%;
+        } as JitterRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = 10 requests every 2 seconds for { ip } cost 4;
+        } as CostRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            thousand = 1k requests every 1 minute for { ip };
+            million = 2m requests every 1 day for { ip };
+        } as SuffixRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip, session in {
+            main = pub 2 requests every 2 seconds for { ip + session } hide key;
+        } as HiddenKeyRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = pub 2 requests every 2 seconds for { ip };
+        } as HeadersRateLimiter headers { remaining = "X-Quota-Left", reset = "X-Quota-Reset" }
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = 4 requests every 100 seconds for { ip } leaky;
+        } as LeakyRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = pub [2 requests every 100 seconds, 3 requests every 200 seconds timeout 300 seconds] for { ip };
+        } as WindowsRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = pub 2 requests every 1 seconds for { ip };
+        } as RoundingRateLimiter rounding up
+    }
+
+    ceiling_macros::rate_limiter! {
+        peer, method in {
+            main = pub 2 requests every 100 seconds for { peer + method };
+        } as GrpcRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = pub 2 requests every 1 day for { ip } aligned;
+        } as AlignedRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = pub 3 requests every 2 seconds for { ip } aligned;
+        } as AlignedSecondsRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = 2 requests every 100 seconds for { ip };
+            visits = count every 1 hour for { ip } cost 2;
+        } as CountingRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        cf_ip, xff, peer, route in {
+            main = pub 2 requests every 100 seconds for { coalesce(cf_ip, xff, peer) + route };
+        } as CoalesceRateLimiter
+    }
+
+    #[cfg(feature = "tokio")]
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = pub [2 requests every 100 seconds, 3 requests every 200 seconds] for { ip };
+        } as TokioRateLimiter async in ceiling::TokioStore
+    }
+
+    // records the calls made to it, the buckets are kept in a `TokioStore`
+    #[cfg(feature = "tokio")]
+    #[derive(Debug)]
+    struct RecordingStore {
+        inner: TokioStore,
+        calls: std::sync::Mutex<Vec<&'static str>>,
+    }
+
+    #[cfg(feature = "tokio")]
+    #[async_trait::async_trait]
+    impl AsyncStore for RecordingStore {
+        type Lock = <TokioStore as AsyncStore>::Lock;
+
+        fn new() -> Self {
+            Self {
+                inner: TokioStore::new(),
+                calls: Default::default(),
+            }
+        }
+
+        async fn get(&self, key: &str) -> Self::Lock {
+            self.calls.lock().unwrap().push("get");
+            self.inner.get(key).await
+        }
+
+        async fn set(&self, key: &str, value: (u32, u64), reset_updated: bool) {
+            self.calls.lock().unwrap().push("set");
+            self.inner.set(key, value, reset_updated).await;
+        }
+
+        async fn get_many(&self, keys: &[&str]) -> Vec<Self::Lock> {
+            self.calls.lock().unwrap().push("get_many");
+            let mut locks = vec![];
+            for key in keys {
+                locks.push(self.inner.get(key).await);
+            }
+            locks
+        }
+
+        async fn set_many(&self, values: &[(&str, (u32, u64), bool)]) {
+            self.calls.lock().unwrap().push("set_many");
+            for (key, value, reset_updated) in values {
+                self.inner.set(key, *value, *reset_updated).await;
+            }
+        }
+
+        async fn remove(&self, key: &str) {
+            self.inner.remove(key).await;
+        }
+
+        async fn prune(&self, now: u64) {
+            self.inner.prune(now).await;
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = pub [2 requests every 100 seconds, 3 requests every 200 seconds] for { ip };
+        } as BatchedRateLimiter async in RecordingStore
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = 2 requests every 10 seconds for { ip } timeout 3x;
+            burst = [2 requests every 5 seconds timeout 2 intervals, 5 requests every 1 minute] for { ip };
+        } as RelativeTimeoutRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            fixed = 2 requests every 100 seconds for { ip } timeout 50 seconds;
+            extended = 2 requests every 100 seconds for { ip } timeout 50 seconds extend on abuse;
+        } as AbuseRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip, route in include!("src/test_rules.rl") as IncludedRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = 2 requests every 100 seconds for { ip };
+            total = 4 requests every 100 seconds for global;
+        } as GlobalRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        key, route in {
+            routes = 3 requests every 100 seconds for { key } distinct { route };
+        } as DistinctRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        user, query in {
+            main = pub 2 requests every 100 seconds for { user + query };
+        } as SeparatorRateLimiter separator "::"
+    }
+
+    ceiling_macros::rate_limiter! {
+        in {
+            total = pub 3 requests every 100 seconds for global;
+            daily = 10 requests every 1 day for global aligned;
+        } as ServiceRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            burst = pub 2 requests every 10 seconds for { ip } reason "burst_limit_exceeded";
+            quota = 3 requests every 100 seconds for { ip } reason "quota_exceeded";
+        } as ReasonRateLimiter headers { reason = "X-Limit-Reason" }
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            trial = pub 2 requests every 100 seconds for { ip } grace 2;
+        } as GraceRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            trial = 4 requests every 100 seconds for { ip } cost 2 grace 1;
+        } as GraceCostRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = 2 requests every 100 seconds for { ip };
+        } as LocalRateLimiter single_threaded
+    }
+
+    struct Request {
+        ip: String,
+        path: &'static str,
+    }
+
+    ceiling_macros::rate_limiter! {
+        req.ip, req.path in {
+            main = 1 requests every 100 seconds for { ip + path };
+        } as ContextRateLimiter context Request
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            keyed = pub 2 requests every 100 seconds for { header("X-Api-Key") };
+            tenant = 3 requests every 100 seconds for { ip + header("X-Tenant", "none") };
+        } as HeaderRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip, route in {
+            main = 2 requests every 100 seconds for { ip + route };
+            proxied = 2 requests every 100 seconds for { coalesce(ip, route) };
+        } as EmptyAllowRateLimiter on_empty allow
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip, route in {
+            main = 2 requests every 100 seconds for { ip + route };
+            proxied = 2 requests every 100 seconds for { coalesce(ip, route) };
+        } as EmptyDenyRateLimiter on_empty deny
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip, route in {
+            main = 2 requests every 100 seconds for { ip + route };
+            proxied = 2 requests every 100 seconds for { coalesce(ip, route) };
+        } as EmptyPlaceholderRateLimiter on_empty "-"
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            blocked = pub 0 requests every 60 seconds for { ip };
+        } as BlockedRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = pub 1 requests every 100 seconds for { ip } timeout 1000 seconds forgive after 10 seconds;
+        } as ForgivingRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip, page_size in {
+            pages = pub 100 requests every 100 seconds for { ip } cost { page_size };
+            rows = count every 100 seconds for { ip } cost { page_size };
+        } as MeteredRateLimiter
+    }
+
+    /// A store that is always unreachable through the fallible methods.
+    #[derive(Debug)]
+    pub struct FailingStore;
+
+    #[derive(Debug)]
+    pub struct FailingLock(Option<(u32, u64)>);
+
+    impl StoreLock for FailingLock {}
+
+    impl std::ops::Deref for FailingLock {
+        type Target = Option<(u32, u64)>;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    impl SyncStore for FailingStore {
+        type Lock = FailingLock;
+
+        fn new() -> Self {
+            Self
+        }
+
+        fn get(&self, _key: &str) -> Self::Lock {
+            FailingLock(None)
+        }
+
+        fn set(&self, _key: &str, _value: (u32, u64), _reset_updated: bool) {}
+
+        fn try_get(&self, _key: &str) -> Result<Self::Lock, StoreError> {
+            Err(StoreError::new("unreachable"))
+        }
+
+        fn remove(&self, _key: &str) {}
+
+        fn prune(&self, _now: u64) {}
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = pub 2 requests every 100 seconds for { ip };
+        } as FailOpenRateLimiter in FailingStore on_error allow
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = pub 2 requests every 100 seconds for { ip };
+        } as FailClosedRateLimiter in FailingStore on_error deny
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            main = pub 2 requests every 100 seconds for { ip };
+        } as ErasedRateLimiter erased
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            limits = pub 2 requests every 100 seconds for { ip };
+            enabled = 3 requests every 100 seconds for { ip };
+        } as ConfigNamedRateLimiter
+    }
+
+    ceiling_macros::rate_limiter! {
+        ip in {
+            reset = 2 requests every 100 seconds for { ip } leaky;
+            rule = [2 requests every 100 seconds, 3 requests every 200 seconds] for { ip };
+        } as LocalNamedRateLimiter
+    }
+
     #[test]
     fn it_works() {
         let limiter = RateLimiter::new();
@@ -37,7 +462,7 @@ mod tests {
         assert!(!hit_2.0);
         assert_eq!(hit_2.1.main.0, 1);
         assert_eq!(hit_2.1.main.1, now + 2);
-        limiter.hit("1.1.1.1", "/help", "GET");
+        let _ = limiter.hit("1.1.1.1", "/help", "GET");
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
@@ -47,4 +472,1595 @@ mod tests {
         assert_eq!(hit_3.1.main.0, 0);
         assert_eq!(hit_3.1.main.1, now + 3);
     }
+
+    #[test]
+    fn hit_eq() {
+        let limiter = RateLimiter::new();
+        let (_, hit) = limiter.hit("1.1.1.1", "/eq", "GET");
+        assert_eq!(
+            hit,
+            RateLimiterHit {
+                main: (1, hit.main.1, true, "1.1.1.1+/eq+GET".to_string(), 2, 2, 3),
+                max: (2, hit.max.1, false, "1.1.1.1+/eq".to_string(), 3, 2, 2),
+                now: hit.now,
+            }
+        );
+    }
+
+    #[test]
+    fn generic_store() {
+        let limiter = GenericRateLimiter::<DefaultStore>::new();
+        assert_eq!(limiter.hit("1.1.1.1").1.main.0, 1);
+        // clones share the underlying stores
+        assert_eq!(limiter.clone().hit("1.1.1.1").1.main.0, 0);
+        // forks don't
+        assert_eq!(limiter.fork().hit("1.1.1.1").1.main.0, 1);
+    }
+
+    #[test]
+    fn jittered_reset() {
+        let limiter = JitterRateLimiter::new();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let (_, hit) = limiter.hit("1.1.1.1");
+        let reset = (now + 100).saturating_add_signed(jitter::jitter_offset("1.1.1.1", 10));
+        assert!((reset..=reset + 1).contains(&hit.main.1));
+        // the resets of different keys are spread on both sides of the interval
+        let offsets = (0..100).map(|i| jitter::jitter_offset(&format!("10.0.0.{i}"), 10));
+        assert!(offsets.clone().all(|offset| (-10..=10).contains(&offset)));
+        assert!(
+            offsets.clone().any(|offset| offset < 0) && offsets.clone().any(|offset| offset > 0)
+        );
+        // the reset is stable for the key while the bucket is alive
+        assert_eq!(limiter.hit("1.1.1.1").1.main.1, hit.main.1);
+    }
+
+    #[test]
+    fn get_rule() {
+        let (_, hit) = RateLimiter::new().hit("1.1.1.1", "/get", "GET");
+        assert_eq!(hit.get("max"), Some(&hit.max));
+        assert_eq!(hit.get("unknown"), None);
+    }
+
+    #[test]
+    fn config() {
+        let config = RateLimiter::new().with_rule_public("max", true).config();
+        assert_eq!(config.inputs, ["ip", "route", "method"]);
+        assert_eq!(config.rules.len(), 2);
+        let max = &config.rules[1];
+        assert_eq!(max.name, "max");
+        assert_eq!(*max.windows, [Rule::new(3, 2, 2)]);
+        assert_eq!(max.key, [["ip"], ["route"]]);
+        assert_eq!(max.tags, [("tier", "free"), ("note", "a \"b\"")]);
+        assert!(max.public);
+        assert!(!RateLimiter::RULES[1].public);
+        assert_eq!(config.rules[0], RateLimiter::RULES[0]);
+    }
+
+    #[test]
+    fn rule_limits() {
+        let limiter = RateLimiter::new()
+            .with_rule_limits("main", 0, Rule::new(4, 10, 20))
+            .unwrap();
+        let (_, hit) = limiter.hit("1.1.1.1", "/limits", "GET");
+        assert_eq!(
+            (hit.main.0, hit.main.4, hit.main.5, hit.main.6),
+            (3, 4, 10, 20)
+        );
+        assert_eq!(
+            *limiter.fork().config().rules[0].windows,
+            [Rule::new(4, 10, 20)]
+        );
+        assert_eq!(
+            RateLimiter::new()
+                .with_rule_limits("main", 1, Rule::new(4, 10, 20))
+                .unwrap_err(),
+            ConfigError::UnknownRule {
+                rule: "main".into(),
+                window: 1
+            }
+        );
+        let limiter = WindowsRateLimiter::new()
+            .with_rule_limits("main", 1, Rule::new(1, 10, 10))
+            .unwrap();
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(limited);
+        assert_eq!(hit.main_windows[1].rule, Rule::new(1, 10, 10));
+        assert!(matches!(
+            LeakyRateLimiter::new().with_rule_limits("main", 0, Rule::new(4, 0, 0)),
+            Err(ConfigError::InvalidLimits { .. })
+        ));
+        // fixed window and counting rules need an interval too
+        assert!(matches!(
+            RateLimiter::new().with_rule_limits("main", 0, Rule::new(4, 0, 0)),
+            Err(ConfigError::InvalidLimits { .. })
+        ));
+        assert!(matches!(
+            CountingRateLimiter::new().with_rule_limits("visits", 0, Rule::new(u32::MAX, 0, 0)),
+            Err(ConfigError::InvalidLimits { .. })
+        ));
+    }
+
+    #[cfg(feature = "env")]
+    #[test]
+    fn from_env() {
+        std::env::set_var("CEILING_ENV_MAX_LIMIT", "5");
+        std::env::set_var("CEILING_ENV_MAX_TIMEOUT", " 30 ");
+        let limiter = RateLimiter::from_env("CEILING_ENV").unwrap();
+        let rules = limiter.config().rules;
+        assert_eq!(*rules[0].windows, [Rule::new(2, 2, 3)]);
+        assert_eq!(*rules[1].windows, [Rule::new(5, 2, 30)]);
+        std::env::set_var("CEILING_ENV_MAIN_1_INTERVAL", "60");
+        let limiter = WindowsRateLimiter::from_env("CEILING_ENV").unwrap();
+        assert_eq!(
+            *limiter.config().rules[0].windows,
+            [Rule::new(2, 100, 100), Rule::new(3, 60, 300)]
+        );
+        std::env::set_var("CEILING_ENV_MAIN_1_INTERVAL", "1m");
+        assert_eq!(
+            WindowsRateLimiter::from_env("CEILING_ENV").unwrap_err(),
+            ConfigError::InvalidEnv {
+                var: "CEILING_ENV_MAIN_1_INTERVAL".into(),
+                value: "1m".into()
+            }
+        );
+    }
+
+    #[test]
+    fn hit_fast() {
+        let limiter = RateLimiter::new();
+        assert_eq!(limiter.hit_fast("1.1.1.1", "/fast", "GET"), (false, None));
+        assert_eq!(
+            limiter.hit_fast("1.1.1.1", "/fast", "GET"),
+            (true, Some("main"))
+        );
+        // the rules after the one that tripped aren't hit
+        assert_eq!(limiter.max_remaining("1.1.1.1", "/fast"), 2);
+    }
+
+    #[test]
+    fn error_body() {
+        let limiter = RateLimiter::new();
+        let (_, hit) = limiter.hit("1.1.1.1", "/error", "GET");
+        assert_eq!(hit.to_error_body(), None);
+        let _ = limiter.hit("1.1.1.1", "/error", "GET");
+        let (_, hit) = limiter.hit("1.1.1.1", "/error", "GET");
+        let error = hit.to_error_body().unwrap();
+        assert_eq!(error.rule, Some("main"));
+        assert_eq!(error.limit, Some(2));
+        assert!(error.retry_after <= 3);
+        assert_eq!(error.reason, None);
+    }
+
+    #[test]
+    fn reason() {
+        let limiter = ReasonRateLimiter::new();
+        let (_, hit) = limiter.hit("1.1.1.1");
+        assert_eq!(hit.reason(), None);
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(limited);
+        assert_eq!(hit.reason(), Some("burst_limit_exceeded"));
+        assert!(hit
+            .to_headers()
+            .contains(&("X-Limit-Reason", "burst_limit_exceeded".into())));
+        // the private quota resets last once both rules are limited, its reason is still given
+        let (_, hit) = limiter.hit("1.1.1.1");
+        let error = hit.to_error_body().unwrap();
+        assert_eq!((error.rule, error.reason), (None, Some("quota_exceeded")));
+        assert_eq!(
+            ReasonRateLimiter::RULES[0].reason,
+            Some("burst_limit_exceeded")
+        );
+    }
+
+    #[test]
+    fn grace() {
+        let limiter = GraceRateLimiter::new();
+        // the first two hits on the new bucket don't take from the limit
+        for _ in 0..2 {
+            let (limited, hit) = limiter.hit("1.1.1.1");
+            assert!(!limited);
+            assert_eq!(hit.trial.0, 2);
+        }
+        assert_eq!(limiter.trial_remaining("1.1.1.1"), 2);
+        assert!(!limiter.hit("1.1.1.1").0);
+        assert!(limiter.hit("1.1.1.1").0);
+        // the grace is given again once the bucket is pruned
+        limiter.trial.prune(u64::MAX);
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(!limited);
+        assert_eq!(hit.trial.0, 2);
+        assert_eq!(GraceRateLimiter::RULES[0].grace, 2);
+        assert!(GraceRateLimiter::new()
+            .with_rule_limits("trial", 0, ceiling::Rule::new(0, 100, 100))
+            .is_err());
+        // the grace is counted in hits, each costing the rule's cost
+        let limiter = GraceCostRateLimiter::new();
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(!limited);
+        assert_eq!(hit.trial.0, 4);
+        assert!(!limiter.hit("1.1.1.1").0);
+        assert!(limiter.hit("1.1.1.1").0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_rules() {
+        let (_, hit) = RateLimiter::new().hit("1.1.1.1", "/serialize", "GET");
+        let (_, hit_public) =
+            RateLimiter::new()
+                .with_public(true)
+                .hit("1.1.1.1", "/serialize", "GET");
+        let json = |value: serde_json::Value| value.as_object().unwrap().len();
+        assert_eq!(json(serde_json::to_value(&hit_public).unwrap()), 2);
+        let only_max =
+            serde_json::to_value(hit_public.serialize_rules(&["max", "unknown"])).unwrap();
+        assert_eq!(json(only_max.clone()), 1);
+        assert_eq!(
+            only_max.as_object().unwrap().values().next().unwrap()["limit"],
+            3
+        );
+        // private rules aren't serialized even if they're named
+        assert_eq!(
+            json(serde_json::to_value(hit.serialize_rules(&["max"])).unwrap()),
+            0
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_schema() {
+        let (_, hit) = RateLimiter::new()
+            .with_public(true)
+            .hit("1.1.1.1", "/schema", "GET");
+        let json = serde_json::to_string(&hit).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let rules = value.as_object().unwrap();
+        assert_eq!(rules.len(), 2);
+        for (rule, limit) in [("main", 2), ("max", 3)] {
+            let rule = rules[rule].as_object().unwrap();
+            assert_eq!(rule["limit"], limit);
+            for field in ["interval", "timeout", "remaining", "reset", "reset_after"] {
+                assert!(rule[field].is_u64(), "{field}");
+            }
+            assert!(rule["spacing"].is_f64());
+            assert!(rule["key"].is_string());
+            assert_eq!(rule["public"], true);
+            assert_eq!(rule.len(), 9);
+        }
+    }
+
+    #[test]
+    fn zero_limit() {
+        let limiter = BlockedRateLimiter::new();
+        // every hit is limited, the first one included, and the reset doesn't move while the bucket lasts
+        for _ in 0..3 {
+            let (limited, hit) = limiter.hit("1.1.1.1");
+            assert!(limited);
+            let map = hit.to_flat_map();
+            assert_eq!(
+                [
+                    &map["blocked.remaining"],
+                    &map["blocked.used"],
+                    &map["blocked.spacing"]
+                ],
+                ["0", "0", "60"]
+            );
+            assert_eq!(hit.to_error_body().unwrap().retry_after, 60);
+        }
+        assert!(limiter.hit_atomic("1.1.1.1").0);
+        assert_eq!(limiter.blocked_remaining("1.1.1.1"), 0);
+    }
+
+    #[test]
+    fn forgive() {
+        let limiter = ForgivingRateLimiter::new();
+        // the hit emptying the bucket is the last limited hit, and a client that keeps retrying isn't forgiven
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(limited);
+        assert_eq!(
+            limiter.main.read("1.1.1.1#last"),
+            Some((1000, hit.now + 1000))
+        );
+        let (_, retry) = limiter.hit("1.1.1.1");
+        assert_eq!(retry.main.1, hit.main.1);
+        // a client quiet for 200 seconds has the rest of its timeout cut to a twentieth
+        let start = hit.now;
+        limiter.main.set("2.2.2.2", (0, start + 600), true);
+        limiter.main.set("2.2.2.2#last", (800, start + 600), true);
+        let (limited, hit) = limiter.hit("2.2.2.2");
+        assert!(limited);
+        let quiet = hit.now - (start - 200);
+        let reset = hit.now + (start + 600 - hit.now) * 10 / quiet;
+        assert_eq!(hit.main.1, reset);
+        assert_eq!(limiter.main.read("2.2.2.2"), Some((0, reset)));
+        assert_eq!(
+            limiter.main.read("2.2.2.2#last"),
+            Some(((reset - hit.now) as u32, reset))
+        );
+        assert_eq!(limiter.clear_buckets(Some("2.2.2.2")), 2);
+        // a key ending in `#last` has its own buckets instead of the record of another key
+        let (_, hit) = limiter.hit("3.3.3.3");
+        let record = limiter.main.read("3.3.3.3#last");
+        assert_eq!(record, Some((1000, hit.now + 1000)));
+        let (limited, hit) = limiter.hit("3.3.3.3#last");
+        assert!(limited);
+        assert_eq!(hit.main.3, "3.3.3.3\\#last");
+        assert_eq!(limiter.main.read("3.3.3.3#last"), record);
+        assert_eq!(limiter.clear_buckets(Some("3.3.3.3#last")), 2);
+        assert_eq!(limiter.clear_buckets(Some("3.3.3.3")), 2);
+        assert_eq!(ForgivingRateLimiter::RULES[0].forgive, 10);
+    }
+
+    #[test]
+    fn forgive_clock_skew() {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let limiter = ForgivingRateLimiter::new().with_clock_skew(30);
+        // a bucket kept past its reset by the tolerance is still limited, and is recorded as having no time until its reset
+        limiter.main.set("1.1.1.1", (0, now - 10), true);
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(limited);
+        assert_eq!(hit.main.1, now + 20);
+        assert_eq!(limiter.main.read("1.1.1.1#last"), Some((0, now - 10)));
+    }
+
+    #[test]
+    fn single_threaded() {
+        let limiter = LocalRateLimiter::new();
+        let _ = limiter.clone().hit("1.1.1.1");
+        assert_eq!(limiter.main_remaining("1.1.1.1"), 1);
+        assert_eq!(std::rc::Rc::strong_count(&limiter.main), 1);
+        assert_eq!(limiter.fork().main_remaining("1.1.1.1"), 2);
+    }
+
+    #[test]
+    fn hit_from() {
+        let limiter = ContextRateLimiter::new();
+        let req = Request {
+            ip: String::from("1.1.1.1"),
+            path: "/example",
+        };
+        let (limited, hit) = limiter.hit_from(&req);
+        assert!(limited);
+        assert_eq!(hit.main.3, "1.1.1.1+/example");
+        assert_eq!(
+            ContextRateLimiter::new()
+                .hit("1.1.1.1", "/example")
+                .1
+                .main
+                .3,
+            hit.main.3
+        );
+        assert_eq!(limiter.config().inputs, ["ip", "path"]);
+    }
+
+    #[test]
+    fn header_parts() {
+        let limiter = HeaderRateLimiter::new();
+        let headers =
+            std::collections::HashMap::from([(String::from("x-api-key"), String::from("abc"))]);
+        let (limited, hit) = limiter.hit("1.1.1.1", &headers);
+        assert!(!limited);
+        assert_eq!(hit.keyed.3, "abc");
+        assert_eq!(hit.tenant.3, "1.1.1.1+none");
+        assert!(limiter.hit("1.1.1.1", &headers).0);
+        let headers: &[(&str, &str)] = &[("X-Tenant", "acme")];
+        let (limited, hit) = limiter.hit("1.1.1.1", headers);
+        assert!(limited);
+        assert_eq!((hit.keyed.0, hit.keyed.3.as_str()), (0, "<empty>"));
+        assert_eq!(hit.to_error_body().unwrap().rule, Some("keyed"));
+        assert_eq!(hit.tenant.3, "1.1.1.1+acme");
+        assert_eq!(limiter.hit_fast("1.1.1.1", headers), (true, Some("keyed")));
+        assert_eq!(limiter.tenant_remaining("1.1.1.1", headers), 2);
+        assert_eq!(
+            limiter.config().rules[1].key,
+            [&["ip"][..], &["header(\"X-Tenant\", \"none\")"]]
+        );
+        assert_eq!(
+            limiter.hit("1.1.1.1", headers).1.to_policy_headers()[0].1,
+            "keyed 2 requests per 100 seconds per header(\"X-Api-Key\")"
+        );
+    }
+
+    #[test]
+    fn empty_parts() {
+        let (_, hit) = RateLimiter::new().hit("1.1.1.1", "", "GET");
+        assert_eq!(hit.main.3, "1.1.1.1+<empty>+GET");
+        assert_eq!(hit.max.3, "1.1.1.1+<empty>");
+
+        let limiter = EmptyAllowRateLimiter::new();
+        let (limited, hit) = limiter.hit("1.1.1.1", "");
+        assert!(!limited);
+        assert_eq!(
+            (hit.main.3.as_str(), hit.proxied.3.as_str()),
+            ("1.1.1.1+", "1.1.1.1")
+        );
+        assert_eq!(limiter.keys("", ""), ["+", ""]);
+
+        let limiter = EmptyDenyRateLimiter::new();
+        let (limited, hit) = limiter.hit("1.1.1.1", "");
+        assert!(limited);
+        assert_eq!((hit.main.0, hit.proxied.0), (0, 1));
+        assert!(!limiter.hit("2.2.2.2", "/a").0);
+        assert_eq!(limiter.hit_fast("", ""), (true, Some("main")));
+        assert_eq!(limiter.main_remaining("1.1.1.1", ""), 2);
+
+        let limiter = EmptyPlaceholderRateLimiter::new();
+        assert_eq!(limiter.keys("", ""), ["-+-", "-"]);
+        assert_eq!(limiter.keys("", "/a"), ["-+/a", "/a"]);
+        let _ = limiter.hit("", "/a");
+        assert_eq!(limiter.clear_buckets(Some(""), None), 1);
+        assert_eq!(limiter.clear_buckets(Some("-"), None), 0);
+    }
+
+    #[test]
+    fn decision_sink() {
+        let decisions = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let limiter = MeteredRateLimiter::new().with_decision_sink({
+            let decisions = decisions.clone();
+            move |d| {
+                let record = (d.rule, d.key.to_string(), d.remaining, d.limited, d.now);
+                decisions.lock().unwrap().push(record);
+            }
+        });
+        let (_, hit) = limiter.hit("1.1.1.1", "100");
+        let (_, second) = limiter.clone().hit("1.1.1.1", "1");
+        // `hit_fast` isn't recorded
+        let _ = limiter.hit_fast("1.1.1.1", "1");
+        let key = String::from("1.1.1.1");
+        assert_eq!(
+            *decisions.lock().unwrap(),
+            [
+                ("pages", key.clone(), 0, true, hit.now),
+                ("rows", key.clone(), u32::MAX - 100, false, hit.now),
+                ("pages", key.clone(), 0, true, second.now),
+                ("rows", key, u32::MAX - 101, false, second.now),
+            ]
+        );
+    }
+
+    #[test]
+    fn on_first_hit() {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let limiter = WindowsRateLimiter::new().with_on_first_hit({
+            let seen = seen.clone();
+            move |rule, key| seen.lock().unwrap().push(format!("{rule} {key}"))
+        });
+        let _ = limiter.hit("1.1.1.1");
+        let _ = limiter.clone().hit("1.1.1.1");
+        // an expired bucket that hasn't been pruned resets without the key being new
+        limiter.main.set("2.2.2.2#0", (1, 10), true);
+        let _ = limiter.hit("2.2.2.2");
+        let _ = limiter.fork().hit("1.1.1.1");
+        assert_eq!(*seen.lock().unwrap(), ["main 1.1.1.1", "main 1.1.1.1"]);
+    }
+
+    #[test]
+    fn credit() {
+        let limiter = WindowsRateLimiter::new();
+        let _ = limiter.hit("1.1.1.1");
+        limiter.credit("1.1.1.1", 1);
+        assert_eq!(limiter.main_remaining("1.1.1.1"), 2);
+        // each window is capped at its own limit
+        limiter.credit("1.1.1.1", 5);
+        assert_eq!(limiter.main.read("1.1.1.1#1").unwrap().0, 3);
+        // an expired bucket would reset on the next hit anyway
+        limiter.main.set("2.2.2.2#0", (0, 10), true);
+        limiter.credit("2.2.2.2", 1);
+        assert_eq!(limiter.main.read("2.2.2.2#0"), Some((0, 10)));
+        let limiter = LeakyRateLimiter::new();
+        let _ = limiter.hit("1.1.1.1");
+        let _ = limiter.hit("1.1.1.1");
+        limiter.credit("1.1.1.1", 1);
+        assert_eq!(limiter.main_remaining("1.1.1.1"), 3);
+        limiter.credit("1.1.1.1", 5);
+        assert_eq!(limiter.main_remaining("1.1.1.1"), 4);
+        // the requests over the limit given by a grace aren't taken away
+        let limiter = GraceRateLimiter::new();
+        let _ = limiter.hit("1.1.1.1");
+        limiter.credit("1.1.1.1", 1);
+        assert_eq!(limiter.trial.read("1.1.1.1").unwrap().0, 3);
+    }
+
+    #[test]
+    fn cost_input() {
+        let limiter = MeteredRateLimiter::new();
+        let (_, hit) = limiter.hit("1.1.1.1", 40);
+        assert_eq!(hit.pages.0, 60);
+        let (_, hit) = limiter.hit("1.1.1.1", 50);
+        assert_eq!((hit.pages.0, hit.rows_used()), (10, 90));
+        // a value that isn't a whole number costs 1
+        let (limited, hit) = limiter.hit("1.1.1.1", "all");
+        assert!(!limited);
+        assert_eq!(hit.pages.0, 9);
+        // a cost larger than the requests remaining empties the bucket
+        let (limited, hit) = limiter.hit("1.1.1.1", 20);
+        assert!(limited);
+        assert_eq!(hit.pages.0, 0);
+        assert_eq!(MeteredRateLimiter::RULES[0].cost_input, Some("page_size"));
+    }
+
+    #[test]
+    fn hit_guarded() {
+        let limiter = RateLimiter::new();
+        let (limited, _, guard) = limiter.hit_guarded("1.1.1.1", "/", "GET");
+        assert!(!limited);
+        assert_eq!(limiter.main_remaining("1.1.1.1", "/", "GET"), 1);
+        drop(guard);
+        assert_eq!(limiter.main_remaining("1.1.1.1", "/", "GET"), 2);
+        assert_eq!(limiter.max_remaining("1.1.1.1", "/"), 3);
+        limiter.hit_guarded("1.1.1.1", "/", "GET").2.commit();
+        assert_eq!(limiter.main_remaining("1.1.1.1", "/", "GET"), 1);
+        let _ = limiter.hit("1.1.1.1", "/", "GET");
+        assert_eq!(limiter.max_remaining("1.1.1.1", "/"), 1);
+        // a limited hit takes nothing, even from the rules that didn't trip, so nothing is given back
+        let (limited, _, guard) = limiter.hit_guarded("1.1.1.1", "/", "GET");
+        assert!(limited);
+        assert_eq!(limiter.max_remaining("1.1.1.1", "/"), 1);
+        drop(guard);
+        assert_eq!(limiter.main_remaining("1.1.1.1", "/", "GET"), 0);
+        assert_eq!(limiter.max_remaining("1.1.1.1", "/"), 1);
+
+        // a value already seen by a distinct rule took nothing, a new one is given back and can be seen again
+        let limiter = DistinctRateLimiter::new();
+        let _ = limiter.hit("a", "/1");
+        drop(limiter.hit_guarded("a", "/1").2);
+        assert_eq!(limiter.routes_remaining("a"), 2);
+        drop(limiter.hit_guarded("a", "/2").2);
+        assert_eq!(limiter.routes_remaining("a"), 2);
+        assert_eq!(limiter.hit("a", "/2").1.routes.0, 1);
+
+        let limiter = MeteredRateLimiter::new();
+        let (_, hit, guard) = limiter.hit_guarded("1.1.1.1", 40);
+        assert_eq!(hit.pages.0, 60);
+        drop(guard);
+        assert_eq!(limiter.pages_remaining("1.1.1.1"), 100);
+    }
+
+    #[test]
+    fn rule_cost() {
+        let limiter = CostRateLimiter::new();
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(!limited);
+        assert_eq!(hit.main.0, 6);
+        assert_eq!(limiter.hit("1.1.1.1").1.main.0, 2);
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(limited);
+        assert_eq!(hit.main.0, 0);
+    }
+
+    #[test]
+    fn keys_with_prefix() {
+        let limiter = RateLimiter::new();
+        let _ = limiter.hit("1.1.1.1", "/a", "GET");
+        let _ = limiter.hit("1.1.1.1", "/b", "GET");
+        let _ = limiter.hit("2.2.2.2", "/a", "GET");
+        let mut keys = limiter.keys_with_prefix("max", "1.1.1.1+");
+        keys.sort();
+        assert_eq!(keys, vec!["1.1.1.1+/a", "1.1.1.1+/b"]);
+        assert!(limiter.keys_with_prefix("unknown", "").is_empty());
+    }
+
+    #[test]
+    fn hit_atomic() {
+        let limiter = RateLimiter::new();
+        let _ = limiter.hit("1.1.1.1", "/atomic", "GET");
+        let _ = limiter.hit("1.1.1.1", "/atomic", "GET");
+        // main is now empty, so max shouldn't be touched
+        let (limited, hit) = limiter.hit_atomic("1.1.1.1", "/atomic", "GET");
+        assert!(limited);
+        assert_eq!(hit.max.0, 1);
+        let (limited, hit) = limiter.hit_atomic("1.1.1.1", "/atomic", "POST");
+        assert!(limited);
+        assert_eq!((hit.main.0, hit.max.0), (2, 1));
+    }
+
+    #[test]
+    fn limit_suffixes() {
+        let (_, hit) = SuffixRateLimiter::new().hit("1.1.1.1");
+        assert_eq!(hit.thousand.0, 999);
+        assert_eq!(hit.million.0, 1_999_999);
+    }
+
+    #[test]
+    fn hidden_key() {
+        let (_, hit) = HiddenKeyRateLimiter::new().hit("1.1.1.1", "secret");
+        assert_eq!(hit.main.3, "1.1.1.1+secret");
+        assert!(hit
+            .to_headers()
+            .iter()
+            .all(|(h, _)| *h != "X-RateLimit-Key"));
+    }
+
+    #[test]
+    fn header_names() {
+        let (_, hit) = HeadersRateLimiter::new().hit("1.1.1.1");
+        let headers = hit.to_headers();
+        let names = headers.iter().map(|(h, _)| *h).collect::<Vec<_>>();
+        assert!(names.contains(&"X-Quota-Left"));
+        assert!(names.contains(&"X-Quota-Reset"));
+        assert!(names.contains(&"X-RateLimit-Limit"));
+        assert!(!names.contains(&"X-RateLimit-Remaining"));
+        assert!(headers.contains(&("X-RateLimit-Spacing", "main 1".into())));
+    }
+
+    #[test]
+    fn leaky() {
+        let limiter = LeakyRateLimiter::new();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let (_, hit) = limiter.hit("1.1.1.1");
+        assert_eq!(hit.main.0, 3);
+        // a request leaks back every 25 seconds
+        assert!((now + 25..=now + 26).contains(&hit.main.1));
+        limiter.main.set("2.2.2.2", (0, now + 50), true);
+        // 50 seconds have passed since the last leak, so two requests have leaked back
+        let (limited, hit) = limiter.hit("2.2.2.2");
+        assert!(!limited);
+        assert_eq!(hit.main.0, 1);
+    }
+
+    #[test]
+    fn clock_skew() {
+        let (_, hit) = RateLimiter::new().hit("1.1.1.1", "/skew", "GET");
+        let key = hit.main.3;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let limiter = RateLimiter::new().with_clock_skew(10);
+        // a bucket that reset 5 seconds ago is still within the tolerance
+        limiter.main.set(&key, (0, now - 5), true);
+        let (limited, hit) = limiter.hit("1.1.1.1", "/skew", "GET");
+        assert!(limited);
+        // and reports the reset as the end of the tolerance, when it expires
+        assert_eq!(hit.main.1, now + 5);
+        assert_eq!(
+            hit.to_error_body().unwrap().retry_after,
+            hit.main.1 - hit.now
+        );
+        assert_eq!(limiter.main_remaining("1.1.1.1", "/skew", "GET"), 0);
+        limiter.main.set(&key, (0, now - 15), true);
+        let (limited, hit) = limiter.hit("1.1.1.1", "/skew", "GET");
+        assert!(!limited);
+        assert_eq!(hit.main.0, 1);
+    }
+
+    #[test]
+    fn preload() {
+        let (_, hit) = RateLimiter::new().hit("1.1.1.1", "/preload", "GET");
+        let limiter = RateLimiter::new();
+        let entries = [
+            ("main", hit.main.3.as_str()),
+            ("max", hit.max.3.as_str()),
+            ("unknown", "1.1.1.1"),
+        ];
+        assert_eq!(limiter.preload(entries), 2);
+        assert_eq!(limiter.main.read(&hit.main.3).unwrap().0, 2);
+        // buckets already in the store are left alone
+        let (_, hit) = limiter.hit("1.1.1.1", "/preload", "GET");
+        assert_eq!((hit.main.0, hit.max.0), (1, 2));
+        assert_eq!(limiter.preload(entries), 0);
+        assert_eq!(limiter.main.read(&hit.main.3).unwrap().0, 1);
+        // each window of a rule with several windows has its own bucket
+        let limiter = WindowsRateLimiter::new();
+        assert_eq!(limiter.preload([("main", "1.1.1.1")]), 2);
+        assert_eq!(limiter.main.read("1.1.1.1#1").unwrap().0, 3);
+        let (_, hit) = LeakyRateLimiter::new().hit("1.1.1.1");
+        let limiter = LeakyRateLimiter::new();
+        assert_eq!(limiter.preload([("main", hit.main.3.as_str())]), 1);
+        assert_eq!(limiter.hit("1.1.1.1").1.main.0, 3);
+        // preloading stops once the budget is full, so no bucket is evicted for it
+        let limiter = RateLimiter::new().with_memory_budget(3);
+        let _ = limiter.hit("1.1.1.1", "/", "GET");
+        let entries = [("main", "a"), ("main", "b"), ("max", "c")];
+        assert_eq!(limiter.preload(entries), 1);
+        assert_eq!(limiter.main.read("b"), None);
+        let _ = limiter.hit("1.1.1.1", "/", "GET");
+        assert_eq!(limiter.main_remaining("1.1.1.1", "/", "GET"), 0);
+    }
+
+    #[test]
+    fn capacity_resolver() {
+        let resolved = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let limiter = LeakyRateLimiter::new()
+            .with_capacity_resolver("main", {
+                let resolved = resolved.clone();
+                move |key| {
+                    resolved.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    if key == "premium" {
+                        (10, 2)
+                    } else {
+                        (0, 0)
+                    }
+                }
+            })
+            .unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let (_, hit) = limiter.hit("premium");
+        assert_eq!(hit.main.0, 9);
+        // two requests leak back every 100 seconds
+        assert!((now + 50..=now + 51).contains(&hit.main.1));
+        let (_, hit) = limiter.hit("premium");
+        assert_eq!(hit.main.0, 8);
+        assert_eq!(hit.to_flat_map()["main.limit"], "10");
+        assert_eq!(resolved.load(std::sync::atomic::Ordering::Relaxed), 1);
+        // the capacity is cached by the resolver until an empty bucket would be full again, not in the store
+        let resolver = limiter.__ceiling.capacity_resolvers[0].as_ref().unwrap();
+        assert_eq!(resolver.cached("premium", now + 500), Some((10, 2)));
+        assert_eq!(resolver.cached("premium", now + 502), None);
+        assert_eq!(limiter.keys_with_prefix("main", ""), ["premium"]);
+        // removing the bucket forgets the capacity
+        assert_eq!(limiter.clear_buckets(Some("premium")), 1);
+        assert_eq!(resolver.cached("premium", now), None);
+        let _ = limiter.hit("premium");
+        assert_eq!(resolved.load(std::sync::atomic::Ordering::Relaxed), 2);
+        // a resolved capacity or refill of zero is raised to one
+        let (limited, hit) = limiter.hit("free");
+        assert!(limited);
+        assert_eq!(hit.main.0, 0);
+        assert_eq!(LeakyRateLimiter::new().hit("premium").1.main.0, 3);
+        assert_eq!(
+            LeakyRateLimiter::new()
+                .with_capacity_resolver("unknown", |_| (1, 1))
+                .unwrap_err(),
+            ceiling::ConfigError::UnknownRule {
+                rule: "unknown".to_string(),
+                window: 0
+            }
+        );
+        assert!(RateLimiter::new()
+            .with_capacity_resolver("main", |_| (1, 1))
+            .is_err());
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn response_parts() {
+        let limiter = HeadersRateLimiter::new();
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        let (status, headers) = hit.to_response_parts(limited);
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(headers["x-quota-left"], "main 1");
+        let _ = limiter.hit("1.1.1.1");
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert_eq!(
+            hit.to_response_parts(limited).0,
+            http::StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[test]
+    fn store_errors() {
+        let limiter = GenericRateLimiter::<FailingStore>::new();
+        assert!(limiter.try_hit("1.1.1.1").is_err());
+        // the infallible methods are still used by `hit`
+        assert!(!limiter.hit("1.1.1.1").0);
+        let (limited, hit) = FailOpenRateLimiter::new().try_hit("1.1.1.1").unwrap();
+        assert!(!limited);
+        assert_eq!(hit.main.0, 2);
+        let (limited, hit) = FailClosedRateLimiter::new().try_hit("1.1.1.1").unwrap();
+        assert!(limited);
+        assert_eq!(hit.main.0, 0);
+        // without errors `try_hit` behaves like `hit`
+        let limiter = RateLimiter::new();
+        assert_eq!(
+            limiter.try_hit("1.1.1.1", "/", "GET").unwrap().1.main.0,
+            limiter.hit("1.1.1.1", "/", "GET").1.main.0 + 1
+        );
+    }
+
+    #[test]
+    fn windows() {
+        let limiter = WindowsRateLimiter::new();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(!limited);
+        assert_eq!(hit.main_windows.map(|w| w.remaining), [1, 2]);
+        // the first window has the fewest requests remaining
+        assert_eq!(hit.main.0, 1);
+        assert_eq!(hit.to_headers()[0], ("X-RateLimit-Limit", "main 2".into()));
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(limited);
+        assert_eq!(hit.main_windows.map(|w| w.remaining), [0, 1]);
+        limiter.main.set("2.2.2.2#1", (1, now + 200), true);
+        let _ = limiter.hit("2.2.2.2");
+        let (limited, hit) = limiter.hit("2.2.2.2");
+        assert!(limited);
+        // both windows are limited, the one with the longer timeout is reported
+        assert!((now + 300..=now + 301).contains(&hit.main.1));
+        assert_eq!(hit.to_error_body().unwrap().limit, Some(3));
+        assert_eq!((hit.main.4, hit.main.5, hit.main.6), (3, 200, 300));
+    }
+
+    #[test]
+    fn rule_remaining() {
+        let limiter = RateLimiter::new();
+        assert_eq!(limiter.max_remaining("1.1.1.1", "/"), 3);
+        let _ = limiter.hit("1.1.1.1", "/", "GET");
+        assert_eq!(limiter.max_remaining("1.1.1.1", "/"), 2);
+        assert_eq!(limiter.main_remaining("1.1.1.1", "/", "GET"), 1);
+        let limiter = WindowsRateLimiter::new();
+        let _ = limiter.hit("1.1.1.1");
+        assert_eq!(limiter.main_remaining("1.1.1.1"), 1);
+        let limiter = LeakyRateLimiter::new();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        limiter.main.set("1.1.1.1", (0, now + 50), true);
+        assert_eq!(limiter.main_remaining("1.1.1.1"), 2);
+    }
+
+    mod admin {
+        ceiling_macros::group! {
+            pub const ADMIN_ROUTES {
+                "/admin", "/admin/users";
+            }
+        }
+    }
+
+    ceiling_macros::group! {
+        const HELP_ROUTES {
+            "/help", "/help2";
+            "/one", "/two";
+        }
+    }
+
+    ceiling_macros::group! {
+        help_bucket {
+            "/help", "/help2";
+        }
+    }
+
+    ceiling_macros::group_fn! {
+        bucket(HELP_ROUTES, admin::ADMIN_ROUTES)
+    }
+
+    #[test]
+    fn group_key() {
+        assert_eq!(help_bucket("/help2"), help_bucket("/help"));
+        // the key is derived from the group so every build shares buckets
+        assert_eq!(help_bucket("/help"), "__0-ae7606e39dfa39ed");
+        assert_eq!(help_bucket("/other"), "/other");
+    }
+
+    #[test]
+    fn group_fn() {
+        assert_eq!(bucket("/help2"), "/help");
+        assert_eq!(bucket("/help"), "/help");
+        assert_eq!(bucket("/two"), "/one");
+        assert_eq!(bucket("/admin/users"), "/admin");
+        assert_eq!(bucket("/other"), "/other");
+    }
+
+    #[test]
+    fn reset_after_rounding() {
+        let limiter = RoundingRateLimiter::new();
+        let _ = limiter.hit("1.1.1.1");
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(limited);
+        let retry_after = hit.to_error_body().unwrap().retry_after;
+        assert_eq!(
+            hit.to_headers()[5],
+            ("X-RateLimit-Reset-After", format!("main {retry_after}"))
+        );
+        std::thread::sleep(std::time::Duration::from_secs(retry_after));
+        assert!(!limiter.hit("1.1.1.1").0);
+    }
+
+    #[test]
+    fn headers_at() {
+        let (_, hit) = RateLimiter::new().hit("1.1.1.1", "/at", "GET");
+        let reset_after = |headers: Vec<(&str, String)>| headers[5].1.clone();
+        // the seconds until reset are counted from the time of the hit, not when the headers are made
+        assert_eq!(
+            reset_after(hit.to_headers()),
+            format!("main {}", hit.main.1 - hit.now)
+        );
+        assert_eq!(
+            reset_after(hit.to_headers_at(hit.now + 1)),
+            format!("main {}", hit.main.1 - hit.now - 1)
+        );
+        assert_eq!(reset_after(hit.to_headers_at(u64::MAX)), "main 0");
+    }
+
+    #[test]
+    fn headers_minimal() {
+        let (_, hit) = RateLimiter::new().hit("1.1.1.1", "/minimal", "GET");
+        // the private rule `max` is left out like in the full headers
+        assert_eq!(
+            hit.to_headers_minimal(),
+            [
+                ("X-RateLimit-Limit", String::from("main 2")),
+                ("X-RateLimit-Remaining", String::from("main 1")),
+                ("X-RateLimit-Reset", format!("main {}", hit.main.1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn policy_headers() {
+        let (_, hit) = RateLimiter::new().hit("1.1.1.1", "/policy", "GET");
+        assert_eq!(
+            hit.to_policy_headers(),
+            [(
+                "X-RateLimit-Policy",
+                String::from("main 2 requests per 2 seconds per ip+route+method")
+            )]
+        );
+        let (_, hit) = BlockedRateLimiter::new().hit("1.1.1.1");
+        assert_eq!(
+            hit.to_policy_headers()[0].1,
+            "blocked 0 requests per 60 seconds per ip"
+        );
+    }
+
+    #[cfg(feature = "governor-compat")]
+    #[test]
+    fn governor_compat() {
+        use self::ceiling::governor::{DirectRateLimiter, KeyedRateLimiter};
+
+        let limiter = KeyedRateLimiter::new(HeadersRateLimiter::new());
+        assert!(limiter.check_key("1.1.1.1").is_ok());
+        let now = std::time::SystemTime::now();
+        let not_until = limiter.check_key("1.1.1.1").unwrap_err();
+        assert_eq!(not_until.rule(), Some("main"));
+        assert!(not_until.wait_time_from(now) <= std::time::Duration::from_secs(2));
+        assert!(limiter
+            .check_key(&std::net::Ipv4Addr::new(2, 2, 2, 2))
+            .is_ok());
+        assert_eq!(limiter.into_inner().main_remaining("1.1.1.1"), 0);
+
+        let limiter = DirectRateLimiter::new(ServiceRateLimiter::new());
+        assert!(limiter.check().is_ok());
+        assert!(limiter.check().is_ok());
+        // the limited rule is public, so its name is given
+        assert_eq!(limiter.check().unwrap_err().rule(), Some("total"));
+    }
+
+    #[cfg(feature = "tonic")]
+    #[test]
+    fn tonic_interceptor() {
+        use ::tonic::{service::Interceptor, Code, Request};
+
+        let mut interceptor = tonic::RateLimitInterceptor::new(GrpcRateLimiter::new())
+            .with_method("/test.Service/Method");
+        assert!(interceptor.call(Request::new(())).is_ok());
+        let status = interceptor.call(Request::new(())).unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        let metadata = status.metadata();
+        assert_eq!(metadata.get("x-ratelimit-remaining").unwrap(), "main 0");
+        assert!(metadata.get("retry-after").is_some());
+    }
+
+    #[test]
+    fn aligned() {
+        let limiter = AlignedRateLimiter::new();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let day = 24 * 60 * 60;
+        let end = now - now % day + day - 1;
+        let (_, hit) = limiter.hit("1.1.1.1");
+        // the reset is reported as the next midnight, a hit in the last second of a day would start the next day's window
+        assert!(hit.main.1 == end + 1 || hit.main.1 == end + 1 + day);
+        // a bucket from the previous day has expired by the first second of the next
+        limiter.main.set("2.2.2.2", (0, now - now % day - 1), true);
+        let (limited, hit) = limiter.hit("2.2.2.2");
+        assert!(!limited);
+        assert_eq!(hit.main.0, 1);
+        // reaching the limit keeps the reset at the end of the window
+        limiter.main.set("3.3.3.3", (1, end), true);
+        let (limited, hit) = limiter.hit("3.3.3.3");
+        assert!(limited);
+        assert_eq!(hit.main.1, end + 1);
+    }
+
+    #[test]
+    fn aligned_boundary() {
+        let now = || {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        };
+        // hits until one lands in the last second of a window
+        let (limiter, before) = loop {
+            let limiter = AlignedSecondsRateLimiter::new();
+            let (_, hit) = limiter.hit("1.1.1.1");
+            if hit.now % 2 == 1 {
+                break (limiter, hit);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        };
+        assert_eq!(before.main.1, before.now + 1);
+        assert_eq!(
+            before.to_headers()[4],
+            ("X-RateLimit-Reset", format!("main {}", before.now + 1))
+        );
+        assert_eq!(before.to_headers()[5].1, "main 1");
+        while now() <= before.now {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        // the first hit after the boundary starts the next window, which resets a window later
+        let (_, after) = limiter.hit("1.1.1.1");
+        assert_eq!(after.main.0, 2);
+        assert_eq!(after.main.1, before.main.1 + 2);
+        assert_eq!(
+            after.to_headers()[5].1,
+            format!("main {}", after.main.1 - after.now)
+        );
+    }
+
+    #[test]
+    fn flat_map() {
+        let limiter = HiddenKeyRateLimiter::new();
+        let (_, hit) = limiter.hit("1.1.1.1", "secret");
+        let map = hit.to_flat_map();
+        assert_eq!(map["main.remaining"], "1");
+        assert_eq!(map["main.limit"], "2");
+        assert_eq!(map["main.key"], "1.1.1.1+secret");
+        assert_eq!(map["main.public"], "true");
+        assert_eq!(map["main.spacing"], "1");
+        assert_eq!(map.len(), 10);
+    }
+
+    #[test]
+    fn counting() {
+        let limiter = CountingRateLimiter::new();
+        let _ = limiter.hit("1.1.1.1");
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(limited);
+        assert_eq!(hit.visits_used(), 4);
+        assert_eq!(hit.to_flat_map()["visits.used"], "4");
+        assert_eq!(hit.to_error_body().unwrap().retry_after, 100);
+        // counting rules never limit
+        limiter.main.remove("1.1.1.1");
+        limiter.visits.set("1.1.1.1", (0, u64::MAX), false);
+        assert!(!limiter.hit("1.1.1.1").0);
+    }
+
+    #[test]
+    fn coalesce_key() {
+        let limiter = CoalesceRateLimiter::new();
+        let (_, hit) = limiter.hit("", "2.2.2.2", "3.3.3.3", "/");
+        assert_eq!(hit.main.3, "2.2.2.2+/");
+        let (_, hit) = limiter.hit("1.1.1.1", "2.2.2.2", "3.3.3.3", "/");
+        assert_eq!(hit.main.3, "1.1.1.1+/");
+        let (_, hit) = limiter.hit("", "", "", "/");
+        assert_eq!(hit.main.3, "<empty>+/");
+    }
+
+    #[test]
+    fn public_override() {
+        let limiter = RateLimiter::new().with_public(false);
+        let (_, hit) = limiter.hit("1.1.1.1", "/public", "GET");
+        assert!(!hit.main.2);
+        assert!(hit.to_headers().is_empty());
+        let limiter = limiter.with_rule_public("max", true).fork();
+        let _ = limiter.hit("1.1.1.1", "/public", "GET");
+        let _ = limiter.hit("1.1.1.1", "/public", "GET");
+        let (_, hit) = limiter.hit("1.1.1.1", "/public", "GET");
+        assert!(hit.max.2);
+        assert_eq!(hit.to_headers()[0], ("X-RateLimit-Limit", "max 3".into()));
+        // main resets last and is still overridden to be private
+        assert_eq!(hit.to_error_body().unwrap().rule, None);
+        // counting rules stay private
+        let (_, hit) = CountingRateLimiter::new().with_public(true).hit("1.1.1.1");
+        assert!(hit.main.2 && !hit.visits.2);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn prometheus_metrics() {
+        let limiter = RateLimiter::new();
+        for _ in 0..3 {
+            let _ = limiter.clone().hit("1.1.1.1", "/metrics", "GET");
+        }
+        let metrics = limiter.prometheus_metrics();
+        assert!(metrics.contains("# TYPE ceiling_requests_total counter\n"));
+        assert!(metrics.contains("ceiling_requests_total{rule=\"main\",outcome=\"allowed\"} 1\n"));
+        assert!(metrics.contains("ceiling_requests_total{rule=\"main\",outcome=\"limited\"} 2\n"));
+        assert!(metrics.contains(
+            "ceiling_requests_total{rule=\"max\",tier=\"free\",note=\"a \\\"b\\\"\",outcome=\"limited\"} 1\n"
+        ));
+        assert!(metrics.contains("ceiling_buckets{rule=\"main\"} 1\n"));
+        assert!(limiter
+            .fork()
+            .prometheus_metrics()
+            .contains("outcome=\"limited\"} 0\n"));
+    }
+
+    #[test]
+    fn erased_store() {
+        let limiter = ErasedRateLimiter::new();
+        let _ = limiter.hit("1.1.1.1");
+        assert_eq!(limiter.hit("1.1.1.1").1.main.0, 0);
+        // the same type with a different store
+        let limiter: ErasedRateLimiter = limiter.with_store::<FailingStore>();
+        assert_eq!(limiter.hit("1.1.1.1").1.main.0, 1);
+        assert!(limiter.try_hit("1.1.1.1").is_err());
+    }
+
+    #[test]
+    fn clear_rule() {
+        let limiter = RateLimiter::new();
+        let _ = limiter.hit("1.1.1.1", "/clear", "GET");
+        let _ = limiter.hit("2.2.2.2", "/clear", "GET");
+        assert!(limiter.clear_rule("main"));
+        assert_eq!(limiter.main.bucket_count(), 0);
+        assert_eq!(limiter.max.bucket_count(), 2);
+        assert!(!limiter.clear_rule("unknown"));
+    }
+
+    #[test]
+    fn memory_budget() {
+        let limiter = GraceRateLimiter::new().with_memory_budget(2);
+        for ip in ["1.1.1.1", "2.2.2.2", "3.3.3.3"] {
+            let _ = limiter.hit(ip);
+        }
+        // the budget is enforced before each hit, evicting the bucket resetting soonest
+        assert_eq!(limiter.trial.bucket_count(), 3);
+        let _ = limiter.clone().hit("4.4.4.4");
+        assert_eq!(limiter.trial.bucket_count(), 3);
+        assert_eq!(limiter.trial.read("1.1.1.1"), None);
+        assert_eq!(limiter.fork().__ceiling.memory_budget, Some(2));
+    }
+
+    #[cfg(feature = "clock")]
+    #[test]
+    fn clock() {
+        #[derive(Debug)]
+        struct FixedClock;
+
+        impl ceiling::Clock for FixedClock {
+            fn now_secs(&self) -> u64 {
+                1000
+            }
+        }
+
+        let limiter = GraceRateLimiter::new().with_clock(FixedClock);
+        let (_, hit) = limiter.hit("1.1.1.1");
+        assert_eq!(hit.now, 1000);
+        assert!(hit.trial.1 < 2000);
+        let (_, hit) = limiter.fork().hit("1.1.1.1");
+        assert_eq!(hit.now, 1000);
+        let limiter = GraceRateLimiter::new().with_clock(ceiling::CoarseClock::new());
+        assert!(!limiter.hit("1.1.1.1").0);
+    }
+
+    #[test]
+    fn config_named_rules() {
+        let limiter = ConfigNamedRateLimiter::new();
+        let (_, hit) = limiter.hit("1.1.1.1");
+        assert_eq!((hit.limits.0, hit.enabled.0), (1, 2));
+        assert_eq!(limiter.limits.bucket_count(), 1);
+        limiter.set_rule_enabled("enabled", false);
+        assert_eq!(limiter.rule_enabled("enabled"), Some(false));
+        let (_, hit) = limiter.hit("1.1.1.1");
+        assert_eq!(hit.limits.0, 0);
+    }
+
+    #[test]
+    fn local_named_rules() {
+        let limiter = LocalNamedRateLimiter::new();
+        let (_, hit) = limiter.hit("1.1.1.1");
+        assert_eq!((hit.reset.0, hit.reset.4), (1, 2));
+        assert_eq!((hit.rule.0, hit.rule.4), (1, 2));
+    }
+
+    #[test]
+    fn memory_budget_untouched_keys() {
+        let limiter = GraceRateLimiter::new().with_memory_budget(2);
+        let _ = limiter.hit("1.1.1.1");
+        // crediting keys without buckets locks them without saving them, which doesn't count towards the budget
+        for i in 0..100 {
+            limiter.credit(format!("10.0.0.{i}"), 1);
+        }
+        let _ = limiter.hit("2.2.2.2");
+        assert!(limiter.trial.read("1.1.1.1").is_some());
+        assert_eq!(limiter.trial.bucket_count(), 2);
+    }
+
+    #[cfg(feature = "events")]
+    #[test]
+    fn events() {
+        use ceiling::{BucketEvent, BucketOutcome};
+
+        // the grace keeps the bucket from tripping until the fourth hit
+        let limiter = GraceRateLimiter::new();
+        // nothing is published without a receiver
+        let _ = limiter.hit("1.1.1.1");
+        let mut events = limiter.clone().subscribe();
+        for _ in 0..3 {
+            let _ = limiter.hit("1.1.1.1");
+        }
+        let _ = limiter.hit("2.2.2.2");
+        let event = |key: &str, remaining, outcome| BucketEvent {
+            rule: "trial",
+            key: key.into(),
+            remaining,
+            outcome,
+        };
+        assert_eq!(
+            events.try_recv().unwrap(),
+            event("1.1.1.1", 2, BucketOutcome::Hit)
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            event("1.1.1.1", 1, BucketOutcome::Hit)
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            event("1.1.1.1", 0, BucketOutcome::Trip)
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            event("2.2.2.2", 2, BucketOutcome::Reset)
+        );
+        assert!(events.try_recv().is_err());
+        let _ = limiter.fork().hit("1.1.1.1");
+        limiter.set_rule_enabled("trial", false);
+        let _ = limiter.hit("3.3.3.3");
+        assert!(events.try_recv().is_err());
+        // an atomic hit rejected by one rule only publishes the rule that tripped
+        let limiter = RateLimiter::new();
+        let _ = limiter.hit_atomic("1.1.1.1", "/events", "A");
+        let _ = limiter.hit_atomic("1.1.1.1", "/events", "B");
+        let mut events = limiter.subscribe();
+        assert!(limiter.hit_atomic("1.1.1.1", "/events", "C").0);
+        let event = events.try_recv().unwrap();
+        assert_eq!((event.rule, event.outcome), ("max", BucketOutcome::Trip));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn pruner() {
+        let limiter = RateLimiter::new();
+        limiter.main.set("expired", (1, 10), true);
+        let pruner = limiter.spawn_pruner(std::time::Duration::from_millis(10));
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(limiter.main.read("expired"), None);
+        pruner.stop();
+    }
+
+    #[test]
+    fn prune_expired() {
+        let limiter = RateLimiter::new();
+        limiter.main.set("expired", (1, 10), true);
+        limiter.max.set("expired", (1, 10), true);
+        limiter.max.set("fresh", (1, u64::MAX), true);
+        let mut pruned = limiter.prune_expired();
+        pruned.sort();
+        assert_eq!(
+            pruned,
+            [
+                ("main", String::from("expired")),
+                ("max", String::from("expired"))
+            ]
+        );
+        assert!(limiter.prune_expired().is_empty());
+        assert_eq!(limiter.max.bucket_count(), 1);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn tokio_prune_expired() {
+        let limiter = TokioRateLimiter::new();
+        limiter.main.set("expired#0", (1, 10), true).await;
+        assert_eq!(
+            limiter.prune_expired().await,
+            [("main", String::from("expired#0"))]
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn tokio_pruner() {
+        let limiter = TokioRateLimiter::new();
+        limiter.main.set("expired#0", (1, 10), true).await;
+        assert_eq!(limiter.main.keys_with_prefix("").await.len(), 1);
+        let _pruner = limiter.spawn_pruner(std::time::Duration::from_millis(10));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(limiter.main.keys_with_prefix("").await.is_empty());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn tokio_store() {
+        let limiter = TokioRateLimiter::new();
+        assert!(!limiter.hit("1.1.1.1").await.0);
+        assert!(limiter.hit_atomic("1.1.1.1").await.0);
+        // a limited atomic hit takes nothing from the buckets
+        assert_eq!(limiter.main_remaining("1.1.1.1").await, 1);
+        assert_eq!(limiter.keys_with_prefix("main", "1.1.1.1").await.len(), 2);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn batched_windows() {
+        let limiter = BatchedRateLimiter::new();
+        // the buckets of every window are got and set in one call each
+        let (_, hit) = limiter.hit("1.1.1.1").await;
+        assert_eq!(hit.main.0, 1);
+        assert_eq!(
+            std::mem::take(&mut *limiter.main.calls.lock().unwrap()),
+            ["get_many", "set_many"]
+        );
+        // a limited atomic hit has nothing to set
+        assert!(limiter.hit_atomic("1.1.1.1").await.0);
+        assert_eq!(
+            std::mem::take(&mut *limiter.main.calls.lock().unwrap()),
+            ["get_many"]
+        );
+        // the fallible methods have no batched variants
+        let _ = limiter.try_hit("2.2.2.2").await;
+        assert_eq!(
+            std::mem::take(&mut *limiter.main.calls.lock().unwrap()),
+            ["get", "set", "get", "set"]
+        );
+    }
+
+    #[test]
+    fn fresh_bucket_pruned() {
+        let limiter = RateLimiter::new();
+        let _ = limiter.hit("1.1.1.1", "/fresh", "GET");
+        limiter.main.prune(u64::MAX);
+        assert!(limiter.keys_with_prefix("main", "1.1.1.1").is_empty());
+    }
+
+    #[test]
+    fn clear_buckets() {
+        let limiter = RateLimiter::new();
+        let _ = limiter.hit("1.1.1.1", "/a", "GET");
+        let _ = limiter.hit("1.1.1.1", "/b", "POST");
+        let _ = limiter.hit("2.2.2.2", "/a", "GET");
+        assert_eq!(limiter.clear_buckets(Some("1.1.1.1"), None, None), 4);
+        assert_eq!(limiter.keys_with_prefix("main", "").len(), 1);
+        // the route isn't the first part of the key, so the keys are matched after listing them
+        assert_eq!(limiter.clear_buckets(None, Some("/a"), None), 2);
+        assert_eq!(limiter.clear_buckets(None, None, Some("GET")), 0);
+        let limiter = WindowsRateLimiter::new();
+        let _ = limiter.hit("1.1.1.1");
+        assert_eq!(limiter.clear_buckets(Some("1.1.1.1")), 2);
+    }
+
+    #[test]
+    fn relative_timeout() {
+        let limiter = RelativeTimeoutRateLimiter::new();
+        let _ = limiter.hit("1.1.1.1");
+        let (_, hit) = limiter.hit("1.1.1.1");
+        assert_eq!(hit.to_flat_map()["main.timeout"], "30");
+        assert_eq!(hit.burst_windows[0].rule.timeout, 10);
+        assert_eq!(hit.burst_windows[1].rule.timeout, 60);
+    }
+
+    #[test]
+    fn extend_on_abuse() {
+        let limiter = AbuseRateLimiter::new();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        // a client that emptied its buckets earlier and is still limited
+        limiter.fixed.set("1.1.1.1", (0, now + 10), true);
+        limiter.extended.set("1.1.1.1", (0, now + 10), true);
+        let (limited, hit) = limiter.hit("1.1.1.1");
+        assert!(limited);
+        assert_eq!(
+            hit.fixed,
+            (0, now + 10, false, "1.1.1.1".into(), 2, 100, 50)
+        );
+        assert_eq!(hit.extended.0, 0);
+        assert!(hit.extended.1 >= now + 50);
+        // the extended reset is saved, so it keeps moving while the client retries
+        assert_eq!(limiter.extended.read("1.1.1.1"), Some((0, hit.extended.1)));
+        assert_eq!(limiter.fixed.read("1.1.1.1"), Some((0, now + 10)));
+    }
+
+    #[test]
+    fn included_rules() {
+        let limiter = IncludedRateLimiter::new();
+        let _ = limiter.hit("1.1.1.1", "/a");
+        let (limited, hit) = limiter.hit("1.1.1.1", "/a");
+        assert!(limited);
+        assert_eq!(hit.main.3, "1.1.1.1+/a");
+        assert_eq!(hit.daily.0, 8);
+    }
+
+    #[test]
+    fn global_rule() {
+        let limiter = GlobalRateLimiter::new();
+        let _ = limiter.hit("1.1.1.1");
+        let _ = limiter.hit("");
+        let (limited, hit) = limiter.hit("2.2.2.2");
+        assert!(!limited);
+        assert_eq!(
+            hit.total,
+            (1, hit.total.1, false, "total".into(), 4, 100, 100)
+        );
+        // an empty input gets its own bucket in keyed rules instead of sharing the global one
+        assert_eq!(limiter.main_remaining(""), 1);
+        assert!(limiter.hit("3.3.3.3").0);
+        assert!(GlobalRateLimiter::RULES[1].global);
+        assert!(!GlobalRateLimiter::RULES[0].global);
+    }
+
+    #[test]
+    fn rule_enabled() {
+        let limiter = RateLimiter::new();
+        let clone = limiter.clone();
+        clone.set_rule_enabled("main", false);
+        clone.set_rule_enabled("unknown", false);
+        assert_eq!(limiter.rule_enabled("main"), Some(false));
+        assert_eq!(limiter.rule_enabled("unknown"), None);
+        for _ in 0..2 {
+            let (limited, hit) = limiter.hit("1.1.1.1", "/enabled", "GET");
+            assert!(!limited);
+            assert_eq!(hit.main.0, 2);
+        }
+        // a disabled rule doesn't take from its bucket
+        assert_eq!(limiter.main_remaining("1.1.1.1", "/enabled", "GET"), 2);
+        assert_eq!(limiter.max_remaining("1.1.1.1", "/enabled"), 1);
+        assert!(limiter.hit_atomic("1.1.1.1", "/enabled", "GET").0);
+        assert_eq!(limiter.fork().rule_enabled("main"), Some(false));
+        limiter.set_rule_enabled("main", true);
+        assert_eq!(clone.hit("2.2.2.2", "/enabled", "GET").1.main.0, 1);
+        assert!(clone.hit("2.2.2.2", "/enabled", "GET").0);
+    }
+
+    #[test]
+    fn no_inputs() {
+        let limiter = ServiceRateLimiter::new();
+        let (limited, hit) = limiter.hit();
+        assert!(!limited);
+        assert_eq!(hit.total.3, "total");
+        assert_eq!((hit.total.0, hit.daily.0), (2, 9));
+        assert_eq!(limiter.total_remaining(), 2);
+        assert!(!limiter.hit_atomic().0);
+        assert_eq!(limiter.hit_fast(), (true, Some("total")));
+        assert!(ServiceRateLimiter::new().config().inputs.is_empty());
+    }
+
+    #[test]
+    fn hit_with_keys() {
+        let limiter = RateLimiter::new();
+        let keys = limiter.keys("1.1.1.1", "/keys", "GET");
+        assert_eq!(keys, ["1.1.1.1+/keys+GET", "1.1.1.1+/keys"]);
+        let (_, hit) = limiter.hit_with_keys(keys);
+        assert_eq!((hit.main.0, hit.max.0), (1, 2));
+        // the keys are the same buckets `hit` uses
+        let (limited, hit) = limiter.hit("1.1.1.1", "/keys", "GET");
+        assert!(limited);
+        assert_eq!(hit.max.0, 1);
+        let (_, hit) = limiter.hit_with_keys(["tenant-1", "tenant-1"]);
+        assert_eq!(hit.main.3, "tenant-1");
+    }
+
+    #[test]
+    fn hit_dyn() {
+        let limiter = RateLimiter::new();
+        let port = 8080;
+        let (_, hit) = limiter.hit_dyn(&[&"1.1.1.1", &"/dyn", &port]).unwrap();
+        assert_eq!(hit.main.3, "1.1.1.1+/dyn+8080");
+        assert_eq!(
+            limiter.hit_dyn(&[&"1.1.1.1"]).unwrap_err(),
+            crate::InputCountError {
+                expected: 3,
+                found: 1
+            }
+        );
+        assert!(ServiceRateLimiter::new().hit_dyn(&[]).is_ok());
+    }
+
+    #[test]
+    fn dyn_limiter() {
+        let limiters: std::collections::HashMap<&str, Box<dyn crate::DynLimiter>> = [
+            (
+                "routes",
+                Box::new(RateLimiter::new()) as Box<dyn crate::DynLimiter>,
+            ),
+            ("service", Box::new(ServiceRateLimiter::new())),
+        ]
+        .into_iter()
+        .collect();
+        let (limited, states) = limiters["routes"]
+            .hit_dyn(&["1.1.1.1", "/dyn", "GET"])
+            .unwrap();
+        assert!(!limited);
+        assert_eq!(states.len(), 2);
+        assert_eq!(
+            (
+                states[0].name,
+                states[0].remaining,
+                states[0].limit,
+                states[0].public
+            ),
+            ("main", 1, 2, true)
+        );
+        assert_eq!(states[1].key, "1.1.1.1+/dyn");
+        assert_eq!(
+            limiters["service"].hit_dyn(&["extra"]).unwrap_err(),
+            crate::InputCountError {
+                expected: 0,
+                found: 1
+            }
+        );
+    }
+
+    #[test]
+    fn chain() {
+        let (global, routes) = (ServiceRateLimiter::new(), RateLimiter::new());
+        let (limited, hit) = crate::chain!(global.hit(), routes.hit("1.1.1.1", "/chain", "GET"));
+        assert!(!limited);
+        assert_eq!(hit.len(), 2);
+        assert_eq!(
+            HitSummary::to_headers(&hit)[0],
+            ("X-RateLimit-Limit", "total 3".into())
+        );
+        assert!(hit
+            .to_headers()
+            .contains(&("X-RateLimit-Limit", "main 2".into())));
+        assert_eq!(hit.to_error_body(), None);
+        // the route is limited, every limiter is still hit
+        let (limited, hit) = crate::chain!(global.hit(), routes.hit("1.1.1.1", "/chain", "GET"),);
+        assert!(limited);
+        assert_eq!(hit.to_error_body().unwrap().rule, Some("main"));
+        assert_eq!(global.total_remaining(), 1);
+    }
+
+    #[test]
+    fn separator() {
+        let limiter = SeparatorRateLimiter::new();
+        let (_, hit) = limiter.hit("a+b", "c");
+        assert_eq!(hit.main.3, "a+b::c");
+        // the keys would both be `a+b+c` if they were joined with `+`
+        let (_, hit) = limiter.hit("a", "b+c");
+        assert_eq!(hit.main.0, 1);
+        assert_eq!(limiter.clear_buckets(Some("a+b"), None), 1);
+        assert_eq!(limiter.main_remaining("a", "b+c"), 1);
+    }
+
+    #[test]
+    fn distinct() {
+        let limiter = DistinctRateLimiter::new();
+        let (limited, hit) = limiter.hit("a", "/1");
+        assert!(!limited);
+        assert_eq!(hit.routes.0, 2);
+        // repeating a value doesn't take from the bucket
+        let (limited, hit) = limiter.hit("a", "/1");
+        assert!(!limited);
+        assert_eq!(hit.routes.0, 2);
+        assert!(!limiter.hit("b", "/2").0);
+        assert!(!limiter.hit("a", "/2").0);
+        assert!(limiter.hit("a", "/3").0);
+        assert!(limiter.hit("a", "/4").0);
+        // values seen before the limit was reached are still allowed
+        assert!(!limiter.hit("a", "/1").0);
+        // the bucket and the two values seen are removed
+        assert_eq!(limiter.clear_buckets(Some("a"), None), 3);
+        assert!(!limiter.hit("a", "/3").0);
+        // the value of one key can't be mistaken for another key containing `#`
+        assert!(!limiter.hit("b", "c#/1").0);
+        let (limited, hit) = limiter.hit("b#c", "/1");
+        assert!(!limited);
+        assert_eq!((hit.routes.0, hit.routes.3.as_str()), (2, "b\\#c"));
+        assert_eq!(limiter.clear_buckets(Some("b#c"), None), 2);
+        assert_eq!(limiter.clear_buckets(Some("b"), None), 3);
+    }
 }
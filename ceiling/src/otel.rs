@@ -0,0 +1,90 @@
+use opentelemetry::{trace::get_active_span, KeyValue};
+
+/// The state of a rule on a hit as passed to `record_hit`: its name, the requests remaining, its limit, and its reset.
+pub type RuleSpan = (&'static str, u32, u32, u64);
+
+/// Sets the outcome of a hit as attributes of the active span, called by the generated hit methods with the crate feature `otel` enabled.
+/// Counting rules aren't passed, since they never limit a request.
+///
+/// | Attribute                     | Description                                                                       |
+/// | ----------------------------- | --------------------------------------------------------------------------------- |
+/// | `http.rate_limit.limited`     | whether the request was limited                                                   |
+/// | `http.rate_limit.rule`        | the limited rule that resets last, or the rule with the fewest requests remaining |
+/// | `http.rate_limit.limit`       | the limit of that rule                                                            |
+/// | `http.rate_limit.remaining`   | the requests remaining in that rule's bucket                                      |
+/// | `http.rate_limit.reset_after` | the seconds until that rule's bucket resets                                       |
+pub fn record_hit(limited: bool, rules: &[RuleSpan], now: u64) {
+    let attributes = attributes(limited, rules, now);
+    get_active_span(|span| span.set_attributes(attributes));
+}
+
+fn attributes(limited: bool, rules: &[RuleSpan], now: u64) -> Vec<KeyValue> {
+    let mut attributes = vec![KeyValue::new("http.rate_limit.limited", limited)];
+    let rule = if limited {
+        rules
+            .iter()
+            .filter(|(_, remaining, ..)| *remaining == 0)
+            .max_by_key(|(.., reset)| *reset)
+    } else {
+        rules.iter().min_by_key(|(_, remaining, ..)| *remaining)
+    };
+    if let Some((rule, remaining, limit, reset)) = rule {
+        attributes.extend([
+            KeyValue::new("http.rate_limit.rule", *rule),
+            KeyValue::new("http.rate_limit.limit", *limit as i64),
+            KeyValue::new("http.rate_limit.remaining", *remaining as i64),
+            KeyValue::new(
+                "http.rate_limit.reset_after",
+                reset.saturating_sub(now) as i64,
+            ),
+        ]);
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::Value;
+
+    use super::*;
+
+    fn attribute(attributes: &[KeyValue], key: &str) -> Option<Value> {
+        attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    #[test]
+    fn limiting_rule() {
+        let rules = [
+            ("main", 0, 2, 110),
+            ("max", 0, 3, 130),
+            ("burst", 1, 5, 150),
+        ];
+        let limited = attributes(true, &rules, 100);
+        assert_eq!(
+            attribute(&limited, "http.rate_limit.limited"),
+            Some(Value::Bool(true))
+        );
+        assert_eq!(
+            attribute(&limited, "http.rate_limit.rule"),
+            Some(Value::from("max"))
+        );
+        assert_eq!(
+            attribute(&limited, "http.rate_limit.reset_after"),
+            Some(Value::I64(30))
+        );
+        let allowed = attributes(false, &rules[2..], 100);
+        assert_eq!(
+            attribute(&allowed, "http.rate_limit.remaining"),
+            Some(Value::I64(1))
+        );
+        assert_eq!(
+            attribute(&allowed, "http.rate_limit.limit"),
+            Some(Value::I64(5))
+        );
+        // only whether the request was limited is known without any rules that can limit it
+        assert_eq!(attributes(false, &[], 100).len(), 1);
+    }
+}
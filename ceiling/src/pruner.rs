@@ -0,0 +1,83 @@
+use std::{sync::mpsc, time::Duration};
+
+/// A handle to a background task pruning the stores of a rate limiter, returned from the generated `spawn_pruner` method.
+/// The stores are pruned every interval until the handle is stopped or dropped.
+#[derive(Debug)]
+pub struct Pruner {
+    inner: Inner,
+}
+
+#[derive(Debug)]
+enum Inner {
+    Thread {
+        // dropping the sender wakes the thread and stops it
+        stop: Option<mpsc::Sender<()>>,
+        handle: Option<std::thread::JoinHandle<()>>,
+    },
+    #[cfg(feature = "tokio")]
+    Task(tokio::task::JoinHandle<()>),
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl Pruner {
+    /// Spawns a thread calling `prune` with the current time in seconds every interval, used for synchronous stores.
+    pub fn thread<F>(interval: Duration, prune: F) -> Self
+    where
+        F: Fn(u64) + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = std::thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                prune(now());
+            }
+        });
+        Self {
+            inner: Inner::Thread {
+                stop: Some(stop),
+                handle: Some(handle),
+            },
+        }
+    }
+
+    /// Spawns a `tokio` task awaiting `prune` with the current time in seconds every interval, used for asynchronous stores.
+    /// Must be called from within a `tokio` runtime.
+    #[cfg(feature = "tokio")]
+    pub fn task<F, Fut>(interval: Duration, prune: F) -> Self
+    where
+        F: Fn(u64) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        Self {
+            inner: Inner::Task(tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+                    prune(now()).await;
+                }
+            })),
+        }
+    }
+
+    /// Stops pruning, waiting for a prune in progress on a thread to finish. This is the same as dropping the handle.
+    pub fn stop(self) {}
+}
+
+impl Drop for Pruner {
+    fn drop(&mut self) {
+        match &mut self.inner {
+            Inner::Thread { stop, handle } => {
+                drop(stop.take());
+                if let Some(handle) = handle.take() {
+                    let _ = handle.join();
+                }
+            },
+            #[cfg(feature = "tokio")]
+            Inner::Task(handle) => handle.abort(),
+        }
+    }
+}
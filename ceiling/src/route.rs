@@ -0,0 +1,46 @@
+/// Normalizes a route so equivalent paths share a bucket, e.g. `/API//Users/` and `/api/users` both become `/api/users`.
+/// The route is lowercased, the query string and fragment are removed, duplicate slashes are collapsed,
+/// and a trailing slash is stripped unless the route is just `/`.
+/// Percent-encoded slashes (`%2F`) aren't decoded, so they never split or join segments.
+pub fn normalize_route(route: &str) -> String {
+    let path = route.split(['?', '#']).next().unwrap_or_default();
+    let mut normalized = String::with_capacity(path.len() + 1);
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        normalized.push('/');
+        normalized.extend(segment.chars().flat_map(char::to_lowercase));
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// Normalizes a route like `normalize_route` and strips the last segment if it's a numeric ID,
+/// so `/users/123` and `/users/456` both become `/users`.
+pub fn normalize_route_strip_id(route: &str) -> String {
+    let mut normalized = normalize_route(route);
+    if let Some(index) = normalized.rfind('/') {
+        let segment = &normalized[index + 1..];
+        if !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit()) {
+            normalized.truncate(index.max(1));
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_route("/API//Users/"), "/api/users");
+        assert_eq!(normalize_route("api/users?page=2#top"), "/api/users");
+        assert_eq!(normalize_route("/files/a%2Fb/"), "/files/a%2fb");
+        assert_eq!(normalize_route("//?q=/a/b"), "/");
+        assert_eq!(normalize_route(""), "/");
+        assert_eq!(normalize_route_strip_id("/Users/123/?x=1"), "/users");
+        assert_eq!(normalize_route_strip_id("/123"), "/");
+        assert_eq!(normalize_route_strip_id("/users/12a"), "/users/12a");
+    }
+}
@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+/// The runtime configuration of a single rate limiting rule.
+/// Durations are stored internally as whole seconds in a `u32`, the same width used by the `rate_limiter!` macro.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Rule {
+    /// The number of requests allowed every interval.
+    pub limit: u32,
+    /// The interval in seconds before the bucket resets after the first hit.
+    pub interval: u32,
+    /// The timeout in seconds before the bucket resets after the limit is reached.
+    pub timeout: u32,
+}
+
+impl Rule {
+    /// Creates a new rule from raw second values.
+    pub const fn new(limit: u32, interval: u32, timeout: u32) -> Self {
+        Self {
+            limit,
+            interval,
+            timeout,
+        }
+    }
+
+    /// Creates a new rule allowing `limit` requests every `interval`, the timeout defaults to the interval.
+    /// Returns an error if the interval does not fit in the internal representation.
+    pub fn every(limit: u32, interval: Duration) -> Result<Self, DurationOverflow> {
+        let interval = Seconds::try_from(interval)?.0;
+        Ok(Self::new(limit, interval, interval))
+    }
+
+    /// Sets the timeout of the rule.
+    /// Returns an error if the timeout does not fit in the internal representation.
+    pub fn with_timeout(self, timeout: Duration) -> Result<Self, DurationOverflow> {
+        Ok(Self {
+            timeout: Seconds::try_from(timeout)?.0,
+            ..self
+        })
+    }
+
+    /// The interval of the rule as a `Duration`.
+    pub fn interval_duration(&self) -> Duration {
+        Seconds(self.interval).into()
+    }
+
+    /// The timeout of the rule as a `Duration`.
+    pub fn timeout_duration(&self) -> Duration {
+        Seconds(self.timeout).into()
+    }
+
+    /// The seconds a client should leave between requests to never be limited, the interval divided by the limit
+    /// rounded up to the millisecond. A rule with a limit of zero never allows a request, so this is the whole interval.
+    pub fn spacing(&self) -> f64 {
+        let millis = (self.interval as u64) * 1000;
+        millis.div_ceil(self.limit.max(1) as u64) as f64 / 1000.0
+    }
+}
+
+/// The state of one window of a rule with several windows, reported in the generated hit struct.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Window {
+    /// The limit, interval, and timeout of the window.
+    pub rule: Rule,
+    /// The requests remaining in the window.
+    pub remaining: u32,
+    /// The time in seconds when the window resets.
+    pub reset: u64,
+}
+
+impl Window {
+    /// Finds the most restrictive window, the limited window that resets last or otherwise the window with the fewest requests remaining.
+    /// This is the window reported for the rule in the headers and serialized output.
+    ///
+    /// # Panics
+    /// Panics if there are no windows.
+    pub fn most_restrictive(windows: &[Window]) -> &Window {
+        windows
+            .iter()
+            .min_by_key(|w| (w.remaining, std::cmp::Reverse(w.reset)))
+            .expect("a rule has at least one window")
+    }
+}
+
+/// A number of whole seconds in the internal representation used for intervals and timeouts.
+/// Sub-second precision is truncated when converting from a `Duration`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Seconds(pub u32);
+
+impl TryFrom<Duration> for Seconds {
+    type Error = DurationOverflow;
+
+    fn try_from(value: Duration) -> Result<Self, Self::Error> {
+        u32::try_from(value.as_secs())
+            .map(Self)
+            .map_err(|_| DurationOverflow(value))
+    }
+}
+
+impl From<Seconds> for Duration {
+    fn from(value: Seconds) -> Self {
+        Duration::from_secs(value.0 as u64)
+    }
+}
+
+/// The error returned when a `Duration` is too large to be stored as a number of seconds in a `u32`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DurationOverflow(pub Duration);
+
+impl std::fmt::Display for DurationOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "duration of {} seconds exceeds the maximum of {} seconds",
+            self.0.as_secs(),
+            u32::MAX
+        )
+    }
+}
+
+impl std::error::Error for DurationOverflow {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        let rule = Rule::every(10, Duration::from_secs(60))
+            .unwrap()
+            .with_timeout(Duration::from_millis(120_500))
+            .unwrap();
+        assert_eq!(rule, Rule::new(10, 60, 120));
+        assert_eq!(rule.timeout_duration(), Duration::from_secs(120));
+        let too_long = Duration::from_secs(u32::MAX as u64 + 1);
+        assert_eq!(Rule::every(1, too_long), Err(DurationOverflow(too_long)));
+    }
+
+    #[test]
+    fn spacing() {
+        assert_eq!(Rule::new(10, 1, 1).spacing(), 0.1);
+        assert_eq!(Rule::new(3, 1, 1).spacing(), 0.334);
+        assert_eq!(Rule::new(1, 60, 60).spacing(), 60.0);
+        assert_eq!(Rule::new(0, 60, 60).spacing(), 60.0);
+    }
+
+    #[test]
+    fn most_restrictive() {
+        let window = |remaining, reset| Window {
+            rule: Rule::new(10, 60, 60),
+            remaining,
+            reset,
+        };
+        let windows = [window(5, 10), window(0, 20), window(0, 30), window(1, 40)];
+        assert_eq!(Window::most_restrictive(&windows), &windows[2]);
+        assert_eq!(Window::most_restrictive(&windows[3..]), &windows[3]);
+    }
+}
@@ -1,52 +1,166 @@
-use std::{collections::BinaryHeap, sync::Mutex};
+use std::{
+    collections::{BinaryHeap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use dashmap::DashMap;
-use sero::{LockGuard, LockStore};
+
+use crate::error::StoreError;
+
+type Slots = DashMap<String, Arc<Slot>>;
 
 /// The default store implementation if none is specified when creating a rate limiter.
-/// The default implementation uses `dashmap::DashMap` to store buckets, `sero::LockStore` to store locks,
+/// The default implementation uses `dashmap::DashMap` to store buckets, each guarded by its own mutex and condvar,
 /// and a `std::collections::BinaryHeap` containing the expiry times for pruning expired buckets.
+/// Locking an uncontended bucket only takes the bucket's mutex, threads only wait on the condvar when the bucket is already locked.
+/// Each key has at most one entry in the heap, if a bucket's reset is pushed back the entry is rescheduled when it's popped.
 #[derive(Debug)]
 pub struct DefaultStore {
-    map: DashMap<String, (u32, u64)>,
-    locks: LockStore<String>,
-    expiring: Mutex<BinaryHeap<Expiry>>,
+    map: Arc<Slots>,
+    expiring: Mutex<Expiring>,
+    // the number of slots holding a value, the map also holds empty slots while they're locked
+    live: AtomicUsize,
 }
 
-#[derive(Debug, PartialEq, Eq)]
-struct Expiry(pub(crate) u64, pub(crate) String);
+/// A bucket in the `DefaultStore` along with whether it's locked by a `DefaultStoreLock`.
+#[derive(Debug, Default)]
+pub struct Slot {
+    state: Mutex<SlotState>,
+    unlocked: Condvar,
+}
 
-impl PartialOrd for Expiry {
-    #[inline]
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        other.0.partial_cmp(&self.0)
+#[derive(Debug, Default)]
+struct SlotState {
+    locked: bool,
+    value: Option<(u32, u64)>,
+}
+
+impl Slot {
+    fn state(&self) -> std::sync::MutexGuard<'_, SlotState> {
+        // the state is only ever assigned whole values, so it can't be left inconsistent by a panic
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
     }
+}
 
-    #[inline]
-    fn lt(&self, other: &Self) -> bool {
-        other.0 < self.0
+/// Assigns the value of a slot, counting the slots holding a value in `live`.
+fn store_value(live: &AtomicUsize, state: &mut SlotState, value: Option<(u32, u64)>) {
+    match (state.value.is_some(), value.is_some()) {
+        (false, true) => {
+            live.fetch_add(1, Ordering::Relaxed);
+        },
+        (true, false) => {
+            live.fetch_sub(1, Ordering::Relaxed);
+        },
+        _ => {},
     }
+    state.value = value;
+}
 
-    #[inline]
-    fn le(&self, other: &Self) -> bool {
-        other.0 <= self.0
+/// Removes the slot of a key from the map if it's empty and the only other reference to it is `held`.
+fn remove_empty(map: &Slots, key: &str, held: usize) {
+    map.remove_if(key, |_, slot| {
+        Arc::strong_count(slot) == held + 1 && slot.state().value.is_none()
+    });
+}
+
+impl DefaultStore {
+    /// Returns the statistics of the store's pruning and its current size,
+    /// e.g. to alert when the buckets grow faster than pruning removes them.
+    pub fn prune_stats(&self) -> PruneStats {
+        let expiring = self.expiring.lock().unwrap();
+        expiring.stats(self.live.load(Ordering::Relaxed))
     }
 
-    #[inline]
-    fn gt(&self, other: &Self) -> bool {
-        other.0 > self.0
+    fn slot(&self, key: &str) -> Arc<Slot> {
+        // the shared lookup is tried first so existing buckets don't take the shard's write lock
+        if let Some(slot) = self.map.get(key) {
+            return slot.clone();
+        }
+        self.map.entry(key.to_string()).or_default().clone()
     }
+}
+
+/// Statistics of the pruning of a `DefaultStore` or `TokioStore` since it was created, along with its current size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PruneStats {
+    /// The number of times the store was pruned.
+    pub prunes: u64,
+    /// The number of buckets removed by pruning.
+    pub pruned: u64,
+    /// The number of buckets removed by the last prune.
+    pub last_pruned: usize,
+    /// The time the last prune took.
+    pub last_duration: Duration,
+    /// The time spent pruning.
+    pub total_duration: Duration,
+    /// The number of buckets holding a value in the store, including those expired but not yet pruned.
+    pub buckets: usize,
+    /// The number of expiries scheduled in the heap, at most one per bucket.
+    pub scheduled: usize,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Expiring {
+    pub(crate) heap: BinaryHeap<Expiry>,
+    pub(crate) scheduled: HashSet<String>,
+    prunes: u64,
+    pruned: u64,
+    last_pruned: usize,
+    last_duration: Duration,
+    total_duration: Duration,
+}
 
+impl Expiring {
+    /// Records a prune that removed `pruned` buckets and started at `start`, only called for prunes that found expired entries
+    /// so the last prune's statistics aren't overwritten by the prunes made on every hit that have nothing to do.
+    pub(crate) fn record_prune(&mut self, pruned: usize, start: Instant) {
+        let duration = start.elapsed();
+        self.prunes += 1;
+        self.pruned += pruned as u64;
+        self.last_pruned = pruned;
+        self.last_duration = duration;
+        self.total_duration += duration;
+    }
+
+    pub(crate) fn stats(&self, buckets: usize) -> PruneStats {
+        PruneStats {
+            prunes: self.prunes,
+            pruned: self.pruned,
+            last_pruned: self.last_pruned,
+            last_duration: self.last_duration,
+            total_duration: self.total_duration,
+            buckets,
+            scheduled: self.heap.len(),
+        }
+    }
+
+    pub(crate) fn schedule(&mut self, expiry: u64, key: &str) {
+        if !self.scheduled.contains(key) {
+            self.scheduled.insert(key.to_string());
+            self.heap.push(Expiry(expiry, key.to_string()));
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Expiry(pub(crate) u64, pub(crate) String);
+
+impl PartialOrd for Expiry {
     #[inline]
-    fn ge(&self, other: &Self) -> bool {
-        other.0 >= self.0
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Expiry {
     #[inline]
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        other.0.cmp(&self.0)
+        // reversed so the heap pops the earliest expiry first, ties are popped in the order of the keys
+        other.0.cmp(&self.0).then_with(|| other.1.cmp(&self.1))
     }
 }
 
@@ -58,53 +172,183 @@ impl SyncStore for DefaultStore {
         Self: Sized,
     {
         Self {
-            map: DashMap::new(),
-            locks: LockStore::new(),
-            expiring: Mutex::new(BinaryHeap::new()),
+            map: Arc::new(DashMap::new()),
+            expiring: Mutex::new(Expiring::default()),
+            live: AtomicUsize::new(0),
         }
     }
 
     fn get(&self, key: &str) -> Self::Lock {
-        let guard = self.locks.lock(key.into()).wait();
-        let value = self.map.get(key).map(|v| *v);
-        Self::Lock::new(value, guard)
+        let slot = self.slot(key);
+        let mut state = slot.state();
+        while state.locked {
+            state = slot.unlocked.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        state.locked = true;
+        let value = state.value;
+        drop(state);
+        // the slot of a new bucket is removed when it's released unless a value was set
+        let owner = value.is_none().then(|| (self.map.clone(), key.to_string()));
+        DefaultStoreLock { value, slot, owner }
+    }
+
+    fn read(&self, key: &str) -> Option<(u32, u64)> {
+        self.map.get(key).and_then(|slot| slot.state().value)
     }
 
     fn set(&self, key: &str, value: (u32, u64), reset_updated: bool) {
-        self.map.insert(key.to_string(), value);
+        store_value(&self.live, &mut self.slot(key).state(), Some(value));
         if reset_updated {
             let mut lock = self.expiring.lock().unwrap();
-            lock.push(Expiry(value.1 + 1, key.to_string()));
+            lock.schedule(value.1.saturating_add(1), key);
         }
     }
 
+    fn compare_and_set(&self, key: &str, expected: Option<(u32, u64)>, new: (u32, u64)) -> bool {
+        let slot = self.slot(key);
+        let mut state = slot.state();
+        // a bucket locked by `get` is about to be written, so the comparison waits for that write
+        while state.locked {
+            state = slot.unlocked.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        if state.value != expected {
+            let empty = state.value.is_none();
+            drop(state);
+            if empty {
+                remove_empty(&self.map, key, 1);
+            }
+            return false;
+        }
+        store_value(&self.live, &mut state, Some(new));
+        drop(state);
+        if expected.is_none_or(|e| e.1 != new.1) {
+            let mut lock = self.expiring.lock().unwrap();
+            lock.schedule(new.1.saturating_add(1), key);
+        }
+        true
+    }
+
     fn remove(&self, key: &str) {
-        self.map.remove(key);
+        if let Some(slot) = self.map.get(key) {
+            store_value(&self.live, &mut slot.state(), None);
+        }
+        // buckets that are locked or being waited on stay in the map so every waiter shares the same slot
+        self.map
+            .remove_if(key, |_, slot| Arc::strong_count(slot) == 1);
     }
 
-    fn prune(&self, now: u64) {
+    fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        self.map
+            .iter()
+            .filter(|entry| {
+                entry.key().starts_with(prefix) && entry.value().state().value.is_some()
+            })
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    fn clear(&self) {
+        let mut expiring = self.expiring.lock().unwrap();
+        // buckets that are locked or being waited on stay in the map like in `remove`, along with their expiries
+        // so a hit in progress that saves its bucket again still has it pruned
+        self.map.retain(|_, slot| {
+            store_value(&self.live, &mut slot.state(), None);
+            Arc::strong_count(slot) > 1
+        });
+        expiring
+            .heap
+            .retain(|expiry| self.map.contains_key(&expiry.1));
+        expiring.scheduled.retain(|key| self.map.contains_key(key));
+    }
+
+    fn bucket_count(&self) -> usize {
+        self.live.load(Ordering::Relaxed)
+    }
+
+    fn evict(&self, count: usize) -> usize {
+        // the heap is popped in order of expiry, a bucket whose reset was pushed back may be evicted a little early
         let mut expiring = self.expiring.lock().unwrap();
-        loop {
-            let peek = expiring.peek();
-            if let Some(peek) = peek {
-                if peek.0 < now {
+        let mut kept = vec![];
+        let mut evicted = 0;
+        while evicted < count {
+            let Some(expiry) = expiring.heap.pop() else {
+                break;
+            };
+            // buckets that are locked or being waited on are kept, a hit in progress would save them again without scheduling them
+            if let Some((_, slot)) = self
+                .map
+                .remove_if(&expiry.1, |_, slot| Arc::strong_count(slot) == 1)
+            {
+                store_value(&self.live, &mut slot.state(), None);
+                evicted += 1;
+            } else if self.map.contains_key(&expiry.1) {
+                kept.push(expiry);
+                continue;
+            }
+            expiring.scheduled.remove(&expiry.1);
+        }
+        expiring.heap.extend(kept);
+        evicted
+    }
+
+    fn prune(&self, now: u64) {
+        self.prune_returning(now);
+    }
+
+    fn prune_returning(&self, now: u64) -> Vec<String> {
+        // the expired keys are collected first so the heap isn't locked while waiting on bucket
+        // locks, `set` acquires them in the opposite order
+        let mut expired = vec![];
+        {
+            let mut expiring = self.expiring.lock().unwrap();
+            while let Some(peek) = expiring.heap.peek() {
+                if peek.0 >= now {
                     break;
                 }
-                let key = &expiring.pop().unwrap().1;
-                let lock = self.get(key);
-                let item = *lock;
-                if let Some(item) = item {
-                    if item.1 < now {
-                        continue;
-                    }
-                    self.remove(key);
-                }
+                expired.push(expiring.heap.pop().unwrap().1);
+            }
+        }
+        let mut pruned = vec![];
+        if expired.is_empty() {
+            return pruned;
+        }
+        let start = Instant::now();
+        let last = expired.len() - 1;
+        for (i, key) in expired.into_iter().enumerate() {
+            let lock = self.get(&key);
+            let mut expiring = self.expiring.lock().unwrap();
+            match *lock {
+                // the reset was pushed back since the entry was scheduled
+                Some(item) if item.1 >= now => {
+                    expiring.heap.push(Expiry(item.1.saturating_add(1), key));
+                },
+                Some(_) => {
+                    store_value(&self.live, &mut lock.slot.state(), None);
+                    // the only other reference to the slot is the lock held here, unless another thread is waiting on it
+                    self.map
+                        .remove_if(&key, |_, slot| Arc::strong_count(slot) == 2);
+                    expiring.scheduled.remove(&key);
+                    pruned.push(key);
+                },
+                None => {
+                    expiring.scheduled.remove(&key);
+                },
+            }
+            // recorded while the heap is locked for the last key, so recording doesn't lock it again
+            if i == last {
+                expiring.record_prune(pruned.len(), start);
             }
         }
+        pruned
     }
 }
 
 /// The trait providing the required methods for a synchronous store of buckets.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` isn't a synchronous store",
+    label = "doesn't implement `ceiling::SyncStore`",
+    note = "rate limiters without `async` need a store implementing `ceiling::SyncStore`, add `async` before `in` for a store implementing `ceiling::AsyncStore`"
+)]
 pub trait SyncStore: std::fmt::Debug + Send + Sync {
     /// The type of the Lock returned from `SyncStore::get`, must implement `ceiling::StoreLock`.
     type Lock: StoreLock;
@@ -115,17 +359,99 @@ pub trait SyncStore: std::fmt::Debug + Send + Sync {
         Self: Sized;
     /// Gets a bucket from the store, the return value must implement `ceiling::StoreLock`
     fn get(&self, key: &str) -> Self::Lock;
+    /// Reads a bucket from the store without locking it, the value may be outdated by the time it's returned.
+    /// Stores that can read a bucket without taking its lock should override this,
+    /// the default implementation locks the bucket with `SyncStore::get` and releases it immediately.
+    fn read(&self, key: &str) -> Option<(u32, u64)> {
+        *self.get(key)
+    }
     /// Sets the value of a bucket in the store.
     /// If reset_updated is true then the u64 reset value was updated. This may be helpful for internal implementations of `SyncStore::prune`.
     fn set(&self, key: &str, value: (u32, u64), reset_updated: bool);
+    /// Gets a bucket from the store like `SyncStore::get`, returning an error if the store couldn't be reached.
+    /// Called by the generated `try_hit` method, stores that can fail should override this and `SyncStore::try_set`.
+    /// The default implementation calls `SyncStore::get` and never errors.
+    fn try_get(&self, key: &str) -> Result<Self::Lock, StoreError> {
+        Ok(self.get(key))
+    }
+    /// Sets the value of a bucket in the store like `SyncStore::set`, returning an error if the store couldn't be reached.
+    /// The default implementation calls `SyncStore::set` and never errors.
+    fn try_set(&self, key: &str, value: (u32, u64), reset_updated: bool) -> Result<(), StoreError> {
+        self.set(key, value, reset_updated);
+        Ok(())
+    }
+    /// Sets the value of a bucket only if its current value is `expected`, returning whether the value was set.
+    /// The reset is considered updated if it differs from the expected value's.
+    /// The default implementation locks the bucket with `SyncStore::get` and calls `SyncStore::set` while holding the lock,
+    /// so it's only atomic with respect to other callers locking the bucket, e.g. a `set` made without the lock may be lost.
+    fn compare_and_set(&self, key: &str, expected: Option<(u32, u64)>, new: (u32, u64)) -> bool {
+        let lock = self.get(key);
+        if *lock != expected {
+            return false;
+        }
+        self.set(key, new, expected.is_none_or(|e| e.1 != new.1));
+        true
+    }
     /// Removes a bucket from the store.
     fn remove(&self, key: &str);
+    /// Lists the keys of the buckets in the store starting with the prefix, this may include expired buckets that haven't been pruned yet.
+    /// This is O(n) in the size of the store and should be used sparingly.
+    /// The default implementation returns no keys, for stores that can't scan their buckets.
+    fn keys_with_prefix(&self, _prefix: &str) -> Vec<String> {
+        vec![]
+    }
+    /// Removes every bucket from the store, called by the generated `clear_rule` method.
+    /// The default implementation removes each key from `SyncStore::keys_with_prefix`, so stores that can't list their keys should override it.
+    fn clear(&self) {
+        for key in self.keys_with_prefix("") {
+            self.remove(&key);
+        }
+    }
+    /// The number of buckets in the store, counted by rate limiters with a memory budget before each hit.
+    /// The default implementation counts the keys from `SyncStore::keys_with_prefix`, so it's O(n) and stores that can't list their keys report none.
+    fn bucket_count(&self) -> usize {
+        self.keys_with_prefix("").len()
+    }
+    /// Evicts up to `count` buckets, those resetting soonest first, returning the number evicted.
+    /// Called by rate limiters over their memory budget on the store holding the most buckets.
+    /// The default implementation reads every bucket from `SyncStore::keys_with_prefix` and removes those with the earliest resets.
+    fn evict(&self, count: usize) -> usize {
+        let mut buckets = self
+            .keys_with_prefix("")
+            .into_iter()
+            .filter_map(|key| Some((self.read(&key)?.1, key)))
+            .collect::<Vec<_>>();
+        buckets.sort_unstable();
+        buckets.truncate(count);
+        for (_, key) in &buckets {
+            self.remove(key);
+        }
+        buckets.len()
+    }
     /// Prunes the store of any expired values. Any bucket with a reset value less than the provided now value is considered expired.
     fn prune(&self, now: u64);
+    /// Prunes the store like `SyncStore::prune`, returning the keys of the buckets removed, called by the generated `prune_expired` method
+    /// so they can be acted on, e.g. to invalidate caches keyed by them.
+    /// The default implementation reads every bucket from `SyncStore::keys_with_prefix` before pruning and returns those that had expired,
+    /// so stores that can't list their keys return none.
+    fn prune_returning(&self, now: u64) -> Vec<String> {
+        let expired = self
+            .keys_with_prefix("")
+            .into_iter()
+            .filter(|key| self.read(key).is_some_and(|(_, reset)| reset < now))
+            .collect();
+        self.prune(now);
+        expired
+    }
 }
-///
+/// The trait providing the required methods for an asynchronous store of buckets.
 #[cfg(feature = "async")]
 #[async_trait::async_trait]
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` isn't an asynchronous store",
+    label = "doesn't implement `ceiling::AsyncStore`",
+    note = "rate limiters with `async` need a store implementing `ceiling::AsyncStore`, remove `async` for a store implementing `ceiling::SyncStore`"
+)]
 pub trait AsyncStore: std::fmt::Debug + Send + Sync {
     /// The type of the Lock returned from `AsyncStore::get`, must implement `ceiling::StoreLock`.
     type Lock: StoreLock;
@@ -136,13 +462,124 @@ pub trait AsyncStore: std::fmt::Debug + Send + Sync {
         Self: Sized;
     /// Gets a bucket from the store, the return value must implement `ceiling::StoreLock`
     async fn get(&self, key: &str) -> Self::Lock;
+    /// Reads a bucket from the store without locking it, the value may be outdated by the time it's returned.
+    /// Stores that can read a bucket without taking its lock should override this,
+    /// the default implementation locks the bucket with `AsyncStore::get` and releases it immediately.
+    async fn read(&self, key: &str) -> Option<(u32, u64)> {
+        *self.get(key).await
+    }
     /// Sets the value of a bucket in the store.
     /// If reset_updated is true then the u64 reset value was updated. This may be helpful for internal implementations of `AsyncStore::prune`.
     async fn set(&self, key: &str, value: (u32, u64), reset_updated: bool);
+    /// Gets a bucket from the store like `AsyncStore::get`, returning an error if the store couldn't be reached.
+    /// Called by the generated `try_hit` method, stores that can fail should override this and `AsyncStore::try_set`.
+    /// The default implementation awaits `AsyncStore::get` and never errors.
+    async fn try_get(&self, key: &str) -> Result<Self::Lock, StoreError> {
+        Ok(self.get(key).await)
+    }
+    /// Sets the value of a bucket in the store like `AsyncStore::set`, returning an error if the store couldn't be reached.
+    /// The default implementation awaits `AsyncStore::set` and never errors.
+    async fn try_set(
+        &self,
+        key: &str,
+        value: (u32, u64),
+        reset_updated: bool,
+    ) -> Result<(), StoreError> {
+        self.set(key, value, reset_updated).await;
+        Ok(())
+    }
+    /// Gets multiple buckets from the store, in the same order as the keys.
+    /// The generated hit methods get the buckets of every window of a rule with several windows with this, in the order of the windows.
+    /// Stores backed by a remote service can override this to fetch every bucket in a single round trip,
+    /// the default implementation awaits `AsyncStore::get` for each key in turn.
+    /// The buckets are locked in the order given, so callers should pass keys in a consistent order to avoid deadlocks.
+    async fn get_many(&self, keys: &[&str]) -> Vec<Self::Lock> {
+        let mut locks = Vec::with_capacity(keys.len());
+        for key in keys {
+            locks.push(self.get(key).await);
+        }
+        locks
+    }
+    /// Sets the values of multiple buckets in the store, each item corresponding to the arguments of `AsyncStore::set`.
+    /// The generated hit methods set the buckets of a rule with several windows with this while they're still locked.
+    /// Stores backed by a remote service can override this to write every bucket in a single round trip,
+    /// the default implementation awaits `AsyncStore::set` for each item in turn.
+    async fn set_many(&self, values: &[(&str, (u32, u64), bool)]) {
+        for (key, value, reset_updated) in values {
+            self.set(key, *value, *reset_updated).await;
+        }
+    }
+    /// Sets the value of a bucket only if its current value is `expected`, returning whether the value was set.
+    /// The reset is considered updated if it differs from the expected value's.
+    /// The default implementation locks the bucket with `AsyncStore::get` and awaits `AsyncStore::set` while holding the lock,
+    /// so it's only atomic with respect to other callers locking the bucket, e.g. a `set` made without the lock may be lost.
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<(u32, u64)>,
+        new: (u32, u64),
+    ) -> bool {
+        let lock = self.get(key).await;
+        if *lock != expected {
+            return false;
+        }
+        self.set(key, new, expected.is_none_or(|e| e.1 != new.1))
+            .await;
+        true
+    }
     /// Removes a bucket from the store.
     async fn remove(&self, key: &str);
+    /// Lists the keys of the buckets in the store starting with the prefix, this may include expired buckets that haven't been pruned yet.
+    /// This is O(n) in the size of the store and should be used sparingly.
+    /// The default implementation returns no keys, for stores that can't scan their buckets.
+    async fn keys_with_prefix(&self, _prefix: &str) -> Vec<String> {
+        vec![]
+    }
+    /// Removes every bucket from the store, called by the generated `clear_rule` method.
+    /// The default implementation removes each key from `AsyncStore::keys_with_prefix`, so stores that can't list their keys should override it.
+    async fn clear(&self) {
+        for key in self.keys_with_prefix("").await {
+            self.remove(&key).await;
+        }
+    }
+    /// The number of buckets in the store, counted by rate limiters with a memory budget before each hit.
+    /// The default implementation counts the keys from `AsyncStore::keys_with_prefix`, so it's O(n) and stores that can't list their keys report none.
+    async fn bucket_count(&self) -> usize {
+        self.keys_with_prefix("").await.len()
+    }
+    /// Evicts up to `count` buckets, those resetting soonest first, returning the number evicted.
+    /// Called by rate limiters over their memory budget on the store holding the most buckets.
+    /// The default implementation reads every bucket from `AsyncStore::keys_with_prefix` and removes those with the earliest resets.
+    async fn evict(&self, count: usize) -> usize {
+        let mut buckets = vec![];
+        for key in self.keys_with_prefix("").await {
+            if let Some((_, reset)) = self.read(&key).await {
+                buckets.push((reset, key));
+            }
+        }
+        buckets.sort_unstable();
+        buckets.truncate(count);
+        for (_, key) in &buckets {
+            self.remove(key).await;
+        }
+        buckets.len()
+    }
     /// Prunes the store of any expired values. Any bucket with a reset value less than the provided now value is considered expired.
     async fn prune(&self, now: u64);
+    /// Prunes the store like `AsyncStore::prune`, returning the keys of the buckets removed, called by the generated `prune_expired` method
+    /// so they can be acted on, e.g. to invalidate caches keyed by them.
+    /// The default implementation reads every bucket from `AsyncStore::keys_with_prefix` before pruning and returns those that had expired,
+    /// so stores that can't list their keys return none.
+    async fn prune_returning(&self, now: u64) -> Vec<String> {
+        let mut expired = vec![];
+        for key in self.keys_with_prefix("").await {
+            if self.read(&key).await.is_some_and(|(_, reset)| reset < now) {
+                expired.push(key);
+            }
+        }
+        self.prune(now).await;
+        expired
+    }
 }
 
 /// The implementor of this trait is expected to dereference into an Option<(u32, u64)> with the items
@@ -154,11 +591,13 @@ pub trait StoreLock:
 {
 }
 
-/// The default implementation of `StoreLock` for use with `DefaultStore`.
+/// The default implementation of `StoreLock` for use with `DefaultStore`, the bucket is unlocked when it's dropped.
 #[derive(Debug)]
 pub struct DefaultStoreLock {
     value: Option<(u32, u64)>,
-    _guard: LockGuard<String>,
+    slot: Arc<Slot>,
+    // the map and key of a bucket that was empty when it was locked, so its slot can be removed if it's still empty when it's released
+    owner: Option<(Arc<Slots>, String)>,
 }
 
 impl StoreLock for DefaultStoreLock {}
@@ -172,11 +611,240 @@ impl std::ops::Deref for DefaultStoreLock {
 }
 
 impl DefaultStoreLock {
-    /// Creates a new `DefaultStoreLock`
-    pub fn new(value: Option<(u32, u64)>, guard: LockGuard<String>) -> Self {
+    /// Creates a new `DefaultStoreLock` for a slot that has already been marked as locked.
+    pub fn new(value: Option<(u32, u64)>, slot: Arc<Slot>) -> Self {
         Self {
             value,
-            _guard: guard,
+            slot,
+            owner: None,
         }
     }
 }
+
+impl Drop for DefaultStoreLock {
+    fn drop(&mut self) {
+        let mut state = self.slot.state();
+        state.locked = false;
+        let empty = state.value.is_none();
+        drop(state);
+        self.slot.unlocked.notify_one();
+        // a bucket that was locked without being set, e.g. by a rejected `hit_atomic`, would otherwise stay in the map forever
+        if let Some((map, key)) = &self.owner {
+            if empty {
+                remove_empty(map, key, 1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry_heap_bounded() {
+        let store = DefaultStore::new();
+        for window in 0..1000 {
+            store.set("key", (1, window * 2), true);
+            store.prune(window * 2);
+        }
+        store.set("other", (1, 100), true);
+        let expiring = store.expiring.lock().unwrap();
+        assert_eq!(expiring.heap.len(), store.map.len());
+        assert_eq!(expiring.scheduled.len(), 2);
+    }
+
+    #[test]
+    fn empty_slots_removed() {
+        let store = DefaultStore::new();
+        for i in 0..100 {
+            drop(store.get(&format!("read{i}")));
+            assert!(!store.compare_and_set(&format!("cas{i}"), Some((1, 10)), (0, 10)));
+        }
+        assert_eq!(store.map.len(), 0);
+        // a waiter keeps the slot until it releases it
+        let lock = store.get("shared");
+        let waiter = std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| drop(store.get("shared")));
+            std::thread::sleep(Duration::from_millis(20));
+            drop(lock);
+            waiter.join()
+        });
+        assert!(waiter.is_ok());
+        assert_eq!(store.map.len(), 0);
+        store.set("set", (1, 10), true);
+        drop(store.get("set"));
+        assert_eq!(store.map.len(), 1);
+        store.prune(u64::MAX);
+        assert_eq!(store.map.len(), 0);
+    }
+
+    #[test]
+    fn bucket_count_live() {
+        let store = DefaultStore::new();
+        store.set("a", (1, 10), true);
+        let _locked = store.get("b");
+        let _locked_set = store.get("a");
+        assert_eq!(store.bucket_count(), 1);
+        store.remove("a");
+        assert_eq!(store.bucket_count(), 0);
+        store.set("c", (1, 10), true);
+        store.set("d", (1, 20), true);
+        assert_eq!(store.evict(1), 1);
+        assert_eq!(store.bucket_count(), 1);
+        store.clear();
+        assert_eq!(store.bucket_count(), 0);
+    }
+
+    #[test]
+    fn expiry_tie_order() {
+        let mut heap = BinaryHeap::new();
+        for key in ["c", "a", "d", "b"] {
+            heap.push(Expiry(10, key.to_string()));
+        }
+        heap.push(Expiry(5, "z".to_string()));
+        let order = std::iter::from_fn(|| heap.pop().map(|e| e.1)).collect::<Vec<_>>();
+        assert_eq!(order, ["z", "a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn clear_keeps_locked() {
+        let store = DefaultStore::new();
+        store.set("free", (1, 10), true);
+        let lock = store.get("locked");
+        store.set("locked", (1, 20), true);
+        store.clear();
+        assert_eq!(store.read("free"), None);
+        assert_eq!(store.read("locked"), None);
+        // the locked bucket is saved again by its holder and still pruned
+        store.set("locked", (0, 20), false);
+        drop(lock);
+        assert_eq!(store.bucket_count(), 1);
+        store.prune(22);
+        assert_eq!(store.bucket_count(), 0);
+        assert!(store.expiring.lock().unwrap().heap.is_empty());
+    }
+
+    #[test]
+    fn evict_earliest() {
+        let store = DefaultStore::new();
+        store.set("late", (1, 30), true);
+        store.set("early", (1, 10), true);
+        store.set("middle", (1, 20), true);
+        let lock = store.get("early");
+        assert_eq!(store.evict(1), 1);
+        // the locked bucket is kept for a later eviction
+        assert_eq!(store.read("middle"), None);
+        drop(lock);
+        assert_eq!(store.evict(5), 2);
+        assert_eq!(store.bucket_count(), 0);
+        assert!(store.expiring.lock().unwrap().scheduled.is_empty());
+    }
+
+    #[test]
+    fn prune_rescheduled() {
+        let store = DefaultStore::new();
+        store.set("key", (1, 10), true);
+        store.set("key", (1, 20), true);
+        store.prune(15);
+        assert!(store.map.contains_key("key"));
+        store.prune(25);
+        assert!(!store.map.contains_key("key"));
+        assert!(store.expiring.lock().unwrap().heap.is_empty());
+    }
+
+    #[test]
+    fn prune_returning() {
+        let store = DefaultStore::new();
+        store.set("a", (1, 10), true);
+        store.set("b", (1, 20), true);
+        assert!(store.prune_returning(5).is_empty());
+        assert_eq!(store.prune_returning(15), ["a"]);
+        assert_eq!(store.read("b"), Some((1, 20)));
+    }
+
+    #[test]
+    fn prune_stats() {
+        let store = DefaultStore::new();
+        store.set("a", (1, 10), true);
+        store.set("b", (1, 10), true);
+        store.set("c", (1, 20), true);
+        store.prune(15);
+        // prunes without expired entries aren't recorded
+        store.prune(16);
+        let stats = store.prune_stats();
+        assert_eq!((stats.prunes, stats.pruned, stats.last_pruned), (1, 2, 2));
+        assert_eq!((stats.buckets, stats.scheduled), (1, 1));
+        assert!(stats.total_duration >= stats.last_duration);
+    }
+
+    #[test]
+    fn concurrent_increments() {
+        let store = std::sync::Arc::new(DefaultStore::new());
+        let threads = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        for key in ["shared", "other"] {
+                            let lock = store.get(key);
+                            let (count, _) = (*lock).unwrap_or((0, 0));
+                            store.set(key, (count + 1, 10), true);
+                        }
+                        if i % 100 == 0 {
+                            store.prune(5);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(store.read("shared"), Some((8000, 10)));
+        assert_eq!(store.read("other"), Some((8000, 10)));
+        store.prune(12);
+        assert_eq!(store.read("shared"), None);
+        assert!(store.map.is_empty());
+    }
+
+    #[test]
+    fn compare_and_set() {
+        let store = std::sync::Arc::new(DefaultStore::new());
+        assert!(!store.compare_and_set("key", Some((1, 10)), (2, 10)));
+        assert!(store.compare_and_set("key", None, (1, 10)));
+        assert!(store.compare_and_set("key", Some((1, 10)), (2, 10)));
+        assert_eq!(store.read("key"), Some((2, 10)));
+        let threads = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        // retries until the increment isn't raced by another thread
+                        loop {
+                            let current = store.read("counter");
+                            let (count, _) = current.unwrap_or((0, 0));
+                            if store.compare_and_set("counter", current, (count + 1, 10)) {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(store.read("counter"), Some((8000, 10)));
+        store.prune(12);
+        assert!(store.map.is_empty());
+    }
+
+    #[test]
+    fn far_future_reset() {
+        let store = DefaultStore::new();
+        store.set("key", (1, u64::MAX), true);
+        store.prune(u64::MAX - 1);
+        assert!(store.map.contains_key("key"));
+    }
+}
@@ -0,0 +1,2 @@
+main = pub 2 requests every 100 seconds for { ip + route };
+daily = 10 requests every 1 day for { ip };
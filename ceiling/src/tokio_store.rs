@@ -0,0 +1,302 @@
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use tokio::sync::{Mutex, OwnedMutexGuard, RwLock};
+
+// a bucket's lock is only in the map while it's held or waited on, it's removed by the last `TokioStoreLock` releasing it.
+// The map is only locked briefly and never across an await, so it uses a synchronous mutex that can be locked on drop
+type Locks = std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>;
+
+use crate::store::{AsyncStore, Expiring, Expiry, PruneStats, StoreLock};
+
+/// An asynchronous store using `tokio`'s synchronization primitives, for async rate limiters that don't need an external store.
+/// Buckets are kept in a `tokio::sync::RwLock<HashMap>` and each bucket is locked with its own `tokio::sync::Mutex`,
+/// so a task waiting on a contended bucket yields to the runtime instead of blocking the thread.
+/// Expired buckets are pruned like `DefaultStore`, using a heap of expiry times.
+#[derive(Debug)]
+pub struct TokioStore {
+    map: RwLock<HashMap<String, (u32, u64)>>,
+    locks: Arc<Locks>,
+    expiring: Mutex<Expiring>,
+}
+
+impl TokioStore {
+    /// Returns the statistics of the store's pruning and its current size, like `DefaultStore::prune_stats`.
+    pub async fn prune_stats(&self) -> PruneStats {
+        let expiring = self.expiring.lock().await;
+        expiring.stats(self.map.read().await.len())
+    }
+}
+
+#[async_trait::async_trait]
+impl AsyncStore for TokioStore {
+    type Lock = TokioStoreLock;
+
+    fn new() -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            map: RwLock::new(HashMap::new()),
+            locks: Arc::new(Locks::default()),
+            expiring: Mutex::new(Expiring::default()),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Self::Lock {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        let guard = lock.lock_owned().await;
+        let value = self.map.read().await.get(key).copied();
+        TokioStoreLock {
+            value,
+            guard: Some(guard),
+            owner: Some((self.locks.clone(), key.to_string())),
+        }
+    }
+
+    async fn read(&self, key: &str) -> Option<(u32, u64)> {
+        self.map.read().await.get(key).copied()
+    }
+
+    async fn set(&self, key: &str, value: (u32, u64), reset_updated: bool) {
+        self.map.write().await.insert(key.to_string(), value);
+        if reset_updated {
+            let mut expiring = self.expiring.lock().await;
+            expiring.schedule(value.1.saturating_add(1), key);
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        self.map.write().await.remove(key);
+    }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        self.map
+            .read()
+            .await
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    async fn clear(&self) {
+        let mut expiring = self.expiring.lock().await;
+        self.map.write().await.clear();
+        // the expiries of buckets that are locked or being waited on are kept,
+        // so a task in progress that saves its bucket again still has it pruned
+        let locks = self.locks.lock().unwrap();
+        expiring.heap.retain(|expiry| locks.contains_key(&expiry.1));
+        expiring.scheduled.retain(|key| locks.contains_key(key));
+    }
+
+    async fn bucket_count(&self) -> usize {
+        self.map.read().await.len()
+    }
+
+    async fn evict(&self, count: usize) -> usize {
+        // the heap is popped in order of expiry, a bucket whose reset was pushed back may be evicted a little early
+        let mut expiring = self.expiring.lock().await;
+        let mut kept = vec![];
+        let mut evicted = 0;
+        while evicted < count {
+            let Some(expiry) = expiring.heap.pop() else {
+                break;
+            };
+            // buckets that are locked or being waited on are kept, the map of locks is held until the bucket
+            // is removed so no task can lock it in between
+            let mut map = self.map.write().await;
+            let locks = self.locks.lock().unwrap();
+            if locks.contains_key(&expiry.1) {
+                kept.push(expiry);
+                continue;
+            }
+            if map.remove(&expiry.1).is_some() {
+                evicted += 1;
+            }
+            drop(locks);
+            expiring.scheduled.remove(&expiry.1);
+        }
+        expiring.heap.extend(kept);
+        evicted
+    }
+
+    async fn prune(&self, now: u64) {
+        self.prune_returning(now).await;
+    }
+
+    async fn prune_returning(&self, now: u64) -> Vec<String> {
+        // the expired keys are collected first so the heap isn't locked while waiting on bucket locks
+        let mut expired = vec![];
+        let mut pruned = vec![];
+        {
+            let mut expiring = self.expiring.lock().await;
+            while let Some(peek) = expiring.heap.peek() {
+                if peek.0 >= now {
+                    break;
+                }
+                expired.push(expiring.heap.pop().unwrap().1);
+            }
+        }
+        if expired.is_empty() {
+            return pruned;
+        }
+        let start = Instant::now();
+        let last = expired.len() - 1;
+        for (i, key) in expired.into_iter().enumerate() {
+            let lock = self.get(&key).await;
+            let mut expiring = self.expiring.lock().await;
+            match *lock {
+                // the reset was pushed back since the entry was scheduled
+                Some(item) if item.1 >= now => {
+                    expiring.heap.push(Expiry(item.1.saturating_add(1), key));
+                },
+                Some(_) => {
+                    self.remove(&key).await;
+                    expiring.scheduled.remove(&key);
+                    pruned.push(key);
+                },
+                None => {
+                    expiring.scheduled.remove(&key);
+                },
+            }
+            // recorded while the heap is locked for the last key, so recording doesn't lock it again
+            if i == last {
+                expiring.record_prune(pruned.len(), start);
+            }
+        }
+        pruned
+    }
+}
+
+/// The implementation of `StoreLock` for use with `TokioStore`, the bucket is unlocked when it's dropped.
+#[derive(Debug)]
+pub struct TokioStoreLock {
+    value: Option<(u32, u64)>,
+    guard: Option<OwnedMutexGuard<()>>,
+    // the map of locks and the key, so the bucket's lock can be removed from it when it's released
+    owner: Option<(Arc<Locks>, String)>,
+}
+
+impl StoreLock for TokioStoreLock {}
+
+impl std::ops::Deref for TokioStoreLock {
+    type Target = Option<(u32, u64)>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl TokioStoreLock {
+    /// Creates a new `TokioStoreLock`
+    pub fn new(value: Option<(u32, u64)>, guard: OwnedMutexGuard<()>) -> Self {
+        Self {
+            value,
+            guard: Some(guard),
+            owner: None,
+        }
+    }
+}
+
+impl Drop for TokioStoreLock {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        // the lock is removed unless another task is waiting on it, the only other reference being the map's
+        if let Some((locks, key)) = &self.owner {
+            let mut locks = locks.lock().unwrap_or_else(|e| e.into_inner());
+            if locks.get(key).is_some_and(|l| Arc::strong_count(l) == 1) {
+                locks.remove(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_increments() {
+        let store = Arc::new(TokioStore::new());
+        let tasks = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for _ in 0..500 {
+                        let lock = store.get("key").await;
+                        let (count, _) = (*lock).unwrap_or((0, 0));
+                        tokio::task::yield_now().await;
+                        store.set("key", (count + 1, 10), true).await;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(store.read("key").await, Some((4000, 10)));
+        store.prune(12).await;
+        assert_eq!(store.read("key").await, None);
+        assert!(store.locks.lock().unwrap().is_empty());
+        assert!(store.expiring.lock().await.heap.is_empty());
+    }
+
+    #[tokio::test]
+    async fn locks_released() {
+        let store = TokioStore::new();
+        for i in 0..100 {
+            drop(store.get(&format!("key{i}")).await);
+        }
+        store.set("key0", (1, 10), true).await;
+        store.remove("key0").await;
+        assert!(store.locks.lock().unwrap().is_empty());
+        let lock = store.get("held").await;
+        store.clear().await;
+        assert_eq!(store.locks.lock().unwrap().len(), 1);
+        drop(lock);
+        assert!(store.locks.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn prune_stats() {
+        let store = TokioStore::new();
+        store.set("a", (1, 10), true).await;
+        store.set("b", (1, 20), true).await;
+        store.prune(15).await;
+        store.prune(16).await;
+        let stats = store.prune_stats().await;
+        assert_eq!((stats.prunes, stats.pruned, stats.last_pruned), (1, 1, 1));
+        assert_eq!((stats.buckets, stats.scheduled), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn evict_earliest() {
+        let store = TokioStore::new();
+        store.set("late", (1, 30), true).await;
+        store.set("early", (1, 10), true).await;
+        let lock = store.get("early").await;
+        assert_eq!(store.evict(1).await, 1);
+        assert_eq!(store.read("late").await, None);
+        drop(lock);
+        assert_eq!(store.evict(1).await, 1);
+        assert_eq!(store.bucket_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn several_locks() {
+        // a rule with several windows locks a bucket for each window in the same store
+        let store = TokioStore::new();
+        let first = store.get("key#0").await;
+        let second = store.get("key#1").await;
+        store.set("key#0", (1, 10), true).await;
+        store.set("key#1", (2, 10), true).await;
+        drop((first, second));
+        assert_eq!(store.keys_with_prefix("key").await.len(), 2);
+    }
+}
@@ -0,0 +1,82 @@
+use tonic::{
+    metadata::{AsciiMetadataKey, AsciiMetadataValue},
+    service::Interceptor,
+    GrpcMethod, Request, Status,
+};
+
+use crate::RateLimitError;
+
+/// A rate limiter that can be used by `RateLimitInterceptor`.
+/// The `rate_limiter!` macro implements this for rate limiters with a synchronous store and exactly two inputs,
+/// the first being the peer address and the second the method path.
+pub trait GrpcLimiter {
+    /// Hits the rate limiter for the peer address and method path, returning whether the request is limited,
+    /// the headers of the hit, and the error body if the request is limited.
+    #[must_use = "check whether the request is rate limited"]
+    fn hit_grpc(
+        &self,
+        peer: &str,
+        method: &str,
+    ) -> (bool, Vec<(&'static str, String)>, Option<RateLimitError>);
+}
+
+/// A `tonic` interceptor rate limiting requests by the peer's IP address and the method path (e.g. `/package.Service/Method`).
+/// Limited requests are rejected with `Status::resource_exhausted`, with the headers of the hit and a `retry-after` entry
+/// in the status' metadata.
+///
+/// The method path is taken from the `tonic::GrpcMethod` extension, which servers generated by `tonic-build` don't set.
+/// When intercepting a single service on the server use `RateLimitInterceptor::with_method` to set the path used instead.
+#[derive(Clone, Debug)]
+pub struct RateLimitInterceptor<L> {
+    limiter: L,
+    method: String,
+}
+
+impl<L: GrpcLimiter> RateLimitInterceptor<L> {
+    /// Creates a new interceptor using the rate limiter, clones of generated rate limiters share their buckets.
+    pub fn new(limiter: L) -> Self {
+        Self {
+            limiter,
+            method: String::new(),
+        }
+    }
+
+    /// Sets the method path used when the request doesn't have the `tonic::GrpcMethod` extension.
+    pub fn with_method(self, method: impl Into<String>) -> Self {
+        Self {
+            method: method.into(),
+            ..self
+        }
+    }
+}
+
+impl<L: GrpcLimiter> Interceptor for RateLimitInterceptor<L> {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let peer = request
+            .remote_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_default();
+        let method = match request.extensions().get::<GrpcMethod>() {
+            Some(method) => format!("/{}/{}", method.service(), method.method()),
+            None => self.method.clone(),
+        };
+        let (limited, headers, error) = self.limiter.hit_grpc(&peer, &method);
+        if !limited {
+            return Ok(request);
+        }
+        let mut status = Status::resource_exhausted("rate limited");
+        let metadata = status.metadata_mut();
+        for (header, value) in headers {
+            if let (Ok(key), Ok(value)) = (
+                header.to_lowercase().parse::<AsciiMetadataKey>(),
+                value.parse::<AsciiMetadataValue>(),
+            ) {
+                metadata.append(key, value);
+            }
+        }
+        if let Some(error) = error {
+            metadata.insert("retry-after", error.retry_after.into());
+        }
+        Err(status)
+    }
+}
@@ -0,0 +1,24 @@
+//! Feeds arbitrary token streams to the parsers of the macros, which should only ever return errors and never panic.
+//! Run with `cargo fuzz run parse` from the root of the repository.
+#![no_main]
+// the parsed fields are only read when generating code, which isn't fuzzed
+#![allow(dead_code)]
+
+use libfuzzer_sys::fuzz_target;
+
+// a proc-macro crate can't be linked into the target, so the parsers are included directly
+#[path = "../../ceiling-macros/src/generic_input.rs"]
+mod generic_input;
+#[path = "../../ceiling-macros/src/group_input.rs"]
+mod group_input;
+#[path = "../../ceiling-macros/src/rate_limiter_input.rs"]
+mod rate_limiter_input;
+
+fuzz_target!(|data: &str| {
+    let Ok(tokens) = data.parse::<proc_macro2::TokenStream>() else {
+        return;
+    };
+    let _ = syn::parse2::<rate_limiter_input::RateLimiterInput>(tokens.clone());
+    let _ = syn::parse2::<group_input::GroupInput>(tokens.clone());
+    let _ = syn::parse2::<group_input::GroupFnInput>(tokens);
+});